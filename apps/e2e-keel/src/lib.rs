@@ -26,6 +26,12 @@ fn handle_e2e(req: Request) -> Result<Response> {
         (m, "/test/users-add") if *m == Method::Get => users_add_via_query(req),
         (m, "/txn/commit") if *m == Method::Post || *m == Method::Get => txn_commit(),
         (m, "/txn/rollback") if *m == Method::Post || *m == Method::Get => txn_rollback(),
+        (m, p) if *m == Method::Get && p.starts_with("/bench/connections") => {
+            bench_connections(req)
+        }
+        (m, p) if *m == Method::Get && p.starts_with("/estimate/columnar") => {
+            estimate_columnar(req)
+        }
         _ => json(
             404,
             &ApiResponse::<()> {
@@ -82,13 +88,20 @@ fn create_user(req: Request) -> Result<Response> {
     let nu: NewUser = serde_json::from_slice(body)?;
     db.execute(
         "INSERT INTO users (name, email) VALUES (?, ?)",
-        &[Value::Text(nu.name), Value::Text(nu.email)],
+        &[Value::Text(nu.name.clone()), Value::Text(nu.email.clone())],
     )?;
+    // last_insert_rowid() is per-connection, so read it on the same handle
+    let qr = db.execute("SELECT last_insert_rowid()", &[])?;
+    let id: i64 = qr.rows.first().and_then(|r| r.get(0)).unwrap_or_default();
     json(
         200,
-        &ApiResponse::<()> {
+        &ApiResponse {
             ok: true,
-            data: None,
+            data: Some(UserOut {
+                id,
+                name: nu.name,
+                email: nu.email,
+            }),
             error: None,
         },
     )
@@ -228,3 +241,137 @@ fn txn_rollback() -> Result<Response> {
         },
     )
 }
+
+#[derive(Serialize)]
+struct ConnectionBench {
+    iterations: u32,
+    per_call_open_us: u128,
+    cached_us: u128,
+}
+
+/// Compare opening a connection per statement (the adapter's old behaviour)
+/// with reusing one instance-scoped connection, as sql-spin-sqlite now does.
+fn bench_connections(req: Request) -> Result<Response> {
+    let iterations: u32 = query_param(&req, "n").unwrap_or(1000);
+
+    let start = std::time::Instant::now();
+    for _ in 0..iterations {
+        let db = Connection::open_default()?;
+        db.execute("SELECT 1", &[])?;
+    }
+    let per_call_open_us = start.elapsed().as_micros();
+
+    let start = std::time::Instant::now();
+    let db = Connection::open_default()?;
+    for _ in 0..iterations {
+        db.execute("SELECT 1", &[])?;
+    }
+    let cached_us = start.elapsed().as_micros();
+
+    json(
+        200,
+        &ApiResponse {
+            ok: true,
+            data: Some(ConnectionBench {
+                iterations,
+                per_call_open_us,
+                cached_us,
+            }),
+            error: None,
+        },
+    )
+}
+
+fn query_param<T: std::str::FromStr>(req: &Request, name: &str) -> Option<T> {
+    let (_, q) = req.path_and_query()?.split_once('?')?;
+    q.split('&')
+        .find_map(|kv| kv.strip_prefix(name)?.strip_prefix('='))?
+        .parse()
+        .ok()
+}
+
+#[derive(Serialize)]
+struct ColumnarEstimate {
+    rows: u32,
+    cols: u32,
+    row_shape_bytes: usize,
+    columnar_bytes: usize,
+}
+
+/// Estimate the bytes the canonical ABI would copy for `query-result`, which
+/// repeats every column name per row, and for `columnar-result`.
+///
+/// This app talks to Spin's SQLite directly rather than importing `keel:sql`,
+/// so nothing here crosses the adapter's component boundary. The sizes are
+/// computed from the ABI layout of each shape over the same result; they are
+/// an estimate, not a measurement.
+fn estimate_columnar(req: Request) -> Result<Response> {
+    let rows: u32 = query_param(&req, "rows").unwrap_or(1000);
+    let cols: u32 = query_param(&req, "cols").unwrap_or(20);
+    let select = (0..cols)
+        .map(|c| format!("value * {c} AS metric_column_{c}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let sql = format!(
+        "WITH RECURSIVE seq(value) AS (SELECT 1 UNION ALL SELECT value + 1 FROM seq WHERE value < ?) \
+         SELECT {select} FROM seq"
+    );
+    let db = Connection::open_default()?;
+    let qr = db.execute(&sql, &[Value::Integer(i64::from(rows))])?;
+
+    let row_shape_bytes = qr
+        .rows
+        .iter()
+        .map(|r| {
+            LIST + qr
+                .columns
+                .iter()
+                .zip(&r.values)
+                .map(|(name, v)| NAMED_VALUE + name.len() + payload(v))
+                .sum::<usize>()
+        })
+        .sum::<usize>()
+        + LIST;
+
+    let columnar_bytes = qr
+        .rows
+        .iter()
+        .map(|r| LIST + r.values.iter().map(|v| VALUE + payload(v)).sum::<usize>())
+        .sum::<usize>()
+        + LIST
+        + qr.columns
+            .iter()
+            .map(|c| COLUMN_META + c.len())
+            .sum::<usize>()
+        + LIST;
+
+    json(
+        200,
+        &ApiResponse {
+            ok: true,
+            data: Some(ColumnarEstimate {
+                rows,
+                cols,
+                row_shape_bytes,
+                columnar_bytes,
+            }),
+            error: None,
+        },
+    )
+}
+
+// Canonical ABI sizes on wasm32: a list/string is a (ptr, len) pair, an
+// `sql-value` is an 8-byte-aligned discriminant plus 8-byte payload, and a
+// `column-meta` is a string, an option<enum> and a bool.
+const LIST: usize = 8;
+const VALUE: usize = 16;
+const NAMED_VALUE: usize = 24;
+const COLUMN_META: usize = 12;
+
+fn payload(v: &Value) -> usize {
+    match v {
+        Value::Text(s) => s.len(),
+        Value::Blob(b) => b.len(),
+        _ => 0,
+    }
+}
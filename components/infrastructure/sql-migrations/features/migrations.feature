@@ -0,0 +1,62 @@
+Feature: Schema migrations
+  As an application component
+  I want versioned schema changes applied through the sql interface
+  So that every instance sees the same schema without ad-hoc CREATE TABLE calls
+
+  # Scenarios tagged @native run under `cargo test` against a local SQLite
+  # standing in for the sql adapter (see src/bdd_tests.rs).
+
+  Background:
+    Given a sqlite database is available
+    And the database is empty
+    And the migration set:
+      | version | name         | up                                                          | down                |
+      | 1       | create_users | CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT)     | DROP TABLE users    |
+      | 2       | create_posts | CREATE TABLE posts (id INTEGER PRIMARY KEY, user_id INTEGER) | DROP TABLE posts    |
+      | 3       | seed_admin   | INSERT INTO users (email) VALUES ('admin@example.com')      |                     |
+
+  @native
+  Scenario: A fresh database has every migration pending
+    When I request the migration status
+    Then the current version should be none
+    And the pending versions should be 1, 2, 3
+    And table "_keel_migrations" should not exist
+
+  @native
+  Scenario: Migrating forward applies each version once
+    When I migrate to version 2
+    Then the current version should be 2
+    And the pending versions should be 3
+    And table "_keel_migrations" should have 2 rows
+    When I migrate to version 2
+    Then table "_keel_migrations" should have 2 rows
+
+  @native
+  Scenario: Migrating back runs down scripts newest first
+    Given I migrate to version 2
+    When I migrate to version 0
+    Then the current version should be none
+    And table "posts" should not exist
+
+  @native
+  Scenario: Migrations without a down script cannot be reverted
+    Given I migrate to version 3
+    When I migrate to version 1
+    Then the migration should fail with "irreversible" for version 3
+    And the current version should be 3
+
+  @native
+  Scenario: Editing an applied migration is detected
+    Given I migrate to version 1
+    And migration 1 is changed to "CREATE TABLE users (id INTEGER PRIMARY KEY)"
+    When I migrate to version 2
+    Then the migration should fail with "checksum-mismatch" for version 1
+    And table "posts" should not exist
+
+  @native
+  Scenario: A failing migration leaves no trace
+    Given migration 2 is changed to "CREATE TABLE posts (id INTEGER PRIMARY KEY); INSERT INTO missing VALUES (1)"
+    When I migrate to version 2
+    Then the migration should fail with "sql"
+    And the current version should be 1
+    And table "posts" should not exist
@@ -0,0 +1,193 @@
+//! Step definitions for `features/migrations.feature`.
+//!
+//! Scenarios tagged `@native` run under `cargo test`, with the imported `sql`
+//! interface served by the in-memory SQLite in [`crate::host::local`]. Each
+//! call loads a fresh migrator over the scenario's set, as a new component
+//! instance would.
+//!
+//! The steps live in the crate rather than under `tests/` because the
+//! component builds as a cdylib, which integration tests cannot link.
+
+use cucumber::gherkin::Step;
+use cucumber::{World as _, given, then, when};
+
+use crate::bindings::keel::infrastructure::sql;
+use crate::host::{self, local};
+use crate::wit_migrations::{self as wit, GuestMigrator as _};
+use crate::{Migrator, column};
+
+#[derive(Debug, Default, cucumber::World)]
+struct MigrationWorld {
+    set: Vec<wit::Migration>,
+    status: Option<wit::MigrationStatus>,
+    error: Option<wit::MigrationError>,
+}
+
+impl MigrationWorld {
+    fn migrator(&self) -> Migrator {
+        Migrator::open(None, self.set.clone()).expect("load the migration set")
+    }
+
+    fn record(&mut self, result: Result<wit::MigrationStatus, wit::MigrationError>) {
+        match result {
+            Ok(status) => {
+                self.status = Some(status);
+                self.error = None;
+            }
+            Err(e) => self.error = Some(e),
+        }
+    }
+
+    /// The status as the database records it now.
+    fn read_status(&mut self) -> &wit::MigrationStatus {
+        let status = self.migrator().status().expect("read the status");
+        self.status.insert(status)
+    }
+}
+
+fn count_rows(table: &str) -> Result<i64, sql::SqlError> {
+    let result = host::query(&format!("SELECT COUNT(*) AS n FROM {table}"), &[])?;
+    match column(&result.rows[0], "n") {
+        Some(sql::SqlValue::Int64(n)) => Ok(*n),
+        other => panic!("unexpected count {other:?}"),
+    }
+}
+
+fn kind(e: &wit::MigrationError) -> &'static str {
+    match e {
+        wit::MigrationError::Sql(_) => "sql",
+        wit::MigrationError::InvalidSet(_) => "invalid-set",
+        wit::MigrationError::ChecksumMismatch(_) => "checksum-mismatch",
+        wit::MigrationError::UnknownVersion(_) => "unknown-version",
+        wit::MigrationError::Irreversible(_) => "irreversible",
+    }
+}
+
+#[given("a sqlite database is available")]
+fn database_available(_world: &mut MigrationWorld) {
+    local::reset();
+}
+
+#[given("the database is empty")]
+fn database_empty(_world: &mut MigrationWorld) {
+    let tables = host::query(
+        "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%'",
+        &[],
+    )
+    .expect("list tables");
+    for row in &tables.rows {
+        let Some(sql::SqlValue::Text(name)) = column(row, "name") else {
+            continue;
+        };
+        host::execute(&format!("DROP TABLE \"{name}\""), &[]).expect("drop table");
+    }
+}
+
+/// A table of `version | name | up | down`; an empty `down` means none.
+#[given("the migration set:")]
+fn migration_set(world: &mut MigrationWorld, step: &Step) {
+    let table = step.table.as_ref().expect("a migration table");
+    world.set = table
+        .rows
+        .iter()
+        .skip(1)
+        .map(|row| wit::Migration {
+            version: row[0].parse().expect("version"),
+            name: row[1].clone(),
+            up: row[2].clone(),
+            down: (!row[3].is_empty()).then(|| row[3].clone()),
+        })
+        .collect();
+}
+
+#[given(expr = "migration {int} is changed to {string}")]
+fn change_migration(world: &mut MigrationWorld, version: u64, up: String) {
+    let m = world
+        .set
+        .iter_mut()
+        .find(|m| m.version == version)
+        .expect("no such migration");
+    m.up = up;
+}
+
+#[given(expr = "I migrate to version {int}")]
+fn migrated(world: &mut MigrationWorld, version: u64) {
+    let status = world.migrator().migrate_to(version);
+    assert!(status.is_ok(), "{status:?}");
+    world.record(status);
+}
+
+#[when(expr = "I migrate to version {int}")]
+fn migrate(world: &mut MigrationWorld, version: u64) {
+    let status = world.migrator().migrate_to(version);
+    world.record(status);
+}
+
+#[when("I request the migration status")]
+fn request_status(world: &mut MigrationWorld) {
+    let status = world.migrator().status();
+    world.record(status);
+}
+
+#[then("the current version should be none")]
+fn no_current_version(world: &mut MigrationWorld) {
+    let status = world.read_status();
+    assert_eq!(status.current, None, "{status:?}");
+    assert!(status.applied.is_empty());
+}
+
+#[then(expr = "the current version should be {int}")]
+fn current_version(world: &mut MigrationWorld, version: u64) {
+    let status = world.read_status();
+    assert_eq!(status.current, Some(version), "{status:?}");
+}
+
+#[then(regex = r"^the pending versions should be (.*)$")]
+fn pending_versions(world: &mut MigrationWorld, versions: String) {
+    let expected: Vec<u64> = versions
+        .split(", ")
+        .map(|v| v.parse().expect("version"))
+        .collect();
+    assert_eq!(world.read_status().pending, expected);
+}
+
+#[then(expr = "table {string} should not exist")]
+fn table_missing(_world: &mut MigrationWorld, table: String) {
+    assert!(count_rows(&table).is_err(), "table {table:?} exists");
+}
+
+#[then(expr = "table {string} should have {int} rows")]
+fn table_rows(_world: &mut MigrationWorld, table: String, rows: i64) {
+    assert_eq!(count_rows(&table).expect("count rows"), rows);
+}
+
+#[then(expr = "the migration should fail with {string}")]
+fn failed(world: &mut MigrationWorld, expected: String) {
+    let e = world.error.as_ref().expect("the migration succeeded");
+    assert_eq!(kind(e), expected, "{e:?}");
+}
+
+#[then(expr = "the migration should fail with {string} for version {int}")]
+fn failed_for_version(world: &mut MigrationWorld, expected: String, version: u64) {
+    failed(world, expected);
+    let failed_version = match world.error.as_ref() {
+        Some(wit::MigrationError::ChecksumMismatch(m)) => m.version,
+        Some(wit::MigrationError::UnknownVersion(v) | wit::MigrationError::Irreversible(v)) => *v,
+        other => panic!("{other:?} names no version"),
+    };
+    assert_eq!(failed_version, version);
+}
+
+#[test]
+fn migrations() {
+    futures::executor::block_on(
+        MigrationWorld::cucumber()
+            .max_concurrent_scenarios(1)
+            .fail_on_skipped()
+            .with_default_cli()
+            .filter_run_and_exit(
+                concat!(env!("CARGO_MANIFEST_DIR"), "/features/migrations.feature"),
+                |_, _, scenario| scenario.tags.iter().any(|t| t == "native"),
+            ),
+    );
+}
@@ -0,0 +1,249 @@
+//! The `sql` interface the component imports.
+//!
+//! In a composed component the calls go to the `sql` adapter. Unit tests run
+//! natively, where nothing is imported, so they get [`local`] instead: the
+//! functions and resources the migrator uses, over an in-memory SQLite per
+//! test thread, sharing one connection per label as the adapter does.
+
+#[cfg(not(test))]
+pub(crate) use crate::bindings::keel::infrastructure::sql::{
+    Connection, Transaction, begin_transaction, execute, query,
+};
+
+#[cfg(test)]
+pub(crate) use local::{Connection, Transaction, begin_transaction, execute, query};
+
+#[cfg(test)]
+pub(crate) mod local {
+    use std::cell::{Cell, RefCell};
+    use std::collections::BTreeMap;
+    use std::rc::Rc;
+
+    use rusqlite::fallible_iterator::FallibleIterator as _;
+    use rusqlite::types::{Value, ValueRef};
+
+    use crate::bindings::keel::infrastructure::sql;
+
+    const DEFAULT_DATABASE: &str = "default";
+
+    thread_local! {
+        static DATABASES: RefCell<BTreeMap<String, Rc<rusqlite::Connection>>> =
+            RefCell::default();
+    }
+
+    /// Drop every database this thread opened, as a fresh host would start.
+    pub(crate) fn reset() {
+        DATABASES.take();
+    }
+
+    fn shared(label: &str) -> Result<Rc<rusqlite::Connection>, sql::SqlError> {
+        DATABASES.with(|databases| {
+            if let Some(conn) = databases.borrow().get(label) {
+                return Ok(conn.clone());
+            }
+            let conn = rusqlite::Connection::open_in_memory()
+                .map(Rc::new)
+                .map_err(|e| sql::SqlError::ConnectionFailed(info(e, None)))?;
+            databases
+                .borrow_mut()
+                .insert(label.to_string(), conn.clone());
+            Ok(conn)
+        })
+    }
+
+    pub(crate) fn query(
+        sql: &str,
+        params: &[sql::SqlValue],
+    ) -> Result<sql::QueryResult, sql::SqlError> {
+        let conn = shared(DEFAULT_DATABASE)?;
+        run_query(&conn, sql, params)
+    }
+
+    pub(crate) fn execute(sql: &str, params: &[sql::SqlValue]) -> Result<u64, sql::SqlError> {
+        let conn = shared(DEFAULT_DATABASE)?;
+        run_execute(&conn, sql, params)
+    }
+
+    pub(crate) fn begin_transaction(
+        options: Option<sql::TransactionOptions>,
+    ) -> Result<Transaction, sql::SqlError> {
+        Transaction::begin(shared(DEFAULT_DATABASE)?, options)
+    }
+
+    pub(crate) struct Connection(Rc<rusqlite::Connection>);
+
+    impl Connection {
+        pub(crate) fn open(name: &str) -> Result<Self, sql::SqlError> {
+            shared(name).map(Self)
+        }
+
+        pub(crate) fn query(
+            &self,
+            sql: &str,
+            params: &[sql::SqlValue],
+        ) -> Result<sql::QueryResult, sql::SqlError> {
+            run_query(&self.0, sql, params)
+        }
+
+        pub(crate) fn execute(
+            &self,
+            sql: &str,
+            params: &[sql::SqlValue],
+        ) -> Result<u64, sql::SqlError> {
+            run_execute(&self.0, sql, params)
+        }
+
+        pub(crate) fn begin_transaction(
+            &self,
+            options: Option<sql::TransactionOptions>,
+        ) -> Result<Transaction, sql::SqlError> {
+            Transaction::begin(self.0.clone(), options)
+        }
+    }
+
+    /// Rolls back when dropped unfinished, like the adapter's transaction.
+    pub(crate) struct Transaction {
+        conn: Rc<rusqlite::Connection>,
+        finished: Cell<bool>,
+    }
+
+    impl Transaction {
+        fn begin(
+            conn: Rc<rusqlite::Connection>,
+            options: Option<sql::TransactionOptions>,
+        ) -> Result<Self, sql::SqlError> {
+            let begin = match options.map(|o| o.mode) {
+                None | Some(sql::TransactionMode::Deferred) => "BEGIN DEFERRED",
+                Some(sql::TransactionMode::Immediate) => "BEGIN IMMEDIATE",
+                Some(sql::TransactionMode::Exclusive) => "BEGIN EXCLUSIVE",
+            };
+            conn.execute_batch(begin)
+                .map_err(|e| sql::SqlError::TransactionFailed(info(e, Some(begin))))?;
+            Ok(Self {
+                conn,
+                finished: Cell::new(false),
+            })
+        }
+
+        pub(crate) fn query(
+            &self,
+            sql: &str,
+            params: &[sql::SqlValue],
+        ) -> Result<sql::QueryResult, sql::SqlError> {
+            run_query(&self.conn, sql, params)
+        }
+
+        pub(crate) fn execute(
+            &self,
+            sql: &str,
+            params: &[sql::SqlValue],
+        ) -> Result<u64, sql::SqlError> {
+            run_execute(&self.conn, sql, params)
+        }
+
+        /// Rows changed by each statement of `script`.
+        pub(crate) fn execute_script(&self, script: &str) -> Result<Vec<u64>, sql::SqlError> {
+            let failed = |e| sql::SqlError::QueryFailed(info(e, Some(script)));
+            let mut batch = rusqlite::Batch::new(&self.conn, script);
+            let mut counts = Vec::new();
+            while let Some(mut statement) = batch.next().map_err(failed)? {
+                counts.push(statement.raw_execute().map_err(failed)? as u64);
+            }
+            Ok(counts)
+        }
+
+        pub(crate) fn commit(&self) -> Result<(), sql::SqlError> {
+            self.finished.set(true);
+            self.conn
+                .execute_batch("COMMIT")
+                .map_err(|e| sql::SqlError::TransactionFailed(info(e, Some("COMMIT"))))
+        }
+    }
+
+    impl Drop for Transaction {
+        fn drop(&mut self) {
+            if !self.finished.get() {
+                let _ = self.conn.execute_batch("ROLLBACK");
+            }
+        }
+    }
+
+    fn run_query(
+        conn: &rusqlite::Connection,
+        sql: &str,
+        params: &[sql::SqlValue],
+    ) -> Result<sql::QueryResult, sql::SqlError> {
+        let failed = |e| sql::SqlError::QueryFailed(info(e, Some(sql)));
+        let mut statement = conn.prepare(sql).map_err(failed)?;
+        let names: Vec<String> = statement
+            .column_names()
+            .into_iter()
+            .map(str::to_string)
+            .collect();
+        let mut rows = statement
+            .query(rusqlite::params_from_iter(params.iter().map(to_sql)))
+            .map_err(failed)?;
+        let mut result = Vec::new();
+        while let Some(row) = rows.next().map_err(failed)? {
+            let columns = names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| Ok((name.clone(), from_sql(row.get_ref(i)?))))
+                .collect::<Result<_, rusqlite::Error>>()
+                .map_err(failed)?;
+            result.push(sql::SqlRow { columns });
+        }
+        Ok(sql::QueryResult {
+            rows: result,
+            rows_affected: 0,
+        })
+    }
+
+    fn run_execute(
+        conn: &rusqlite::Connection,
+        sql: &str,
+        params: &[sql::SqlValue],
+    ) -> Result<u64, sql::SqlError> {
+        conn.execute(sql, rusqlite::params_from_iter(params.iter().map(to_sql)))
+            .map(|n| n as u64)
+            .map_err(|e| sql::SqlError::QueryFailed(info(e, Some(sql))))
+    }
+
+    fn info(e: rusqlite::Error, statement: Option<&str>) -> sql::ErrorInfo {
+        let (code, extended_code) = match &e {
+            rusqlite::Error::SqliteFailure(f, _) => (f.extended_code & 0xff, f.extended_code),
+            _ => (1, 1),
+        };
+        sql::ErrorInfo {
+            code,
+            extended_code,
+            message: e.to_string(),
+            statement: statement.map(str::to_string),
+            parameter_index: None,
+        }
+    }
+
+    fn to_sql(value: &sql::SqlValue) -> Value {
+        match value {
+            sql::SqlValue::Null => Value::Null,
+            sql::SqlValue::Boolean(b) => Value::Integer(i64::from(*b)),
+            sql::SqlValue::Int32(i) => Value::Integer(i64::from(*i)),
+            sql::SqlValue::Int64(i) => Value::Integer(*i),
+            sql::SqlValue::Float32(f) => Value::Real(f64::from(*f)),
+            sql::SqlValue::Float64(f) => Value::Real(*f),
+            sql::SqlValue::Text(s) => Value::Text(s.clone()),
+            sql::SqlValue::Bytes(b) => Value::Blob(b.clone()),
+            other => panic!("the local host does not store {other:?}"),
+        }
+    }
+
+    fn from_sql(value: ValueRef<'_>) -> sql::SqlValue {
+        match value {
+            ValueRef::Null => sql::SqlValue::Null,
+            ValueRef::Integer(i) => sql::SqlValue::Int64(i),
+            ValueRef::Real(r) => sql::SqlValue::Float64(r),
+            ValueRef::Text(t) => sql::SqlValue::Text(String::from_utf8_lossy(t).into_owned()),
+            ValueRef::Blob(b) => sql::SqlValue::Bytes(b.to_vec()),
+        }
+    }
+}
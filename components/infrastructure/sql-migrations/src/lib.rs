@@ -0,0 +1,256 @@
+#![cfg_attr(not(target_arch = "wasm32"), deny(unsafe_code))]
+#![cfg_attr(target_arch = "wasm32", allow(unsafe_code))]
+#![cfg_attr(not(target_arch = "wasm32"), allow(dead_code, unused_imports))]
+// `migration-error` is fixed by the WIT interface and returned by value across it.
+#![allow(clippy::result_large_err)]
+//! Versioned schema migrations over the `sql` WIT interface.
+//!
+//! Each step runs its script and its `_keel_migrations` bookkeeping in one
+//! IMMEDIATE transaction through the imported `sql` adapter, so a failed
+//! migration leaves neither schema changes nor a record behind.
+
+#[macro_use]
+mod bindings {
+    #![allow(unsafe_code)]
+    #![allow(unsafe_op_in_unsafe_fn)]
+    #![allow(unused_attributes)]
+    #![allow(clippy::empty_line_after_outer_attr)]
+    wit_bindgen::generate!({
+        world: "migrations-runner",
+        path: "wit",
+    });
+}
+
+#[cfg(test)]
+mod bdd_tests;
+mod host;
+mod plan;
+
+use crate::bindings::exports::keel::infrastructure::migrations as wit_migrations;
+use crate::bindings::keel::infrastructure::sql;
+use crate::plan::Step;
+
+const BOOKKEEPING: &str = "CREATE TABLE IF NOT EXISTS _keel_migrations (
+    version INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    checksum TEXT NOT NULL,
+    applied_at INTEGER NOT NULL
+)";
+
+const BOOKKEEPING_EXISTS: &str =
+    "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = '_keel_migrations'";
+
+struct Component;
+
+impl wit_migrations::Guest for Component {
+    type Migrator = Migrator;
+}
+
+struct Migrator {
+    database: Database,
+    set: Vec<wit_migrations::Migration>,
+}
+
+impl wit_migrations::GuestMigrator for Migrator {
+    fn load(
+        database: Option<String>,
+        migrations: Vec<wit_migrations::Migration>,
+    ) -> Result<wit_migrations::Migrator, wit_migrations::MigrationError> {
+        Migrator::open(database, migrations).map(wit_migrations::Migrator::new)
+    }
+
+    fn migrate_to(
+        &self,
+        version: u64,
+    ) -> Result<wit_migrations::MigrationStatus, wit_migrations::MigrationError> {
+        self.database.execute(BOOKKEEPING, &[])?;
+        let applied = self.applied()?;
+        for step in plan::plan(&self.set, &applied, version)? {
+            self.run(step)?;
+        }
+        self.status()
+    }
+
+    fn status(&self) -> Result<wit_migrations::MigrationStatus, wit_migrations::MigrationError> {
+        Ok(plan::status(&self.set, self.applied()?))
+    }
+}
+
+impl Migrator {
+    fn open(
+        database: Option<String>,
+        migrations: Vec<wit_migrations::Migration>,
+    ) -> Result<Self, wit_migrations::MigrationError> {
+        let set = plan::validate(migrations)?;
+        let database = match database {
+            Some(name) => Database::Named(host::Connection::open(&name)?),
+            None => Database::Default,
+        };
+        Ok(Migrator { database, set })
+    }
+
+    /// The recorded migrations. A database without the bookkeeping table has
+    /// none; it is only created by `migrate-to`, so `status` never writes.
+    fn applied(&self) -> Result<Vec<wit_migrations::AppliedMigration>, sql::SqlError> {
+        applied_with(|q| self.database.query(q, &[]))
+    }
+
+    /// Run one step in its own transaction. The bookkeeping row is re-checked
+    /// under the write lock so concurrent runners do not apply a step twice.
+    fn run(&self, step: Step) -> Result<(), sql::SqlError> {
+        let tx = self.database.begin(Some(sql::TransactionOptions {
+            mode: sql::TransactionMode::Immediate,
+            read_only: false,
+            busy_timeout_ms: None,
+        }))?;
+        let (Step::Up(i) | Step::Down(i)) = step;
+        let m = &self.set[i];
+        let version = sql::SqlValue::Int64(m.version as i64);
+        let recorded = !tx
+            .query(
+                "SELECT 1 FROM _keel_migrations WHERE version = ?",
+                std::slice::from_ref(&version),
+            )?
+            .rows
+            .is_empty();
+        match step {
+            Step::Up(_) if !recorded => {
+                tracing::info!(version = m.version, name = %m.name, "applying migration");
+                tx.execute_script(&m.up)?;
+                tx.execute(
+                    "INSERT INTO _keel_migrations (version, name, checksum, applied_at) \
+                     VALUES (?, ?, ?, CAST(strftime('%s', 'now') AS INTEGER))",
+                    &[
+                        version,
+                        sql::SqlValue::Text(m.name.clone()),
+                        sql::SqlValue::Text(plan::checksum(&m.up)),
+                    ],
+                )?;
+            }
+            Step::Down(_) if recorded => {
+                tracing::info!(version = m.version, name = %m.name, "reverting migration");
+                tx.execute_script(m.down.as_deref().unwrap_or_default())?;
+                tx.execute("DELETE FROM _keel_migrations WHERE version = ?", &[version])?;
+            }
+            _ => {}
+        }
+        tx.commit()
+    }
+}
+
+impl From<sql::SqlError> for wit_migrations::MigrationError {
+    fn from(e: sql::SqlError) -> Self {
+        wit_migrations::MigrationError::Sql(e)
+    }
+}
+
+/// The default database through the top-level `sql` functions, or a named
+/// one through a `connection`.
+enum Database {
+    Default,
+    Named(host::Connection),
+}
+
+impl Database {
+    fn query(&self, q: &str, params: &[sql::SqlValue]) -> Result<sql::QueryResult, sql::SqlError> {
+        match self {
+            Database::Default => host::query(q, params),
+            Database::Named(conn) => conn.query(q, params),
+        }
+    }
+
+    fn execute(&self, q: &str, params: &[sql::SqlValue]) -> Result<u64, sql::SqlError> {
+        match self {
+            Database::Default => host::execute(q, params),
+            Database::Named(conn) => conn.execute(q, params),
+        }
+    }
+
+    fn begin(
+        &self,
+        options: Option<sql::TransactionOptions>,
+    ) -> Result<host::Transaction, sql::SqlError> {
+        match self {
+            Database::Default => host::begin_transaction(options),
+            Database::Named(conn) => conn.begin_transaction(options),
+        }
+    }
+}
+
+fn applied_with(
+    query: impl Fn(&str) -> Result<sql::QueryResult, sql::SqlError>,
+) -> Result<Vec<wit_migrations::AppliedMigration>, sql::SqlError> {
+    if query(BOOKKEEPING_EXISTS)?.rows.is_empty() {
+        return Ok(Vec::new());
+    }
+    let result =
+        query("SELECT version, name, checksum, applied_at FROM _keel_migrations ORDER BY version")?;
+    Ok(result.rows.iter().map(applied_from_row).collect())
+}
+
+fn applied_from_row(row: &sql::SqlRow) -> wit_migrations::AppliedMigration {
+    let int = |name: &str| match column(row, name) {
+        Some(sql::SqlValue::Int64(v)) => *v,
+        _ => 0,
+    };
+    let text = |name: &str| match column(row, name) {
+        Some(sql::SqlValue::Text(v)) => v.clone(),
+        _ => String::new(),
+    };
+    wit_migrations::AppliedMigration {
+        version: int("version") as u64,
+        name: text("name"),
+        checksum: text("checksum"),
+        applied_at: int("applied_at"),
+    }
+}
+
+fn column<'a>(row: &'a sql::SqlRow, name: &str) -> Option<&'a sql::SqlValue> {
+    row.columns.iter().find(|(c, _)| c == name).map(|(_, v)| v)
+}
+
+// Export the component entry points
+#[cfg(target_arch = "wasm32")]
+bindings::export!(Component with_types_in bindings);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_missing_bookkeeping_table_means_nothing_applied() {
+        let ran = std::cell::RefCell::new(Vec::new());
+        let applied = applied_with(|q| {
+            ran.borrow_mut().push(q.to_string());
+            Ok(sql::QueryResult {
+                rows: Vec::new(),
+                rows_affected: 0,
+            })
+        })
+        .unwrap();
+        assert!(applied.is_empty());
+        assert_eq!(ran.into_inner(), [BOOKKEEPING_EXISTS]);
+    }
+
+    #[test]
+    fn applied_rows_are_read_by_column_name() {
+        let row = sql::SqlRow {
+            columns: vec![
+                ("checksum".into(), sql::SqlValue::Text("abc".into())),
+                ("version".into(), sql::SqlValue::Int64(4)),
+                ("applied_at".into(), sql::SqlValue::Int64(1_700_000_000)),
+                ("name".into(), sql::SqlValue::Text("add_users".into())),
+            ],
+        };
+        let a = applied_from_row(&row);
+        assert_eq!(
+            (
+                a.version,
+                a.name.as_str(),
+                a.checksum.as_str(),
+                a.applied_at
+            ),
+            (4, "add_users", "abc", 1_700_000_000)
+        );
+    }
+}
@@ -0,0 +1,233 @@
+//! Pure migration planning: validating the set, verifying what the database
+//! says was applied, and ordering the steps to reach a target version.
+
+use crate::wit_migrations as wit;
+
+/// A step in a migration run, indexing the validated set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Step {
+    Up(usize),
+    Down(usize),
+}
+
+/// Sort the set by version, rejecting duplicates and unstorable versions.
+pub(crate) fn validate(
+    mut set: Vec<wit::Migration>,
+) -> Result<Vec<wit::Migration>, wit::MigrationError> {
+    set.sort_by_key(|m| m.version);
+    if let Some(m) = set
+        .iter()
+        .find(|m| m.version == 0 || m.version > i64::MAX as u64)
+    {
+        return Err(wit::MigrationError::InvalidSet(format!(
+            "migration {:?} has unusable version {}",
+            m.name, m.version
+        )));
+    }
+    if let Some(pair) = set.windows(2).find(|w| w[0].version == w[1].version) {
+        return Err(wit::MigrationError::InvalidSet(format!(
+            "version {} is used by both {:?} and {:?}",
+            pair[0].version, pair[0].name, pair[1].name
+        )));
+    }
+    Ok(set)
+}
+
+/// FNV-1a over the `up` script. This detects edits to applied migrations; it
+/// is not meant to resist deliberate collisions.
+pub(crate) fn checksum(up: &str) -> String {
+    let hash = up.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |h, b| {
+        (h ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("{hash:016x}")
+}
+
+/// Check every applied migration is still in `set` with an unchanged script.
+pub(crate) fn verify(
+    set: &[wit::Migration],
+    applied: &[wit::AppliedMigration],
+) -> Result<(), wit::MigrationError> {
+    for a in applied {
+        let m = find(set, a.version).ok_or(wit::MigrationError::UnknownVersion(a.version))?;
+        let actual = checksum(&set[m].up);
+        if actual != a.checksum {
+            return Err(wit::MigrationError::ChecksumMismatch(
+                wit::ChecksumMismatch {
+                    version: a.version,
+                    recorded: a.checksum.clone(),
+                    actual,
+                },
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Steps taking the database to `target`: reverts above it, newest first,
+/// then any unapplied migrations at or below it, oldest first.
+pub(crate) fn plan(
+    set: &[wit::Migration],
+    applied: &[wit::AppliedMigration],
+    target: u64,
+) -> Result<Vec<Step>, wit::MigrationError> {
+    verify(set, applied)?;
+    if target != 0 && find(set, target).is_none() {
+        return Err(wit::MigrationError::UnknownVersion(target));
+    }
+    let is_applied = |version| applied.iter().any(|a| a.version == version);
+
+    let mut steps = Vec::new();
+    for (i, m) in set.iter().enumerate().rev() {
+        if m.version > target && is_applied(m.version) {
+            if m.down.is_none() {
+                return Err(wit::MigrationError::Irreversible(m.version));
+            }
+            steps.push(Step::Down(i));
+        }
+    }
+    for (i, m) in set.iter().enumerate() {
+        if m.version <= target && !is_applied(m.version) {
+            steps.push(Step::Up(i));
+        }
+    }
+    Ok(steps)
+}
+
+pub(crate) fn status(
+    set: &[wit::Migration],
+    applied: Vec<wit::AppliedMigration>,
+) -> wit::MigrationStatus {
+    let pending = set
+        .iter()
+        .map(|m| m.version)
+        .filter(|v| !applied.iter().any(|a| a.version == *v))
+        .collect();
+    wit::MigrationStatus {
+        current: applied.iter().map(|a| a.version).max(),
+        applied,
+        pending,
+    }
+}
+
+fn find(set: &[wit::Migration], version: u64) -> Option<usize> {
+    set.binary_search_by_key(&version, |m| m.version).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn migration(version: u64, down: bool) -> wit::Migration {
+        wit::Migration {
+            version,
+            name: format!("m{version}"),
+            up: format!("CREATE TABLE t{version} (id INTEGER)"),
+            down: down.then(|| format!("DROP TABLE t{version}")),
+        }
+    }
+
+    fn applied(m: &wit::Migration) -> wit::AppliedMigration {
+        wit::AppliedMigration {
+            version: m.version,
+            name: m.name.clone(),
+            checksum: checksum(&m.up),
+            applied_at: 0,
+        }
+    }
+
+    fn set() -> Vec<wit::Migration> {
+        validate(vec![
+            migration(3, true),
+            migration(1, true),
+            migration(2, false),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn validate_sorts_and_rejects_bad_sets() {
+        let versions: Vec<_> = set().iter().map(|m| m.version).collect();
+        assert_eq!(versions, vec![1, 2, 3]);
+        assert!(matches!(
+            validate(vec![migration(1, true), migration(1, false)]),
+            Err(wit::MigrationError::InvalidSet(_))
+        ));
+        assert!(matches!(
+            validate(vec![migration(0, true)]),
+            Err(wit::MigrationError::InvalidSet(_))
+        ));
+        assert!(matches!(
+            validate(vec![migration(u64::MAX, true)]),
+            Err(wit::MigrationError::InvalidSet(_))
+        ));
+    }
+
+    #[test]
+    fn checksum_is_stable_and_sensitive() {
+        assert_eq!(checksum(""), "cbf29ce484222325");
+        assert_eq!(checksum("a"), "af63dc4c8601ec8c");
+        assert_ne!(
+            checksum("CREATE TABLE t (x)"),
+            checksum("CREATE TABLE t (y)")
+        );
+    }
+
+    #[test]
+    fn plan_applies_pending_up_to_target() {
+        let set = set();
+        assert_eq!(plan(&set, &[], 2).unwrap(), vec![Step::Up(0), Step::Up(1)]);
+        assert_eq!(
+            plan(&set, &[applied(&set[0])], 3).unwrap(),
+            vec![Step::Up(1), Step::Up(2)]
+        );
+        assert_eq!(plan(&set, &[applied(&set[0])], 1).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn plan_reverts_newest_first() {
+        let set = set();
+        let all: Vec<_> = set.iter().map(applied).collect();
+        assert_eq!(plan(&set, &all, 2).unwrap(), vec![Step::Down(2)]);
+        assert!(matches!(
+            plan(&set, &all, 1),
+            Err(wit::MigrationError::Irreversible(2))
+        ));
+    }
+
+    #[test]
+    fn plan_fills_gaps_below_the_current_version() {
+        let set = set();
+        let partial = [applied(&set[0]), applied(&set[2])];
+        assert_eq!(plan(&set, &partial, 3).unwrap(), vec![Step::Up(1)]);
+    }
+
+    #[test]
+    fn plan_rejects_unknown_versions_and_edited_scripts() {
+        let set = set();
+        assert!(matches!(
+            plan(&set, &[], 9),
+            Err(wit::MigrationError::UnknownVersion(9))
+        ));
+        let mut stray = applied(&set[0]);
+        stray.version = 7;
+        assert!(matches!(
+            plan(&set, &[stray], 3),
+            Err(wit::MigrationError::UnknownVersion(7))
+        ));
+        let mut edited = applied(&set[0]);
+        edited.checksum = checksum("something else");
+        let Err(wit::MigrationError::ChecksumMismatch(m)) = plan(&set, &[edited], 3) else {
+            panic!("expected checksum mismatch");
+        };
+        assert_eq!((m.version, m.actual), (1, checksum(&set[0].up)));
+    }
+
+    #[test]
+    fn status_lists_current_and_pending() {
+        let set = set();
+        let s = status(&set, vec![applied(&set[0]), applied(&set[2])]);
+        assert_eq!(s.current, Some(3));
+        assert_eq!(s.pending, vec![2]);
+        assert_eq!(status(&set, vec![]).current, None);
+    }
+}
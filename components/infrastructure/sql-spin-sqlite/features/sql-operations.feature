@@ -3,16 +3,22 @@ Feature: SQL Operations
   I want to interact with a sqlite database
   So that I can persist and retrieve business data
 
+  # Scenarios tagged @native run under `cargo test` against a local SQLite
+  # (see src/bdd_tests.rs). Only a scenario that needs a real Spin host
+  # would go untagged.
+
   Background:
     Given a sqlite database is available
     And the database is empty
 
+  @native
   Scenario: Execute a simple query
     Given I have a table "users" with columns "id SERIAL PRIMARY KEY, name VARCHAR(255)"
     When I query "SELECT * FROM users"
     Then the query should succeed
     And the result should have 0 rows
 
+  @native
   Scenario: Insert and retrieve data
     Given I have a table "users" with columns "id SERIAL PRIMARY KEY, name VARCHAR(255), email VARCHAR(255)"
     When I execute "INSERT INTO users (name, email) VALUES ($1, $2)" with params ["John Doe", "john@example.com"]
@@ -24,18 +30,35 @@ Feature: SQL Operations
     And row 0 column "name" should be "John Doe"
     And row 0 column "email" should be "john@example.com"
 
+  @native
   Scenario: Handle connection failures gracefully
     Given the database connection is unavailable
     When I query "SELECT 1"
     Then the query should fail with error "connection-failed"
 
+  @native
+  Scenario: Unknown database labels fail to open
+    Given the component is granted databases "default"
+    When I open the database "does-not-exist"
+    Then the open should fail with error "connection-failed"
+
+  @native
   Scenario: Handle invalid SQL gracefully
     Given a sqlite database is available
     When I query "INVALID SQL STATEMENT"
     Then the query should fail with error "query-failed"
 
+  @native
+  Scenario: Duplicate unique values are reported as constraint violations
+    Given I have a table "users" with columns "id INTEGER PRIMARY KEY, email TEXT UNIQUE"
+    And I execute "INSERT INTO users (email) VALUES ($1)" with params ["john@example.com"]
+    When I execute "INSERT INTO users (email) VALUES ($1)" with params ["john@example.com"]
+    Then the execution should fail with error "constraint-violation"
+    And the violated constraint should be "unique" on "users.email"
+
+  @native
   Scenario: Transaction management
-    Given I have a table "accounts" with columns "id SERIAL PRIMARY KEY, balance INTEGER"
+    Given I have a table "accounts" with columns "id INTEGER PRIMARY KEY, balance INTEGER"
     And I execute "INSERT INTO accounts (balance) VALUES (100), (200)" with params []
     When I begin a transaction
     And I execute "UPDATE accounts SET balance = balance - 50 WHERE id = 1" in transaction
@@ -46,8 +69,9 @@ Feature: SQL Operations
     Then row 0 column "balance" should be 50
     And row 1 column "balance" should be 250
 
+  @native
   Scenario: Transaction rollback
-    Given I have a table "accounts" with columns "id SERIAL PRIMARY KEY, balance INTEGER"
+    Given I have a table "accounts" with columns "id INTEGER PRIMARY KEY, balance INTEGER"
     And I execute "INSERT INTO accounts (balance) VALUES (100), (200)" with params []
     When I begin a transaction
     And I execute "UPDATE accounts SET balance = balance - 50 WHERE id = 1" in transaction
@@ -57,3 +81,464 @@ Feature: SQL Operations
     When I query "SELECT balance FROM accounts ORDER BY id"
     Then row 0 column "balance" should be 100
     And row 1 column "balance" should be 200
+
+  @native
+  Scenario: Roll back to a savepoint and keep earlier work
+    Given I have a table "accounts" with columns "id INTEGER PRIMARY KEY, balance INTEGER"
+    And I execute "INSERT INTO accounts (balance) VALUES (100), (200)" with params []
+    When I begin a transaction
+    And I execute "UPDATE accounts SET balance = balance - 50 WHERE id = 1" in transaction
+    And I create savepoint "billing" in transaction
+    And I execute "UPDATE accounts SET balance = balance + 50 WHERE id = 2" in transaction
+    And I rollback to savepoint "billing" in transaction
+    And I commit the transaction
+    Then the transaction should succeed
+    When I query "SELECT balance FROM accounts ORDER BY id"
+    Then row 0 column "balance" should be 50
+    And row 1 column "balance" should be 200
+
+  @native
+  Scenario: Released savepoints commit with the outer transaction
+    Given I have a table "accounts" with columns "id INTEGER PRIMARY KEY, balance INTEGER"
+    And I execute "INSERT INTO accounts (balance) VALUES (100), (200)" with params []
+    When I begin a transaction
+    And I create savepoint "billing" in transaction
+    And I execute "UPDATE accounts SET balance = balance + 50 WHERE id = 2" in transaction
+    And I release savepoint "billing" in transaction
+    And I commit the transaction
+    Then the transaction should succeed
+    When I query "SELECT balance FROM accounts ORDER BY id"
+    Then row 0 column "balance" should be 100
+    And row 1 column "balance" should be 250
+
+  @native
+  Scenario: Nested savepoints roll back independently
+    Given I have a table "accounts" with columns "id INTEGER PRIMARY KEY, balance INTEGER"
+    And I execute "INSERT INTO accounts (balance) VALUES (100), (200)" with params []
+    When I begin a transaction
+    And I create savepoint "lifecycle" in transaction
+    And I execute "UPDATE accounts SET balance = 0 WHERE id = 1" in transaction
+    And I create savepoint "billing" in transaction
+    And I execute "UPDATE accounts SET balance = 0 WHERE id = 2" in transaction
+    And I rollback to savepoint "billing" in transaction
+    And I release savepoint "lifecycle" in transaction
+    And I commit the transaction
+    Then the transaction should succeed
+    When I query "SELECT balance FROM accounts ORDER BY id"
+    Then row 0 column "balance" should be 0
+    And row 1 column "balance" should be 200
+
+  @native
+  Scenario: Rolling back the transaction discards released savepoints
+    Given I have a table "accounts" with columns "id INTEGER PRIMARY KEY, balance INTEGER"
+    And I execute "INSERT INTO accounts (balance) VALUES (100), (200)" with params []
+    When I begin a transaction
+    And I create savepoint "billing" in transaction
+    And I execute "UPDATE accounts SET balance = 0 WHERE id = 1" in transaction
+    And I release savepoint "billing" in transaction
+    And I rollback the transaction
+    Then the transaction should succeed
+    When I query "SELECT balance FROM accounts ORDER BY id"
+    Then row 0 column "balance" should be 100
+    And row 1 column "balance" should be 200
+
+  @native
+  Scenario: Savepoints sent as SQL are refused inside a transaction
+    Given I have a table "accounts" with columns "id INTEGER PRIMARY KEY, balance INTEGER"
+    When I begin a transaction
+    And I execute "SAVEPOINT billing" in transaction
+    Then the execution should fail with error "transaction-failed"
+    When I create savepoint "billing" in transaction
+    And I execute "ROLLBACK TO billing" in transaction
+    Then the execution should fail with error "transaction-failed"
+    When I rollback to savepoint "billing" in transaction
+    Then the transaction should succeed
+    And the transaction should still be active
+
+  # Instances "a" and "b" are separate component instances, each with its own
+  # connection to the same database. Within one instance every connection
+  # resource on a label shares a single SQLite connection, so a second BEGIN
+  # there is rejected instead of contending for locks.
+  @native
+  Scenario: Deferred read-then-write transactions can fail to upgrade
+    Given I have a table "accounts" with columns "id INTEGER PRIMARY KEY, balance INTEGER"
+    And I execute "INSERT INTO accounts (balance) VALUES (100)" with params []
+    And instance "a" begins a transaction with mode "deferred"
+    And instance "b" begins a transaction with mode "deferred"
+    When instance "a" queries "SELECT balance FROM accounts WHERE id = 1" in transaction
+    And instance "b" queries "SELECT balance FROM accounts WHERE id = 1" in transaction
+    And instance "a" executes "UPDATE accounts SET balance = balance - 10 WHERE id = 1" in transaction
+    And instance "b" executes "UPDATE accounts SET balance = balance - 10 WHERE id = 1" in transaction
+    Then one of the executions should fail with error code 5
+
+  @native
+  Scenario: IMMEDIATE transactions avoid the read-then-write upgrade deadlock
+    Given I have a table "accounts" with columns "id INTEGER PRIMARY KEY, balance INTEGER"
+    And I execute "INSERT INTO accounts (balance) VALUES (100)" with params []
+    And instance "a" begins a transaction with mode "immediate"
+    When instance "b" starts to begin a transaction with mode "immediate"
+    And instance "a" queries "SELECT balance FROM accounts WHERE id = 1" in transaction
+    And instance "a" executes "UPDATE accounts SET balance = balance - 10 WHERE id = 1" in transaction
+    Then instance "b" should still be waiting to begin
+    When instance "a" commits the transaction
+    Then instance "b" should then begin its transaction
+    When instance "b" queries "SELECT balance FROM accounts WHERE id = 1" in transaction
+    And instance "b" executes "UPDATE accounts SET balance = balance - 10 WHERE id = 1" in transaction
+    And instance "b" commits the transaction
+    Then every execution should succeed
+    When I query "SELECT balance FROM accounts WHERE id = 1"
+    Then row 0 column "balance" should be 80
+
+  @native
+  Scenario: Read-only transactions reject writes
+    Given I have a table "accounts" with columns "id INTEGER PRIMARY KEY, balance INTEGER"
+    When I begin a read-only transaction
+    And I execute "INSERT INTO accounts (balance) VALUES (1)" in transaction
+    Then the execution should fail with error "query-failed"
+
+  @native
+  Scenario: Autocommit writes retry while another instance holds the write lock
+    Given I have a table "accounts" with columns "id INTEGER PRIMARY KEY, balance INTEGER"
+    And the variable "sql_retry_max_attempts" is "5"
+    And the variable "sql_retry_initial_backoff_ms" is "20"
+    And SQLite does not wait for locks on this instance
+    And instance "a" begins a transaction with mode "immediate"
+    When I execute "INSERT INTO accounts (balance) VALUES (1)" with params [] while instance "a" commits after 50 milliseconds
+    Then the execution should succeed
+    And the execution should have been retried
+    And every execution should succeed
+
+  @native
+  Scenario: Busy errors inside an explicit transaction are not retried
+    Given I have a table "accounts" with columns "id INTEGER PRIMARY KEY, balance INTEGER"
+    And the variable "sql_retry_max_attempts" is "5"
+    And instance "a" begins a transaction with mode "immediate"
+    And instance "b" begins a transaction with mode "deferred" and busy timeout 0 ms
+    When instance "b" executes "INSERT INTO accounts (balance) VALUES (1)" in transaction
+    Then one of the executions should fail with error code 5
+    And the execution should not have been retried
+
+  @native
+  Scenario: Batch execution reports rows affected per parameter row
+    Given I have a table "users" with columns "id INTEGER PRIMARY KEY, email TEXT UNIQUE"
+    When I execute batch "INSERT INTO users (email) VALUES (?)" with rows [["a@example.com"], ["b@example.com"], ["c@example.com"]]
+    Then the batch should succeed with counts [1, 1, 1]
+    When I query "SELECT COUNT(*) AS n FROM users"
+    Then row 0 column "n" should be 3
+
+  @native
+  Scenario: A failing batch row leaves nothing applied
+    Given I have a table "users" with columns "id INTEGER PRIMARY KEY, email TEXT UNIQUE"
+    When I execute batch "INSERT INTO users (email) VALUES (?)" with rows [["a@example.com"], ["a@example.com"]]
+    Then the execution should fail with error "constraint-violation"
+    And the error message should start with "batch row 1:"
+    When I query "SELECT COUNT(*) AS n FROM users"
+    Then row 0 column "n" should be 0
+
+  @native
+  Scenario: Scripts run every statement atomically
+    When I execute script "CREATE TABLE notes (body TEXT); INSERT INTO notes VALUES ('a;b'); INSERT INTO notes VALUES ('c')"
+    Then the batch should succeed with counts [0, 1, 1]
+    When I query "SELECT COUNT(*) AS n FROM notes"
+    Then row 0 column "n" should be 2
+
+  @native
+  Scenario: A failing batch inside a transaction only undoes the batch
+    Given I have a table "users" with columns "id INTEGER PRIMARY KEY, email TEXT UNIQUE"
+    When I begin a transaction
+    And I execute "INSERT INTO users (email) VALUES ('kept@example.com')" in transaction
+    And I execute batch "INSERT INTO users (email) VALUES (?)" with rows [["x@example.com"], ["x@example.com"]] in transaction
+    Then the execution should fail with error "constraint-violation"
+    When I commit the transaction
+    And I query "SELECT email FROM users"
+    Then row 0 column "email" should be "kept@example.com"
+
+  @native
+  Scenario: Streaming a query with LIMIT/OFFSET pages
+    Given I have a table "events" with columns "id INTEGER PRIMARY KEY, kind TEXT"
+    And I insert 25 rows into "events"
+    When I stream "SELECT id FROM events ORDER BY id" with params []
+    Then fetching batches of 10 should return 10, 10, 5 and then 0 rows
+    And the streamed "id" values should be 1 through 25 in order
+
+  @native
+  Scenario: Streaming a query with keyset pages
+    Given I have a table "events" with columns "id INTEGER PRIMARY KEY, kind TEXT"
+    And I insert 25 rows into "events"
+    When I stream "SELECT id, kind FROM events WHERE id > ?" with params [5] keyed by "id"
+    Then fetching batches of 8 should return 8, 8, 4 and then 0 rows
+    And the streamed "id" values should be 6 through 25 in order
+
+  @native
+  Scenario: Streaming with a key column missing from the result
+    Given I have a table "events" with columns "id INTEGER PRIMARY KEY, kind TEXT"
+    And I insert 3 rows into "events"
+    When I stream "SELECT kind FROM events" with params [] keyed by "id"
+    And I fetch a batch of 2 rows
+    Then the execution should fail with error "invalid-parameter"
+
+  @native
+  Scenario: Streaming a statement that is not a query is refused up front
+    Given I have a table "events" with columns "id INTEGER PRIMARY KEY, kind TEXT"
+    And I insert 3 rows into "events"
+    When I stream "DELETE FROM events RETURNING id" with params []
+    Then the execution should fail with error "invalid-parameter"
+    When I query "SELECT COUNT(*) AS n FROM events"
+    Then row 0 column "n" should be 3
+
+  @native
+  Scenario: Columnar query results list column names once
+    Given I have a table "users" with columns "id INTEGER PRIMARY KEY, name TEXT"
+    And I execute "INSERT INTO users (name) VALUES ('Ada'), (NULL)" with params []
+    When I query columnar "SELECT id, name FROM users ORDER BY id" with params []
+    Then the columns should be "id" as int64 not nullable and "name" as text nullable
+    And columnar row 0 should be [1, "Ada"]
+    And columnar row 1 should be [2, null]
+
+  @native
+  Scenario: Prepared statements re-run with new bindings
+    Given I have a table "users" with columns "id INTEGER PRIMARY KEY, email TEXT"
+    When I prepare "INSERT INTO users (email) VALUES (?)"
+    And I bind ["a@example.com"] and execute the statement
+    And I bind ["b@example.com"] and execute the statement
+    Then each execution should affect 1 row
+    When I prepare "SELECT email FROM users WHERE id = ?"
+    And I bind [2] and query the statement
+    Then row 0 column "email" should be "b@example.com"
+
+  @native
+  Scenario: Preparing invalid SQL fails before any bindings
+    When I prepare "SELEC email FROM users"
+    Then the execution should fail with error "query-failed"
+
+  @native
+  Scenario: Postgres-style placeholders bind by number, not by order of appearance
+    Given I have a table "users" with columns "id INTEGER PRIMARY KEY, name TEXT, email TEXT"
+    When I execute "INSERT INTO users (email, name) VALUES ($2, $1)" with params ["John Doe", "john@example.com"]
+    And I query "SELECT name, email FROM users" with params []
+    Then row 0 column "name" should be "John Doe"
+    And row 0 column "email" should be "john@example.com"
+
+  @native
+  Scenario: Named parameters with any sigil
+    Given I have a table "users" with columns "id INTEGER PRIMARY KEY, name TEXT, email TEXT"
+    When I execute named "INSERT INTO users (name, email) VALUES (:name, @email)" with params {"name": "Ada", "email": "ada@example.com"}
+    And I query named "SELECT name FROM users WHERE email = $email" with params {"email": "ada@example.com"}
+    Then row 0 column "name" should be "Ada"
+
+  @native
+  Scenario: Missing named parameters are rejected before reaching SQLite
+    When I query named "SELECT :a, :b" with params {"a": 1}
+    Then the execution should fail with error "invalid-parameter"
+
+  @native
+  Scenario: An empty database lists no tables
+    When I list the tables
+    Then the table list should be empty
+
+  @native
+  Scenario: Describing a table reports columns and foreign keys
+    Given I have a table "orgs" with columns "id INTEGER PRIMARY KEY, name TEXT NOT NULL"
+    And I have a table "users" with columns "id INTEGER PRIMARY KEY, org_id INTEGER NOT NULL REFERENCES orgs(id) ON DELETE CASCADE, email TEXT DEFAULT 'none'"
+    When I describe table "users"
+    Then column "id" should have type "INTEGER" and primary key position 1
+    And column "org_id" should be not null
+    And column "email" should have default "'none'"
+    And the table should have a foreign key from "org_id" to "orgs"."id" on delete "CASCADE"
+
+  @native
+  Scenario: Describing a missing table
+    When I describe table "ghosts"
+    Then the execution should fail with error "not-found"
+
+  @native
+  Scenario: Listing indexes of a table
+    Given I have a table "users" with columns "id INTEGER PRIMARY KEY, email TEXT UNIQUE, name TEXT"
+    And I execute "CREATE INDEX users_name ON users (name) WHERE name IS NOT NULL" with params []
+    When I list the indexes of "users"
+    Then index "users_name" should be explicit, partial and cover "name"
+    And there should be a unique-constraint index covering "email"
+
+  @native
+  Scenario: Explaining a query reports full scans and index searches
+    Given I have a table "users" with columns "id INTEGER PRIMARY KEY, email TEXT, name TEXT"
+    And I execute "CREATE INDEX users_email ON users (email)" with params []
+    When I explain "SELECT id FROM users WHERE name = ?" with params ["Ada"]
+    Then the plan should have a full scan of "users"
+    When I explain "SELECT id, name FROM users WHERE email = ?" with params ["ada@example.com"]
+    Then the plan should have no full scans
+    And a plan node detail should contain "USING INDEX users_email"
+
+  @native
+  Scenario: Dev-mode warning for full scans of large tables
+    Given the variable "sql_warn_scan_rows" is "100"
+    And I have a table "events" with columns "id INTEGER PRIMARY KEY, kind TEXT"
+    And I insert 150 rows into "events"
+    When I query "SELECT id FROM events WHERE kind = 'signup'"
+    Then a warning "query plan scans a large table" should be logged for table "events"
+
+  @native
+  Scenario: Calls are traced with a statement fingerprint
+    Given I have a table "users" with columns "id INTEGER PRIMARY KEY, name TEXT"
+    When I execute "INSERT INTO users (name) VALUES (?)" with params ["Ada"]
+    Then an "sql" span should be recorded with op "execute", fingerprint "INSERT INTO users (name) VALUES (?)", 1 param and 1 row
+    When I query "SELECT * FROM ghosts WHERE id = 7"
+    Then an "sql" span should be recorded with fingerprint "SELECT * FROM ghosts WHERE id = ?" and error "query-failed"
+
+  @native
+  Scenario: Metrics count calls per operation and per statement
+    Given I have a table "users" with columns "id INTEGER PRIMARY KEY, name TEXT"
+    When I execute "INSERT INTO users (name) VALUES ('Ada')" with params []
+    And I execute "INSERT INTO users (name) VALUES ('Grace')" with params []
+    And I query "SELECT name FROM users"
+    And I read the metrics
+    Then operation "execute" should have 3 calls and 0 errors
+    And operation "query" should have 1 call with 2 rows
+    And statement "INSERT INTO users (name) VALUES (?)" should have 2 calls
+    And the latency buckets of every operation should add up to its calls
+
+  @native
+  Scenario: Slow queries are logged with redacted parameters and a plan
+    Given the variable "sql_slow_query_ms" is "0"
+    And I have a table "users" with columns "id INTEGER PRIMARY KEY, email TEXT"
+    When I query "SELECT id FROM users WHERE email = ?" with params ["ada@example.com"]
+    And I read the diagnostics
+    Then the newest slow query should have fingerprint "SELECT id FROM users WHERE email = ?"
+    And its params should be ["text(15)"]
+    And its plan should contain "SCAN users"
+    And the diagnostics should not contain "ada@example.com"
+
+  @native
+  Scenario: The slow query log keeps only the most recent entries
+    Given the variable "sql_slow_query_ms" is "0"
+    And the variable "sql_slow_query_capacity" is "2"
+    When I query "SELECT 1"
+    And I query "SELECT 'a'"
+    And I query "SELECT x'00'"
+    And I read the diagnostics
+    Then the diagnostics should list 2 slow queries
+    And the newest slow query should have operation "query"
+
+  @native
+  Scenario: The slow query log is off by default
+    When I query "SELECT 1"
+    And I read the diagnostics
+    Then the slow query threshold should be unset
+    And the diagnostics should list 0 slow queries
+
+  @native
+  Scenario: Read-only mode rejects writes on every path
+    Given the variable "sql_read_only" is "true"
+    When I query "DROP TABLE users"
+    Then the execution should fail with error "query-failed" and code 8
+    And the error message should contain "read-only mode rejects DROP statements"
+    When I execute script "SELECT 1; DELETE FROM users"
+    Then the execution should fail with error "query-failed" and code 8
+    When I prepare "INSERT INTO users (name) VALUES (?)"
+    Then the execution should fail with error "query-failed" and code 8
+
+  @native
+  Scenario: Read-only mode allows reads, including through WITH
+    Given the variable "sql_read_only" is "true"
+    When I query "WITH n(x) AS (VALUES (1), (2)) SELECT x FROM n"
+    Then I should get 2 rows
+
+  @native
+  Scenario: Read-only mode keeps query_only on across transactions
+    Given the variable "sql_read_only" is "true"
+    When I begin a transaction with options read-only true
+    And I commit the transaction
+    Then the connection should still have query_only on
+
+  @native
+  Scenario: The allow-list only permits registered statement fingerprints
+    Given the variable "sql_allowed_statements" is "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT); SELECT name FROM users WHERE id = ?; INSERT INTO users (name) VALUES (?)"
+    And I have a table "users" with columns "id INTEGER PRIMARY KEY, name TEXT"
+    When I execute "INSERT INTO users (name) VALUES ('Ada')" with params []
+    Then the execution should succeed
+    When I query "SELECT name FROM users WHERE id = 1"
+    Then I should get 1 row
+    When I query "SELECT name FROM users"
+    Then the execution should fail with error "query-failed" and code 23
+    And the error message should contain "not in the allow-list: SELECT name FROM users"
+
+  @native
+  Scenario: Tenant connections route to the tenant's own database
+    Given the component is granted databases "default.acme" and "default.globex"
+    And I open tenant "acme" of database "default"
+    And I execute "CREATE TABLE docs (id INTEGER PRIMARY KEY, body TEXT)" on the connection
+    And I execute "INSERT INTO docs (body) VALUES ('acme secret')" on the connection
+    When I open tenant "globex" of database "default"
+    And I query "SELECT name FROM sqlite_master WHERE name = 'docs'" on the connection
+    Then I should get 0 rows
+
+  @native
+  Scenario: A tenant cannot read another tenant's rows
+    Given the component is granted databases "default.acme" and "default.globex"
+    And tenant "acme" has a "docs" table containing "acme secret"
+    And tenant "globex" has a "docs" table containing "globex notes"
+    When I open tenant "globex" of database "default"
+    And I query "SELECT body FROM docs" on the connection
+    Then I should get 1 row with column "body" equal to "globex notes"
+    And no row should contain "acme secret"
+
+  @native
+  Scenario: Invalid tenant ids are rejected
+    When I open tenant "acme.globex" of database "default"
+    Then the execution should fail with error "invalid-parameter"
+
+  @native
+  Scenario: Required tenant scoping refuses unscoped databases
+    Given the variable "sql_tenant_required" is "true"
+    When I query "SELECT 1"
+    Then the execution should fail with error "connection-failed" and code 23
+    When I open the database "audit"
+    Then the execution should fail with error "connection-failed" and code 23
+
+  @native
+  Scenario: Required tenant scoping refuses ATTACH
+    Given the variable "sql_tenant_required" is "true"
+    And the component is granted databases "default.acme"
+    When I open tenant "acme" of database "default"
+    And I execute "ATTACH 'default.globex' AS other" on the connection
+    Then the execution should fail with error "query-failed" and code 23
+
+  @native
+  Scenario: Queries over max-rows fail with limit-exceeded
+    Given I have a table "events" with columns "id INTEGER PRIMARY KEY, kind TEXT"
+    And I insert 150 rows into "events"
+    When I query "SELECT id FROM events ORDER BY id" with options max-rows 100
+    Then the execution should fail with error "limit-exceeded" and limit kind "rows" of 100
+    When I query "SELECT id FROM events ORDER BY id LIMIT 50" with options max-rows 100
+    Then I should get 50 rows
+
+  @native
+  Scenario: Queries over max-bytes fail with limit-exceeded
+    Given I have a table "docs" with columns "id INTEGER PRIMARY KEY, body TEXT"
+    And I execute "INSERT INTO docs (body) VALUES (?)" with params ["0123456789"]
+    And I execute "INSERT INTO docs (body) VALUES (?)" with params ["0123456789"]
+    When I query "SELECT body FROM docs" with options max-bytes 15
+    Then the execution should fail with error "limit-exceeded" and limit kind "bytes" of 15
+
+  @native
+  Scenario: Queries running past max-duration-ms fail with limit-exceeded
+    When I query "WITH RECURSIVE n(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM n WHERE x < 200000) SELECT count(*) FROM n" with options max-duration-ms 1
+    Then the execution should fail with error "limit-exceeded" and limit kind "duration" of 1
+
+  @native
+  Scenario: Limits apply inside transactions
+    Given I have a table "events" with columns "id INTEGER PRIMARY KEY, kind TEXT"
+    And I insert 10 rows into "events"
+    When I begin a transaction
+    And I query "SELECT id FROM events" with options max-rows 5 in the transaction
+    Then the execution should fail with error "limit-exceeded"
+    And the transaction should still be active
+
+  @native
+  Scenario: Limits are refused on statements that write
+    Given I have a table "events" with columns "id INTEGER PRIMARY KEY, kind TEXT"
+    And I insert 3 rows into "events"
+    When I query "DELETE FROM events RETURNING id" with options max-rows 1
+    Then the execution should fail with error "invalid-parameter"
+    When I query "SELECT COUNT(*) AS n FROM events"
+    Then row 0 column "n" should be 3
@@ -0,0 +1,382 @@
+//! `execute-batch` and `execute-script`.
+//!
+//! Both run in one implicit transaction, so a bulk import pays for a single
+//! commit instead of one per row and a failure leaves nothing half-applied.
+//! When an explicit transaction is already open on the connection a savepoint
+//! is used instead, which undoes only the batch on failure. Scripts therefore
+//! must not contain their own BEGIN/COMMIT.
+//!
+//! A batch's per-row counts come back from the statement itself where SQLite
+//! allows a RETURNING clause on it, instead of costing a `changes()` query
+//! after every row.
+
+use std::borrow::Cow;
+use std::cell::Cell;
+
+use spin_sdk::sqlite::Value as SpinValue;
+
+use crate::config::Config;
+use crate::error::{self, map_err};
+use crate::host::Connection;
+use crate::lexer::{self, Kind};
+use crate::params::normalize_positional;
+use crate::values::values_from;
+use crate::{policy, read_counters, rows_affected, wit_sql};
+
+thread_local! {
+    /// Numbers the savepoints of nested batches so none shadows one the
+    /// guest opened.
+    static SAVEPOINTS: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Run `sql` once per parameter row; returns rows affected per row.
+pub(crate) fn exec_batch_on(
+    conn: &Connection,
+    sql: &str,
+    rows: &[Vec<wit_sql::SqlValue>],
+    nested: bool,
+) -> Result<Vec<u64>, wit_sql::SqlError> {
+    if rows.is_empty() {
+        return Ok(Vec::new());
+    }
+    policy::check(sql)?;
+    let normalized = normalize_positional(sql);
+    let uuid_storage = Config::get().uuid_storage;
+    let values = |i: usize| values_from(&rows[i], uuid_storage).map_err(|e| at_row(e, i));
+    let returning = returning_form(conn, &normalized, &values(0)?);
+    let statement = returning.as_deref().unwrap_or(&normalized);
+    atomically(conn, nested, || {
+        let mut before = returning
+            .is_none()
+            .then(|| read_counters(conn))
+            .transpose()?;
+        (0..rows.len())
+            .map(|i| {
+                let qr = conn
+                    .execute(statement, values(i)?.as_slice())
+                    .map_err(|e| at_row(map_err(e, "query", Some(sql)), i))?;
+                let Some(before) = &mut before else {
+                    return Ok(qr.rows.len() as u64);
+                };
+                let after = read_counters(conn)?;
+                Ok(rows_affected(&std::mem::replace(before, after), &after))
+            })
+            .collect()
+    })
+}
+
+/// `sql` as an INSERT, UPDATE or DELETE that returns one row per row it
+/// changes, so a batch reads each row's count from the statement itself
+/// rather than asking for `changes()` after it. `None` when `sql` is some
+/// other statement or SQLite will not compile the RETURNING form, e.g. an
+/// UPDATE or DELETE on a virtual table; checking once with EXPLAIN covers
+/// the whole batch.
+fn returning_form<'a>(
+    conn: &Connection,
+    sql: &'a str,
+    values: &[SpinValue],
+) -> Option<Cow<'a, str>> {
+    let dml = policy::main_keyword(sql).is_some_and(|k| {
+        ["INSERT", "REPLACE", "UPDATE", "DELETE"]
+            .iter()
+            .any(|dml| k.eq_ignore_ascii_case(dml))
+    });
+    if !dml {
+        return None;
+    }
+    let words = policy::words(sql);
+    let top = words.first().map(|(depth, _)| *depth);
+    if words
+        .iter()
+        .any(|(depth, w)| Some(*depth) == top && w.eq_ignore_ascii_case("RETURNING"))
+    {
+        return Some(Cow::Borrowed(sql));
+    }
+    // On its own line so a trailing `--` comment cannot swallow it.
+    let returning = format!("{}\nRETURNING 1", sql.trim().trim_end_matches(';'));
+    conn.execute(&format!("EXPLAIN {returning}"), values)
+        .is_ok()
+        .then_some(Cow::Owned(returning))
+}
+
+/// Run each statement of `script`; returns rows affected per statement.
+pub(crate) fn exec_script_on(
+    conn: &Connection,
+    script: &str,
+    nested: bool,
+) -> Result<Vec<u64>, wit_sql::SqlError> {
+    let statements = split_script(script);
+    if statements.is_empty() {
+        return Ok(Vec::new());
+    }
+    policy::check(script)?;
+    atomically(conn, nested, || {
+        let mut before = read_counters(conn)?;
+        let mut counts = Vec::with_capacity(statements.len());
+        for sql in &statements {
+            conn.execute(sql, &[])
+                .map_err(|e| map_err(e, "query", Some(sql)))?;
+            let after = read_counters(conn)?;
+            counts.push(rows_affected(&before, &after));
+            before = after;
+        }
+        Ok(counts)
+    })
+}
+
+/// Run `f` between BEGIN/COMMIT, or inside a savepoint when `nested`, undoing
+/// its writes if it fails.
+fn atomically<T>(
+    conn: &Connection,
+    nested: bool,
+    f: impl FnOnce() -> Result<T, wit_sql::SqlError>,
+) -> Result<T, wit_sql::SqlError> {
+    let (begin, commit, rollback) = if nested {
+        let name = format!("keel_batch_{}", SAVEPOINTS.replace(SAVEPOINTS.get() + 1));
+        (
+            format!("SAVEPOINT {name}"),
+            format!("RELEASE {name}"),
+            vec![format!("ROLLBACK TO {name}"), format!("RELEASE {name}")],
+        )
+    } else {
+        ("BEGIN".into(), "COMMIT".into(), vec!["ROLLBACK".into()])
+    };
+    let run = |statement: &str| {
+        conn.execute(statement, &[])
+            .map(|_| ())
+            .map_err(|e| map_err(e, "transaction", Some(statement)))
+    };
+    run(&begin)?;
+    let result = f().and_then(|value| run(&commit).map(|()| value));
+    if result.is_err() {
+        for statement in &rollback {
+            if let Err(e) = run(statement) {
+                tracing::warn!(error = ?e, statement, "failed to undo batch");
+            }
+        }
+    }
+    result
+}
+
+/// Say which parameter row of a batch failed.
+fn at_row(mut e: wit_sql::SqlError, row: usize) -> wit_sql::SqlError {
+    if let Some(info) = error::info_mut(&mut e) {
+        info.message = format!("batch row {row}: {}", info.message);
+    }
+    e
+}
+
+/// Split a script on top-level `;`, skipping quoted text, comments and the
+/// bodies of CREATE TRIGGER statements. Empty statements are dropped.
+pub(crate) fn split_script(script: &str) -> Vec<&str> {
+    let mut statements = Vec::new();
+    let mut start = 0;
+    // Per-statement state: words seen, whether it has anything but comments,
+    // and BEGIN/CASE ... END nesting inside a trigger body.
+    let mut words = 0;
+    let mut code = false;
+    let mut create = false;
+    let mut temp = false;
+    let mut trigger = false;
+    let mut depth = 0usize;
+    for token in lexer::tokens(script) {
+        match token.kind {
+            Kind::Space | Kind::Comment => {}
+            Kind::Symbol if token.text == ";" && depth == 0 => {
+                if code {
+                    statements.push(script[start..token.start].trim());
+                }
+                start = token.range().end;
+                (words, code, create, temp, trigger) = (0, false, false, false, false);
+            }
+            Kind::Word => {
+                let is = |kw: &str| token.is(kw);
+                match words {
+                    0 => create = is("create"),
+                    1 if create && (is("temp") || is("temporary")) => temp = true,
+                    1 | 2 if create && is("trigger") && (words == 1 || temp) => trigger = true,
+                    _ if trigger && (is("begin") || is("case")) => depth += 1,
+                    _ if trigger && is("end") => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
+                words += 1;
+                code = true;
+            }
+            _ => code = true,
+        }
+    }
+    if code {
+        statements.push(script[start..].trim());
+    }
+    statements
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_on_top_level_semicolons() {
+        assert_eq!(
+            split_script(
+                "CREATE TABLE t (x);\n INSERT INTO t VALUES (1) ;INSERT INTO t VALUES (2)"
+            ),
+            vec![
+                "CREATE TABLE t (x)",
+                "INSERT INTO t VALUES (1)",
+                "INSERT INTO t VALUES (2)"
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_semicolons_in_quotes_and_comments() {
+        let script = "INSERT INTO t VALUES ('a;b', \"c;\"\"d\", [e;f], `g;h`); -- x; y\n\
+                      /* z; */ SELECT 'it''s;'";
+        assert_eq!(
+            split_script(script),
+            vec![
+                "INSERT INTO t VALUES ('a;b', \"c;\"\"d\", [e;f], `g;h`)",
+                "-- x; y\n/* z; */ SELECT 'it''s;'"
+            ]
+        );
+    }
+
+    #[test]
+    fn drops_empty_and_comment_only_statements() {
+        assert_eq!(
+            split_script(" ;; -- nothing\n; /* here */ "),
+            Vec::<&str>::new()
+        );
+        assert_eq!(split_script("SELECT 1;;"), vec!["SELECT 1"]);
+    }
+
+    #[test]
+    fn keeps_trigger_bodies_whole() {
+        let trigger = "CREATE TEMP TRIGGER audit AFTER INSERT ON t BEGIN \
+                       INSERT INTO log VALUES (CASE WHEN new.x > 0 THEN 'pos' ELSE 'neg' END); \
+                       UPDATE t SET y = 1; END";
+        let script = format!("{trigger}; INSERT INTO t VALUES (1)");
+        assert_eq!(
+            split_script(&script),
+            vec![trigger, "INSERT INTO t VALUES (1)"]
+        );
+    }
+
+    #[test]
+    fn begin_outside_triggers_is_a_statement() {
+        assert_eq!(
+            split_script("BEGIN; UPDATE t SET x = 1; END"),
+            vec!["BEGIN", "UPDATE t SET x = 1", "END"]
+        );
+    }
+
+    fn text(s: &str) -> Vec<wit_sql::SqlValue> {
+        vec![wit_sql::SqlValue::Text(s.to_string())]
+    }
+
+    fn users(label: &str) {
+        crate::connection::with_shared(label, |conn| {
+            conn.execute("DROP TABLE IF EXISTS users", &[]).unwrap();
+            conn.execute(
+                "CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT UNIQUE)",
+                &[],
+            )
+            .unwrap();
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    fn count(label: &str) -> wit_sql::SqlValue {
+        let qr = crate::Database::connect(label.to_string())
+            .unwrap()
+            .run_query("SELECT COUNT(*) AS n FROM users", &[], &[])
+            .unwrap();
+        qr.rows[0].columns[0].1.clone()
+    }
+
+    #[test]
+    fn a_failing_row_undoes_the_rows_before_it() {
+        users("batch");
+        let db = crate::Database::connect("batch".to_string()).unwrap();
+        let insert = "INSERT INTO users (email) VALUES (?)";
+        let Err(wit_sql::SqlError::ConstraintViolation(details)) =
+            db.run_execute_batch(insert, &[text("a"), text("b"), text("a")])
+        else {
+            panic!("duplicate email was accepted");
+        };
+        assert!(
+            details.info.message.starts_with("batch row 2: "),
+            "{}",
+            details.info.message
+        );
+        assert_eq!(count("batch"), wit_sql::SqlValue::Int64(0));
+    }
+
+    #[test]
+    fn rows_report_what_each_changed() {
+        users("batch");
+        let db = crate::Database::connect("batch".to_string()).unwrap();
+        let insert = "INSERT OR IGNORE INTO users (email) VALUES (?) -- import";
+        let counts = db.run_execute_batch(insert, &[text("a"), text("a"), text("b")]);
+        assert_eq!(counts.unwrap(), [1, 0, 1]);
+        let update = "UPDATE users SET email = email || '!' WHERE email >= ?";
+        let counts = db.run_execute_batch(update, &[text("a"), text("z")]);
+        assert_eq!(counts.unwrap(), [2, 0]);
+    }
+
+    #[test]
+    fn statements_without_a_returning_form_read_changes() {
+        let conn = Connection::open("batch_fts").unwrap();
+        conn.execute("DROP TABLE IF EXISTS docs", &[]).unwrap();
+        conn.execute("CREATE VIRTUAL TABLE docs USING fts5(body)", &[])
+            .unwrap();
+        conn.execute("INSERT INTO docs VALUES ('a'), ('b'), ('b')", &[])
+            .unwrap();
+        let delete = "DELETE FROM docs WHERE body = ?";
+        let b = [SpinValue::Text("b".to_string())];
+        assert!(returning_form(&conn, delete, &b).is_none());
+        assert!(returning_form(&conn, "SELECT 1", &[]).is_none());
+        let returning = "DELETE FROM t RETURNING id";
+        assert_eq!(
+            returning_form(&conn, returning, &[]).as_deref(),
+            Some(returning)
+        );
+        let rows = [text("b"), text("c")];
+        assert_eq!(exec_batch_on(&conn, delete, &rows, false).unwrap(), [2, 0]);
+    }
+
+    #[test]
+    fn batches_inside_a_transaction_undo_only_themselves() {
+        use crate::transaction::Transaction;
+        use crate::wit_sql::GuestTransaction as _;
+
+        users("batch_tx");
+        let tx = Transaction::begin("batch_tx", None).unwrap();
+        let insert = "INSERT INTO users (email) VALUES (?)".to_string();
+        tx.execute(insert.clone(), text("x")).unwrap();
+        // A guest savepoint of any name survives the batch's own.
+        tx.savepoint("keel_batch".to_string()).unwrap();
+        let failed = tx.execute_batch(insert.clone(), vec![text("a"), text("x")]);
+        assert!(matches!(
+            failed,
+            Err(wit_sql::SqlError::ConstraintViolation(_))
+        ));
+        let counts = tx.execute_batch(insert.clone(), vec![text("a"), text("b")]);
+        assert_eq!(counts.unwrap(), [1, 1]);
+        tx.rollback_to("keel_batch".to_string()).unwrap();
+        tx.execute_batch(insert, vec![text("c")]).unwrap();
+        tx.commit().unwrap();
+        assert_eq!(count("batch_tx"), wit_sql::SqlValue::Int64(2));
+    }
+
+    #[test]
+    fn at_row_prefixes_the_message() {
+        let e = wit_sql::SqlError::QueryFailed(error::adapter_error(1, "boom", None));
+        let wit_sql::SqlError::QueryFailed(info) = at_row(e, 3) else {
+            panic!("variant changed");
+        };
+        assert_eq!(info.message, "batch row 3: boom");
+    }
+}
@@ -0,0 +1,1255 @@
+//! Step definitions for `features/sql-operations.feature`.
+//!
+//! Scenarios tagged `@native` run under `cargo test` against the local SQLite
+//! behind [`crate::host`]; untagged ones are left to a real Spin host. A
+//! tagged scenario with a step missing here fails rather than being skipped.
+//!
+//! The steps live in the crate rather than under `tests/` because the
+//! component builds as a cdylib, which integration tests cannot link.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use cucumber::{World as _, given, then, when};
+use tracing::field::{Field, Visit};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt as _};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::config::Config;
+use crate::cursor::RowCursor;
+use crate::statement::Statement;
+use crate::transaction::Transaction;
+use crate::wit_sql::{
+    GuestConnection as _, GuestRowCursor as _, GuestStatement as _, GuestTransaction as _,
+};
+use crate::{
+    Database, connection, diagnostics, error, host, retry, schema, telemetry, tenant, wit_sql,
+};
+
+#[derive(Debug, Default, cucumber::World)]
+struct SqlWorld {
+    result: Option<wit_sql::QueryResult>,
+    columnar: Option<wit_sql::ColumnarResult>,
+    affected: Option<u64>,
+    /// Rows affected per row or statement of the last batch.
+    counts: Option<Vec<u64>>,
+    /// Rows affected by each execution of the prepared statement.
+    executed: Vec<u64>,
+    /// Rows read from the stream so far.
+    streamed: Vec<wit_sql::SqlRow>,
+    error: Option<wit_sql::SqlError>,
+    plan: Option<Vec<wit_sql::PlanNode>>,
+    metrics: Option<wit_sql::MetricsSnapshot>,
+    diagnostics: Option<wit_sql::DiagnosticsReport>,
+    tables: Option<Vec<String>>,
+    table: Option<wit_sql::TableInfo>,
+    indexes: Vec<wit_sql::IndexInfo>,
+    instances: BTreeMap<String, Instance>,
+    /// BEGINs still waiting for a lock, by instance.
+    pending: BTreeMap<String, mpsc::Receiver<Result<(), wit_sql::SqlError>>>,
+    /// Outcomes of the statements and commits run by instances.
+    executions: Vec<Result<(), wit_sql::SqlError>>,
+    /// Busy retries made by the last statement.
+    retries: u32,
+    /// The label of the connection opened by the last `open` step.
+    connection: Option<String>,
+    /// Spin variables set by the scenario.
+    variables: BTreeMap<String, String>,
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+thread_local! {
+    /// The transaction an instance thread has open.
+    static TRANSACTION: RefCell<Option<Transaction>> = const { RefCell::new(None) };
+    /// The stream the scenario is reading.
+    static STREAM: RefCell<Option<RowCursor>> = const { RefCell::new(None) };
+    /// The statement the scenario prepared last.
+    static STATEMENT: RefCell<Option<Statement>> = const { RefCell::new(None) };
+    /// Events logged on the scenario's thread.
+    static LOGGED: RefCell<Vec<Logged>> = const { RefCell::new(Vec::new()) };
+    /// Spans closed on the scenario's thread, with their final fields.
+    static SPANS: RefCell<Vec<(&'static str, Fields)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A `tracing` event and its fields, with the text under `message`.
+#[derive(Debug)]
+struct Logged {
+    level: tracing::Level,
+    fields: Fields,
+}
+
+#[derive(Debug, Default)]
+struct Fields(BTreeMap<String, String>);
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{value:?}"));
+    }
+}
+
+/// Keeps the events the adapter logs in [`LOGGED`] and its spans in
+/// [`SPANS`].
+struct Recorder;
+
+impl<S: tracing::Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Recorder {
+    fn on_new_span(
+        &self,
+        attrs: &tracing::span::Attributes<'_>,
+        id: &tracing::Id,
+        ctx: Context<'_, S>,
+    ) {
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(fields);
+        }
+    }
+
+    fn on_record(&self, id: &tracing::Id, values: &tracing::span::Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id)
+            && let Some(fields) = span.extensions_mut().get_mut::<Fields>()
+        {
+            values.record(fields);
+        }
+    }
+
+    fn on_close(&self, id: tracing::Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(&id)
+            && let Some(fields) = span.extensions_mut().remove::<Fields>()
+        {
+            SPANS.with(|spans| spans.borrow_mut().push((span.name(), fields)));
+        }
+    }
+
+    fn on_event(&self, event: &tracing::Event<'_>, _ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let level = *event.metadata().level();
+        LOGGED.with(|logged| logged.borrow_mut().push(Logged { level, fields }));
+    }
+}
+
+/// Another instance of the component: a thread with its own connection cache,
+/// named after the test thread so it opens the same database files.
+#[derive(Debug)]
+struct Instance {
+    jobs: Option<mpsc::Sender<Job>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Instance {
+    fn spawn() -> Self {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let name = thread::current().name().unwrap_or("instance").to_string();
+        let config = Config::get().clone();
+        let thread = thread::Builder::new()
+            .name(name)
+            .spawn(move || {
+                Config::set_for_thread(Some(config));
+                queue.into_iter().for_each(|job| job());
+            })
+            .expect("spawn instance");
+        Self {
+            jobs: Some(jobs),
+            thread: Some(thread),
+        }
+    }
+
+    /// Start `f` on the instance and return where its result will arrive.
+    fn start<T: Send + 'static>(
+        &self,
+        f: impl FnOnce() -> T + Send + 'static,
+    ) -> mpsc::Receiver<T> {
+        let (done, result) = mpsc::channel();
+        let job: Job = Box::new(move || {
+            let _ = done.send(f());
+        });
+        self.jobs
+            .as_ref()
+            .expect("running")
+            .send(job)
+            .expect("send");
+        result
+    }
+
+    fn run<T: Send + 'static>(&self, f: impl FnOnce() -> T + Send + 'static) -> T {
+        self.start(f).recv().expect("instance step panicked")
+    }
+}
+
+impl Drop for Instance {
+    fn drop(&mut self) {
+        // Closing the queue ends the thread, which rolls back any transaction
+        // it left open before the next scenario starts.
+        drop(self.jobs.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn begin(
+    mode: &str,
+    read_only: bool,
+    busy_timeout_ms: Option<u32>,
+) -> Result<(), wit_sql::SqlError> {
+    let mode = match mode {
+        "deferred" => wit_sql::TransactionMode::Deferred,
+        "immediate" => wit_sql::TransactionMode::Immediate,
+        "exclusive" => wit_sql::TransactionMode::Exclusive,
+        other => panic!("unknown transaction mode {other:?}"),
+    };
+    let options = wit_sql::TransactionOptions {
+        mode,
+        read_only,
+        busy_timeout_ms,
+    };
+    let tx = Transaction::begin(connection::DEFAULT_DATABASE, Some(options))?;
+    TRANSACTION.with(|open| *open.borrow_mut() = Some(tx));
+    Ok(())
+}
+
+/// Run `f` on the calling instance's open transaction.
+fn in_transaction<T>(
+    f: impl FnOnce(&Transaction) -> Result<T, wit_sql::SqlError>,
+) -> Result<T, wit_sql::SqlError> {
+    TRANSACTION.with(|open| f(open.borrow().as_ref().expect("no open transaction")))
+}
+
+impl SqlWorld {
+    /// Keep the error of a failed call, or clear the last one.
+    fn record<T>(&mut self, result: Result<T, wit_sql::SqlError>) -> Option<T> {
+        match result {
+            Ok(value) => {
+                self.error = None;
+                Some(value)
+            }
+            Err(e) => {
+                self.error = Some(e);
+                None
+            }
+        }
+    }
+
+    fn record_result(&mut self, result: Result<wit_sql::QueryResult, wit_sql::SqlError>) {
+        if let Some(result) = self.record(result) {
+            self.result = Some(result);
+        }
+    }
+
+    fn record_affected(&mut self, result: Result<u64, wit_sql::SqlError>) {
+        if let Some(affected) = self.record(result) {
+            self.affected = Some(affected);
+        }
+    }
+
+    fn query(&mut self, sql: &str, params: &[wit_sql::SqlValue]) {
+        self.record_result(Database::default().run_query(sql, params, &[]));
+    }
+
+    fn execute(&mut self, sql: &str, params: &[wit_sql::SqlValue]) {
+        self.record_affected(Database::default().run_execute(sql, params));
+    }
+
+    fn connection(&self) -> Database {
+        let label = self.connection.clone().expect("no open connection");
+        Database::connect(label).expect("reopen connection")
+    }
+
+    fn instance(&mut self, name: &str) -> &Instance {
+        self.instances
+            .entry(name.to_string())
+            .or_insert_with(Instance::spawn)
+    }
+
+    /// Fetch the next batch of the open stream, keeping its rows.
+    fn fetch(&mut self, max: u32) -> Option<usize> {
+        let batch = STREAM.with(|open| {
+            open.borrow()
+                .as_ref()
+                .expect("no open stream")
+                .next_batch(max)
+        });
+        let rows = self.record(batch)?;
+        let fetched = rows.len();
+        self.streamed.extend(rows);
+        Some(fetched)
+    }
+
+    fn column(&self, name: &str) -> &wit_sql::ColumnInfo {
+        let table = self.table.as_ref().expect("no table description");
+        table
+            .columns
+            .iter()
+            .find(|c| c.name == name)
+            .unwrap_or_else(|| panic!("no column {name:?}"))
+    }
+
+    fn call_metrics(&self, statement: bool, name: &str) -> &wit_sql::CallMetrics {
+        let metrics = self.metrics.as_ref().expect("metrics not read");
+        let list = if statement {
+            &metrics.statements
+        } else {
+            &metrics.operations
+        };
+        list.iter()
+            .find(|m| m.name == name)
+            .unwrap_or_else(|| panic!("no metrics for {name:?} in {list:?}"))
+    }
+
+    fn newest_slow_query(&self) -> &wit_sql::SlowQuery {
+        let report = self.diagnostics.as_ref().expect("diagnostics not read");
+        report.slow_queries.first().expect("no slow queries")
+    }
+
+    fn value(&self, row: usize, column: &str) -> &wit_sql::SqlValue {
+        let result = self.result.as_ref().expect("no query result");
+        let row = result.rows.get(row).expect("no such row");
+        row.columns
+            .iter()
+            .find_map(|(name, value)| (name == column).then_some(value))
+            .unwrap_or_else(|| panic!("no column {column:?}"))
+    }
+}
+
+/// Bind `params` to the prepared statement and run `f` on it.
+fn with_statement<T>(
+    params: Vec<wit_sql::SqlValue>,
+    f: impl FnOnce(&Statement) -> Result<T, wit_sql::SqlError>,
+) -> Result<T, wit_sql::SqlError> {
+    STATEMENT.with(|open| {
+        let open = open.borrow();
+        let statement = open.as_ref().expect("no prepared statement");
+        statement.bind(params)?;
+        f(statement)
+    })
+}
+
+/// Parameters written as a JSON array.
+fn params(json: &str) -> Vec<wit_sql::SqlValue> {
+    let values: Vec<serde_json::Value> = serde_json::from_str(json).expect("params are JSON");
+    values.into_iter().map(param).collect()
+}
+
+/// Named parameters written as a JSON object.
+fn named_params(json: &str) -> Vec<(String, wit_sql::SqlValue)> {
+    let values: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_str(json).expect("params are a JSON object");
+    values
+        .into_iter()
+        .map(|(name, value)| (name, param(value)))
+        .collect()
+}
+
+/// Batch parameter rows written as a JSON array of arrays.
+fn param_rows(json: &str) -> Vec<Vec<wit_sql::SqlValue>> {
+    let rows: Vec<Vec<serde_json::Value>> = serde_json::from_str(json).expect("rows are JSON");
+    rows.into_iter()
+        .map(|row| row.into_iter().map(param).collect())
+        .collect()
+}
+
+fn param(value: serde_json::Value) -> wit_sql::SqlValue {
+    match value {
+        serde_json::Value::Null => wit_sql::SqlValue::Null,
+        serde_json::Value::Bool(b) => wit_sql::SqlValue::Boolean(b),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => wit_sql::SqlValue::Int64(i),
+            None => wit_sql::SqlValue::Float64(n.as_f64().unwrap_or_default()),
+        },
+        serde_json::Value::String(s) => wit_sql::SqlValue::Text(s),
+        other => panic!("unsupported parameter {other}"),
+    }
+}
+
+/// Start each scenario as a fresh instance: defaults, no open transaction
+/// and no cached connections.
+#[given("a sqlite database is available")]
+fn database_available(_world: &mut SqlWorld) {
+    drop(TRANSACTION.with(|open| open.borrow_mut().take()));
+    drop(STREAM.with(|open| open.borrow_mut().take()));
+    drop(STATEMENT.with(|open| open.borrow_mut().take()));
+    LOGGED.with(|logged| logged.borrow_mut().clear());
+    SPANS.with(|spans| spans.borrow_mut().clear());
+    telemetry::reset();
+    diagnostics::clear();
+    tenant::forget_opened();
+    Config::set_for_thread(None);
+    host::local::grant(None);
+    connection::close_all();
+    connection::shared(connection::DEFAULT_DATABASE).expect("open default");
+}
+
+#[given(expr = "the variable {string} is {string}")]
+fn variable(world: &mut SqlWorld, name: String, value: String) {
+    world.variables.insert(name, value);
+    let config = Config::from_lookup(|name| world.variables.get(name).cloned());
+    Config::set_for_thread(Some(config));
+    // Connections apply parts of the configuration when they open.
+    connection::close_all();
+}
+
+#[given("the database is empty")]
+fn database_empty(_world: &mut SqlWorld) {
+    let tables = connection::with_shared(connection::DEFAULT_DATABASE, schema::list_tables_on)
+        .expect("list tables");
+    // Dropped on the connection itself, so the drops are not counted as calls.
+    connection::with_shared(connection::DEFAULT_DATABASE, |conn| {
+        for name in &tables {
+            let drop = format!("DROP TABLE \"{}\"", name.replace('"', "\"\""));
+            conn.execute(&drop, &[])
+                .map_err(|e| error::map_err(e, "execute", Some(&drop)))?;
+        }
+        Ok(())
+    })
+    .expect("drop tables");
+}
+
+#[given(expr = "I have a table {string} with columns {string}")]
+fn table(world: &mut SqlWorld, name: String, columns: String) {
+    world.execute(&format!("CREATE TABLE {name} ({columns})"), &[]);
+    assert!(world.error.is_none(), "{:?}", world.error);
+}
+
+#[given(regex = r#"^I execute "(.*)" with params (\[.*\])$"#)]
+#[when(regex = r#"^I execute "(.*)" with params (\[.*\])$"#)]
+fn execute(world: &mut SqlWorld, sql: String, json: String) {
+    world.execute(&sql, &params(&json));
+}
+
+// Spin's host waits out locks for a while itself; without that wait every
+// lock conflict reaches the adapter's retry.
+#[given("SQLite does not wait for locks on this instance")]
+fn no_busy_wait(world: &mut SqlWorld) {
+    world.execute("PRAGMA busy_timeout = 0", &[]);
+    assert!(world.error.is_none(), "{:?}", world.error);
+}
+
+#[when(
+    regex = r#"^I execute "(.*)" with params (\[.*\]) while instance "(.*)" commits after (\d+) milliseconds$"#
+)]
+fn execute_while_committing(
+    world: &mut SqlWorld,
+    sql: String,
+    json: String,
+    name: String,
+    ms: u64,
+) {
+    let committed = world.instance(&name).start(move || {
+        thread::sleep(Duration::from_millis(ms));
+        in_transaction(|tx| tx.commit())
+    });
+    retry::take_retries();
+    world.execute(&sql, &params(&json));
+    world.retries = retry::take_retries();
+    let committed = committed.recv().expect("instance step panicked");
+    world.executions.push(committed);
+}
+
+#[then("the execution should have been retried")]
+fn retried(world: &mut SqlWorld) {
+    assert!(world.retries > 0);
+}
+
+#[then("the execution should not have been retried")]
+fn not_retried(world: &mut SqlWorld) {
+    assert_eq!(world.retries, 0);
+}
+
+#[when(expr = "I query {string}")]
+fn query(world: &mut SqlWorld, sql: String) {
+    world.query(&sql, &[]);
+}
+
+#[when(regex = r#"^I query "(.*)" with params (\[.*\])$"#)]
+fn query_with_params(world: &mut SqlWorld, sql: String, json: String) {
+    world.query(&sql, &params(&json));
+}
+
+#[when(regex = r#"^I query columnar "(.*)" with params (\[.*\])$"#)]
+fn query_columnar(world: &mut SqlWorld, sql: String, json: String) {
+    let result = Database::default().run_query_columnar(&sql, &params(&json), &[]);
+    world.columnar = world.record(result);
+}
+
+/// Columns written as `"id" as int64 not nullable and "name" as text nullable`.
+#[then(regex = r"^the columns should be (.*)$")]
+fn columns(world: &mut SqlWorld, expected: String) {
+    let result = world.columnar.as_ref().expect("no columnar result");
+    let actual: Vec<String> = result
+        .columns
+        .iter()
+        .map(|c| {
+            let ty = match c.declared_type {
+                None => "unknown",
+                Some(wit_sql::SqlType::Boolean) => "boolean",
+                Some(wit_sql::SqlType::Int32) => "int32",
+                Some(wit_sql::SqlType::Int64) => "int64",
+                Some(wit_sql::SqlType::Float32) => "float32",
+                Some(wit_sql::SqlType::Float64) => "float64",
+                Some(wit_sql::SqlType::Text) => "text",
+                Some(wit_sql::SqlType::Bytes) => "bytes",
+                Some(wit_sql::SqlType::Timestamp) => "timestamp",
+                Some(wit_sql::SqlType::Uuid) => "uuid",
+            };
+            let nullable = if c.nullable {
+                "nullable"
+            } else {
+                "not nullable"
+            };
+            format!("\"{}\" as {ty} {nullable}", c.name)
+        })
+        .collect();
+    assert_eq!(actual.join(" and "), expected);
+}
+
+#[then(regex = r"^columnar row (\d+) should be (\[.*\])$")]
+fn columnar_row(world: &mut SqlWorld, row: usize, json: String) {
+    let result = world.columnar.as_ref().expect("no columnar result");
+    assert_eq!(result.rows.get(row), Some(&params(&json)));
+}
+
+#[when(regex = r#"^I execute named "(.*)" with params (\{.*\})$"#)]
+fn execute_named(world: &mut SqlWorld, sql: String, json: String) {
+    let affected = Database::default().run_execute_named(&sql, &named_params(&json));
+    world.record_affected(affected);
+}
+
+#[when(regex = r#"^I query named "(.*)" with params (\{.*\})$"#)]
+fn query_named(world: &mut SqlWorld, sql: String, json: String) {
+    let result = Database::default().run_query_named(&sql, &named_params(&json));
+    world.record_result(result);
+}
+
+#[then(regex = "^the (?:query|execution) should succeed$")]
+fn succeeded(world: &mut SqlWorld) {
+    assert!(world.error.is_none(), "{:?}", world.error);
+}
+
+#[then(regex = r#"^the (?:query|execution|open) should fail with error "(.*)"$"#)]
+fn failed(world: &mut SqlWorld, kind: String) {
+    let e = world.error.as_ref().expect("the call succeeded");
+    assert_eq!(error::kind(e), kind, "{e:?}");
+}
+
+#[then(regex = r#"^the execution should fail with error "(.*)" and code (\d+)$"#)]
+fn failed_with_code(world: &mut SqlWorld, kind: String, code: i32) {
+    failed(world, kind);
+    let mut e = world.error.clone().expect("the call succeeded");
+    let info = error::info_mut(&mut e).expect("error info");
+    assert_eq!(info.code, code, "{info:?}");
+}
+
+#[then(expr = "the violated constraint should be {string} on {string}")]
+fn violated_constraint(world: &mut SqlWorld, expected: String, target: String) {
+    let Some(wit_sql::SqlError::ConstraintViolation(details)) = &world.error else {
+        panic!("expected constraint-violation, got {:?}", world.error);
+    };
+    let kind = match details.kind {
+        wit_sql::ConstraintKind::Unique => "unique",
+        wit_sql::ConstraintKind::ForeignKey => "foreign-key",
+        wit_sql::ConstraintKind::NotNull => "not-null",
+        wit_sql::ConstraintKind::Check => "check",
+    };
+    let table = details.table.as_deref().unwrap_or_default();
+    let on: Vec<String> = details
+        .columns
+        .iter()
+        .map(|c| format!("{table}.{c}"))
+        .collect();
+    assert_eq!(
+        (kind, on.join(", ")),
+        (expected.as_str(), target),
+        "{details:?}"
+    );
+}
+
+#[then(expr = "the error message should contain {string}")]
+fn message_contains(world: &mut SqlWorld, text: String) {
+    let mut e = world.error.clone().expect("the call succeeded");
+    let info = error::info_mut(&mut e).expect("error info");
+    assert!(info.message.contains(&text), "{:?}", info.message);
+}
+
+#[then(expr = "the error message should start with {string}")]
+fn message_starts_with(world: &mut SqlWorld, text: String) {
+    let mut e = world.error.clone().expect("the call succeeded");
+    let info = error::info_mut(&mut e).expect("error info");
+    assert!(info.message.starts_with(&text), "{:?}", info.message);
+}
+
+#[then(expr = "{int} row(s) should be affected")]
+fn affected(world: &mut SqlWorld, rows: u64) {
+    assert_eq!(world.affected, Some(rows));
+}
+
+#[then(expr = "I should get {int} row(s)")]
+#[then(expr = "the result should have {int} row(s)")]
+fn row_count(world: &mut SqlWorld, rows: usize) {
+    assert_eq!(world.result.as_ref().map(|r| r.rows.len()), Some(rows));
+}
+
+#[then(expr = "row {int} column {string} should be {string}")]
+fn text_value(world: &mut SqlWorld, row: usize, column: String, expected: String) {
+    assert_eq!(
+        world.value(row, &column),
+        &wit_sql::SqlValue::Text(expected)
+    );
+}
+
+#[then(expr = "row {int} column {string} should be {int}")]
+fn int_value(world: &mut SqlWorld, row: usize, column: String, expected: i64) {
+    assert_eq!(
+        world.value(row, &column),
+        &wit_sql::SqlValue::Int64(expected)
+    );
+}
+
+#[given(regex = "^the component is granted databases (.*)$")]
+fn granted(_world: &mut SqlWorld, labels: String) {
+    let labels: Vec<&str> = labels.split('"').skip(1).step_by(2).collect();
+    host::local::grant(Some(&labels));
+}
+
+#[given("the database connection is unavailable")]
+fn unavailable(_world: &mut SqlWorld) {
+    host::local::grant(Some(&[]));
+    connection::close_all();
+}
+
+#[when(expr = "I open the database {string}")]
+fn open_database(world: &mut SqlWorld, label: String) {
+    if let Some(db) = world.record(Database::connect(label)) {
+        world.connection = Some(db.label);
+    }
+}
+
+#[given(expr = "tenant {string} has a {string} table containing {string}")]
+fn tenant_table(_world: &mut SqlWorld, tenant: String, table: String, body: String) {
+    let db = Database::connect_tenant("default", &tenant).expect("open tenant");
+    db.run_execute(&format!("DROP TABLE IF EXISTS {table}"), &[])
+        .expect("drop table");
+    db.run_execute(
+        &format!("CREATE TABLE {table} (id INTEGER PRIMARY KEY, body TEXT)"),
+        &[],
+    )
+    .expect("create table");
+    db.run_execute(
+        &format!("INSERT INTO {table} (body) VALUES (?)"),
+        &[wit_sql::SqlValue::Text(body)],
+    )
+    .expect("insert row");
+}
+
+#[given(expr = "I open tenant {string} of database {string}")]
+#[when(expr = "I open tenant {string} of database {string}")]
+fn open_tenant(world: &mut SqlWorld, tenant: String, name: String) {
+    match Database::connect_tenant(&name, &tenant) {
+        Ok(db) => {
+            world.connection = Some(db.label);
+            world.error = None;
+        }
+        Err(e) => world.error = Some(e),
+    }
+}
+
+#[given(expr = "I execute {string} on the connection")]
+#[when(expr = "I execute {string} on the connection")]
+fn execute_on_connection(world: &mut SqlWorld, sql: String) {
+    let affected = world.connection().run_execute(&sql, &[]);
+    world.record_affected(affected);
+}
+
+#[when(expr = "I query {string} on the connection")]
+fn query_on_connection(world: &mut SqlWorld, sql: String) {
+    match world.connection().run_query(&sql, &[], &[]) {
+        Ok(result) => {
+            world.result = Some(result);
+            world.error = None;
+        }
+        Err(e) => world.error = Some(e),
+    }
+}
+
+#[then(expr = "I should get {int} row(s) with column {string} equal to {string}")]
+fn rows_with_text(world: &mut SqlWorld, rows: usize, column: String, expected: String) {
+    row_count(world, rows);
+    for row in 0..rows {
+        text_value(world, row, column.clone(), expected.clone());
+    }
+}
+
+#[then(expr = "no row should contain {string}")]
+fn no_row_contains(world: &mut SqlWorld, text: String) {
+    let result = world.result.as_ref().expect("no query result");
+    let found = result
+        .rows
+        .iter()
+        .flat_map(|row| &row.columns)
+        .any(|(_, value)| matches!(value, wit_sql::SqlValue::Text(t) if t.contains(&text)));
+    assert!(!found, "a row contains {text:?}");
+}
+
+#[when(expr = "I prepare {string}")]
+fn prepare(world: &mut SqlWorld, sql: String) {
+    let statement = world.record(Statement::prepare(connection::DEFAULT_DATABASE, &sql));
+    STATEMENT.with(|open| *open.borrow_mut() = statement);
+}
+
+#[when(regex = r"^I bind (\[.*\]) and execute the statement$")]
+fn bind_and_execute(world: &mut SqlWorld, json: String) {
+    let executed = with_statement(params(&json), |s| s.execute());
+    if let Some(rows) = world.record(executed) {
+        world.executed.push(rows);
+    }
+}
+
+#[when(regex = r"^I bind (\[.*\]) and query the statement$")]
+fn bind_and_query(world: &mut SqlWorld, json: String) {
+    world.record_result(with_statement(params(&json), |s| s.query()));
+}
+
+#[then(expr = "each execution should affect {int} row(s)")]
+fn each_affected(world: &mut SqlWorld, rows: u64) {
+    assert!(!world.executed.is_empty());
+    assert!(
+        world.executed.iter().all(|&n| n == rows),
+        "{:?}",
+        world.executed
+    );
+}
+
+#[when("I list the tables")]
+fn list_tables(world: &mut SqlWorld) {
+    world.tables = world.record(Database::default().list_tables());
+}
+
+#[then("the table list should be empty")]
+fn no_tables(world: &mut SqlWorld) {
+    assert_eq!(world.tables.as_deref(), Some(&[][..]));
+}
+
+#[when(expr = "I describe table {string}")]
+fn describe_table(world: &mut SqlWorld, name: String) {
+    world.table = world.record(Database::default().describe_table(name));
+}
+
+#[then(expr = "column {string} should have type {string} and primary key position {int}")]
+fn column_type(world: &mut SqlWorld, name: String, declared_type: String, position: u32) {
+    let column = world.column(&name);
+    assert_eq!(
+        (column.declared_type.as_str(), column.primary_key),
+        (declared_type.as_str(), position)
+    );
+}
+
+#[then(expr = "column {string} should be not null")]
+fn column_not_null(world: &mut SqlWorld, name: String) {
+    assert!(world.column(&name).not_null);
+}
+
+#[then(expr = "column {string} should have default {string}")]
+fn column_default(world: &mut SqlWorld, name: String, default: String) {
+    assert_eq!(world.column(&name).default_value, Some(default));
+}
+
+#[then(
+    regex = r#"^the table should have a foreign key from "(.*)" to "(.*)"\."(.*)" on delete "(.*)"$"#
+)]
+fn foreign_key(world: &mut SqlWorld, from: String, table: String, to: String, on_delete: String) {
+    let info = world.table.as_ref().expect("no table description");
+    let found = info.foreign_keys.iter().any(|k| {
+        k.columns == [from.as_str()]
+            && k.referenced_table == table
+            && k.referenced_columns == [to.as_str()]
+            && k.on_delete == on_delete
+    });
+    assert!(found, "{:?}", info.foreign_keys);
+}
+
+#[when(expr = "I list the indexes of {string}")]
+fn list_indexes(world: &mut SqlWorld, table: String) {
+    let indexes = world.record(Database::default().list_indexes(table));
+    world.indexes = indexes.unwrap_or_default();
+}
+
+#[then(expr = "index {string} should be explicit, partial and cover {string}")]
+fn explicit_partial_index(world: &mut SqlWorld, name: String, column: String) {
+    let index = world
+        .indexes
+        .iter()
+        .find(|i| i.name == name)
+        .unwrap_or_else(|| panic!("no index {name:?} in {:?}", world.indexes));
+    assert_eq!(index.origin, wit_sql::IndexOrigin::Explicit);
+    assert!(index.partial);
+    assert_eq!(index.columns, [column]);
+}
+
+#[then(expr = "there should be a unique-constraint index covering {string}")]
+fn unique_constraint_index(world: &mut SqlWorld, column: String) {
+    let found = world.indexes.iter().any(|i| {
+        i.origin == wit_sql::IndexOrigin::UniqueConstraint
+            && i.unique
+            && i.columns == [column.as_str()]
+    });
+    assert!(found, "{:?}", world.indexes);
+}
+
+#[when(regex = r#"^I explain "(.*)" with params (\[.*\])$"#)]
+fn explain(world: &mut SqlWorld, sql: String, json: String) {
+    world.plan = world.record(Database::default().run_explain(&sql, &params(&json)));
+}
+
+#[then(expr = "the plan should have a full scan of {string}")]
+fn plan_scans(world: &mut SqlWorld, table: String) {
+    let plan = world.plan.as_ref().expect("no plan");
+    assert!(
+        plan.iter().any(|n| n.full_scan.as_deref() == Some(&table)),
+        "{plan:?}"
+    );
+}
+
+#[then("the plan should have no full scans")]
+fn plan_without_scans(world: &mut SqlWorld) {
+    let plan = world.plan.as_ref().expect("no plan");
+    assert!(plan.iter().all(|n| n.full_scan.is_none()), "{plan:?}");
+}
+
+#[then(expr = "a plan node detail should contain {string}")]
+fn plan_detail(world: &mut SqlWorld, text: String) {
+    let plan = world.plan.as_ref().expect("no plan");
+    assert!(plan.iter().any(|n| n.detail.contains(&text)), "{plan:?}");
+}
+
+#[then(expr = "a warning {string} should be logged for table {string}")]
+fn warning_logged(_world: &mut SqlWorld, message: String, table: String) {
+    LOGGED.with(|logged| {
+        let logged = logged.borrow();
+        let found = logged.iter().any(|e| {
+            e.level == tracing::Level::WARN
+                && e.fields.0.get("message") == Some(&message)
+                && e.fields.0.get("table") == Some(&table)
+        });
+        assert!(found, "{logged:?}");
+    });
+}
+
+/// Whether an `sql` span closed with all of `expected` among its fields.
+fn span_recorded(name: &str, expected: &[(&str, &str)]) {
+    SPANS.with(|spans| {
+        let spans = spans.borrow();
+        let found = spans.iter().any(|(span, fields)| {
+            *span == name
+                && expected
+                    .iter()
+                    .all(|(k, v)| fields.0.get(*k).map(String::as_str) == Some(*v))
+        });
+        assert!(found, "no {name:?} span with {expected:?} in {spans:?}");
+    });
+}
+
+#[then(
+    regex = r#"^an? "(.*)" span should be recorded with op "(.*)", fingerprint "(.*)", (\d+) params? and (\d+) rows?$"#
+)]
+fn span_with_rows(
+    _world: &mut SqlWorld,
+    name: String,
+    op: String,
+    fingerprint: String,
+    params: String,
+    rows: String,
+) {
+    let expected = [
+        ("op", op.as_str()),
+        ("fingerprint", fingerprint.as_str()),
+        ("params", params.as_str()),
+        ("rows", rows.as_str()),
+    ];
+    span_recorded(&name, &expected);
+}
+
+#[then(regex = r#"^an? "(.*)" span should be recorded with fingerprint "(.*)" and error "(.*)"$"#)]
+fn span_with_error(_world: &mut SqlWorld, name: String, fingerprint: String, error: String) {
+    let expected = [
+        ("fingerprint", fingerprint.as_str()),
+        ("error", error.as_str()),
+    ];
+    span_recorded(&name, &expected);
+}
+
+#[when("I read the metrics")]
+fn read_metrics(world: &mut SqlWorld) {
+    world.metrics = Some(telemetry::snapshot());
+}
+
+#[then(expr = "operation {string} should have {int} call(s) and {int} error(s)")]
+fn operation_calls(world: &mut SqlWorld, op: String, calls: u64, errors: u64) {
+    let metrics = world.call_metrics(false, &op);
+    assert_eq!(
+        (metrics.calls, metrics.errors),
+        (calls, errors),
+        "{metrics:?}"
+    );
+}
+
+#[then(expr = "operation {string} should have {int} call(s) with {int} row(s)")]
+fn operation_rows(world: &mut SqlWorld, op: String, calls: u64, rows: u64) {
+    let metrics = world.call_metrics(false, &op);
+    assert_eq!((metrics.calls, metrics.rows), (calls, rows), "{metrics:?}");
+}
+
+#[then(expr = "statement {string} should have {int} call(s)")]
+fn statement_calls(world: &mut SqlWorld, fingerprint: String, calls: u64) {
+    assert_eq!(world.call_metrics(true, &fingerprint).calls, calls);
+}
+
+#[then("the latency buckets of every operation should add up to its calls")]
+fn buckets_add_up(world: &mut SqlWorld) {
+    let metrics = world.metrics.as_ref().expect("metrics not read");
+    assert!(!metrics.operations.is_empty());
+    for op in &metrics.operations {
+        assert_eq!(op.latency_buckets.len(), metrics.bucket_bounds_us.len() + 1);
+        assert_eq!(op.latency_buckets.iter().sum::<u64>(), op.calls, "{op:?}");
+    }
+}
+
+#[when("I read the diagnostics")]
+fn read_diagnostics(world: &mut SqlWorld) {
+    world.diagnostics = Some(diagnostics::report());
+}
+
+#[then(expr = "the newest slow query should have fingerprint {string}")]
+fn slow_query_fingerprint(world: &mut SqlWorld, fingerprint: String) {
+    assert_eq!(world.newest_slow_query().fingerprint, fingerprint);
+}
+
+#[then(expr = "the newest slow query should have operation {string}")]
+fn slow_query_operation(world: &mut SqlWorld, operation: String) {
+    assert_eq!(world.newest_slow_query().operation, operation);
+}
+
+#[then(regex = r"^its params should be (\[.*\])$")]
+fn slow_query_params(world: &mut SqlWorld, json: String) {
+    let expected: Vec<String> = serde_json::from_str(&json).expect("params are JSON");
+    assert_eq!(world.newest_slow_query().params, expected);
+}
+
+#[then(expr = "its plan should contain {string}")]
+fn slow_query_plan(world: &mut SqlWorld, text: String) {
+    let plan = &world.newest_slow_query().plan;
+    assert!(plan.iter().any(|detail| detail.contains(&text)), "{plan:?}");
+}
+
+#[then(expr = "the diagnostics should not contain {string}")]
+fn diagnostics_omit(world: &mut SqlWorld, text: String) {
+    let report = world.diagnostics.as_ref().expect("diagnostics not read");
+    assert!(!format!("{report:?}").contains(&text), "{report:?}");
+}
+
+#[then(expr = "the diagnostics should list {int} slow queries")]
+fn slow_query_count(world: &mut SqlWorld, count: usize) {
+    let report = world.diagnostics.as_ref().expect("diagnostics not read");
+    assert_eq!(report.slow_queries.len(), count, "{report:?}");
+}
+
+#[then("the slow query threshold should be unset")]
+fn slow_threshold_unset(world: &mut SqlWorld) {
+    let report = world.diagnostics.as_ref().expect("diagnostics not read");
+    assert_eq!(report.slow_threshold_ms, None);
+}
+
+#[given(expr = "I insert {int} rows into {string}")]
+fn insert_rows(world: &mut SqlWorld, rows: u32, table: String) {
+    for _ in 0..rows {
+        world.execute(&format!("INSERT INTO {table} DEFAULT VALUES"), &[]);
+        assert!(world.error.is_none(), "{:?}", world.error);
+    }
+}
+
+#[when(regex = r#"^I execute batch "(.*)" with rows (\[.*\])( in transaction)?$"#)]
+fn execute_batch(world: &mut SqlWorld, sql: String, json: String, in_tx: String) {
+    let rows = param_rows(&json);
+    let counts = if in_tx.is_empty() {
+        Database::default().run_execute_batch(&sql, &rows)
+    } else {
+        in_transaction(|tx| tx.execute_batch(sql, rows))
+    };
+    world.counts = world.record(counts);
+}
+
+#[when(expr = "I execute script {string}")]
+fn execute_script(world: &mut SqlWorld, sql: String) {
+    world.counts = world.record(Database::default().run_execute_script(&sql));
+}
+
+#[then(regex = r"^the batch should succeed with counts (\[.*\])$")]
+fn batch_counts(world: &mut SqlWorld, json: String) {
+    succeeded(world);
+    let expected: Vec<u64> = serde_json::from_str(&json).expect("counts are JSON");
+    assert_eq!(world.counts.as_deref(), Some(expected.as_slice()));
+}
+
+#[when("I begin a transaction")]
+fn begin_transaction(world: &mut SqlWorld) {
+    world.record(begin("deferred", false, None));
+}
+
+#[when("I begin a read-only transaction")]
+fn begin_read_only_transaction(world: &mut SqlWorld) {
+    world.record(begin("deferred", true, None));
+}
+
+#[when(regex = "^I begin a transaction with options read-only (true|false)$")]
+fn begin_transaction_with_options(world: &mut SqlWorld, read_only: String) {
+    world.record(begin("deferred", read_only == "true", None));
+}
+
+#[when(expr = "I execute {string} in transaction")]
+fn execute_in_transaction(world: &mut SqlWorld, sql: String) {
+    world.record_affected(in_transaction(|tx| tx.execute(sql, vec![])));
+}
+
+#[when("I commit the transaction")]
+fn commit(world: &mut SqlWorld) {
+    world.record(in_transaction(|tx| tx.commit()));
+}
+
+#[when("I rollback the transaction")]
+fn rollback(world: &mut SqlWorld) {
+    world.record(in_transaction(|tx| tx.rollback()));
+}
+
+#[when(expr = "I create savepoint {string} in transaction")]
+fn savepoint(world: &mut SqlWorld, name: String) {
+    world.record(in_transaction(|tx| tx.savepoint(name)));
+}
+
+#[when(expr = "I release savepoint {string} in transaction")]
+fn release(world: &mut SqlWorld, name: String) {
+    world.record(in_transaction(|tx| tx.release(name)));
+}
+
+#[when(expr = "I rollback to savepoint {string} in transaction")]
+fn rollback_to(world: &mut SqlWorld, name: String) {
+    world.record(in_transaction(|tx| tx.rollback_to(name)));
+}
+
+// Read-only mode refuses PRAGMA from the guest, so this asks the connection.
+#[then("the connection should still have query_only on")]
+fn query_only_on(_world: &mut SqlWorld) {
+    let query_only = connection::with_shared(connection::DEFAULT_DATABASE, |conn| {
+        conn.execute("PRAGMA query_only", &[])
+            .map_err(|e| error::map_err(e, "query", None))
+    })
+    .expect("read query_only");
+    assert_eq!(query_only.rows[0].get::<i64>(0), Some(1));
+}
+
+#[then("the transaction should succeed")]
+fn transaction_succeeded(world: &mut SqlWorld) {
+    succeeded(world);
+}
+
+#[then("the transaction should still be active")]
+fn transaction_active(_world: &mut SqlWorld) {
+    let used = in_transaction(|tx| tx.query("SELECT 1".to_string(), vec![]));
+    assert!(used.is_ok(), "{used:?}");
+}
+
+#[when(
+    regex = r#"^I query "(.*)" with options (max-rows|max-bytes|max-duration-ms) (\d+)( in the transaction)?$"#
+)]
+fn query_with_options(
+    world: &mut SqlWorld,
+    sql: String,
+    option: String,
+    limit: u32,
+    in_tx: String,
+) {
+    let mut options = wit_sql::CallOptions {
+        max_duration_ms: None,
+        max_rows: None,
+        max_bytes: None,
+    };
+    match option.as_str() {
+        "max-rows" => options.max_rows = Some(limit),
+        "max-bytes" => options.max_bytes = Some(u64::from(limit)),
+        _ => options.max_duration_ms = Some(limit),
+    }
+    let result = if in_tx.is_empty() {
+        Database::default().run_query_with(&sql, &[], &[], &options)
+    } else {
+        in_transaction(|tx| tx.query_with(sql, vec![], vec![], options))
+    };
+    world.record_result(result);
+}
+
+#[then(
+    regex = r#"^the execution should fail with error "limit-exceeded" and limit kind "(.*)" of (\d+)$"#
+)]
+fn limit_exceeded(world: &mut SqlWorld, kind: String, limit: u64) {
+    let Some(wit_sql::SqlError::LimitExceeded(details)) = &world.error else {
+        panic!("expected limit-exceeded, got {:?}", world.error);
+    };
+    let expected = match kind.as_str() {
+        "rows" => wit_sql::LimitKind::Rows,
+        "bytes" => wit_sql::LimitKind::Bytes,
+        _ => wit_sql::LimitKind::Duration,
+    };
+    assert_eq!((details.kind, details.limit), (expected, limit));
+}
+
+#[when(regex = r#"^I stream "(.*)" with params (\[.*\])(?: keyed by "(.*)")?$"#)]
+fn stream(world: &mut SqlWorld, sql: String, json: String, key: String) {
+    let key = (!key.is_empty()).then_some(key);
+    let cursor = RowCursor::new(connection::DEFAULT_DATABASE, &sql, &params(&json), key);
+    let cursor = world.record(cursor);
+    STREAM.with(|open| *open.borrow_mut() = cursor);
+}
+
+#[then(regex = r"^fetching batches of (\d+) should return (.*) and then 0 rows$")]
+fn stream_batches(world: &mut SqlWorld, max: u32, sizes: String) {
+    let expected: Vec<usize> = sizes
+        .split(", ")
+        .map(|n| n.parse().expect("batch size"))
+        .collect();
+    let fetched: Vec<Option<usize>> = (0..=expected.len()).map(|_| world.fetch(max)).collect();
+    let expected: Vec<Option<usize>> = expected.into_iter().chain([0]).map(Some).collect();
+    assert_eq!(fetched, expected, "{:?}", world.error);
+}
+
+#[then(expr = "the streamed {string} values should be {int} through {int} in order")]
+fn streamed_values(world: &mut SqlWorld, column: String, first: i64, last: i64) {
+    let values: Vec<&wit_sql::SqlValue> = world
+        .streamed
+        .iter()
+        .map(|row| {
+            row.columns
+                .iter()
+                .find_map(|(name, value)| (name == &column).then_some(value))
+                .unwrap_or_else(|| panic!("no column {column:?}"))
+        })
+        .collect();
+    let expected: Vec<wit_sql::SqlValue> = (first..=last).map(wit_sql::SqlValue::Int64).collect();
+    assert_eq!(values, expected.iter().collect::<Vec<_>>());
+}
+
+#[when(expr = "I fetch a batch of {int} rows")]
+fn fetch_batch(world: &mut SqlWorld, max: u32) {
+    world.fetch(max);
+}
+
+#[given(expr = "instance {string} begins a transaction with mode {string}")]
+fn instance_begins(world: &mut SqlWorld, name: String, mode: String) {
+    let begun = world.instance(&name).run(move || begin(&mode, false, None));
+    assert!(begun.is_ok(), "{begun:?}");
+}
+
+#[given(
+    expr = "instance {string} begins a transaction with mode {string} and busy timeout {int} ms"
+)]
+fn instance_begins_with_timeout(world: &mut SqlWorld, name: String, mode: String, ms: u32) {
+    let begun = world
+        .instance(&name)
+        .run(move || begin(&mode, false, Some(ms)));
+    assert!(begun.is_ok(), "{begun:?}");
+}
+
+#[when(expr = "instance {string} starts to begin a transaction with mode {string}")]
+fn instance_starts_to_begin(world: &mut SqlWorld, name: String, mode: String) {
+    let begun = world
+        .instance(&name)
+        .start(move || begin(&mode, false, None));
+    world.pending.insert(name, begun);
+}
+
+#[then(expr = "instance {string} should still be waiting to begin")]
+fn instance_waiting(world: &mut SqlWorld, name: String) {
+    let pending = world.pending.get(&name).expect("no pending BEGIN");
+    assert!(matches!(pending.try_recv(), Err(mpsc::TryRecvError::Empty)));
+}
+
+#[then(expr = "instance {string} should then begin its transaction")]
+fn instance_began(world: &mut SqlWorld, name: String) {
+    let pending = world.pending.remove(&name).expect("no pending BEGIN");
+    let begun = pending.recv().expect("instance step panicked");
+    assert!(begun.is_ok(), "{begun:?}");
+}
+
+#[when(expr = "instance {string} queries {string} in transaction")]
+fn instance_queries(world: &mut SqlWorld, name: String, sql: String) {
+    let queried = world
+        .instance(&name)
+        .run(move || in_transaction(|tx| tx.query(sql, vec![]).map(drop)));
+    world.executions.push(queried);
+}
+
+#[when(expr = "instance {string} executes {string} in transaction")]
+fn instance_executes(world: &mut SqlWorld, name: String, sql: String) {
+    let (executed, retries) = world.instance(&name).run(move || {
+        retry::take_retries();
+        let executed = in_transaction(|tx| tx.execute(sql, vec![]).map(drop));
+        (executed, retry::take_retries())
+    });
+    world.executions.push(executed);
+    world.retries = retries;
+}
+
+#[when(expr = "instance {string} commits the transaction")]
+fn instance_commits(world: &mut SqlWorld, name: String) {
+    let committed = world
+        .instance(&name)
+        .run(|| in_transaction(|tx| tx.commit()));
+    world.executions.push(committed);
+}
+
+#[then(expr = "one of the executions should fail with error code {int}")]
+fn one_execution_failed(world: &mut SqlWorld, code: i32) {
+    let failures: Vec<_> = world
+        .executions
+        .iter()
+        .filter_map(|r| r.as_ref().err())
+        .collect();
+    assert_eq!(failures.len(), 1, "{failures:?}");
+    let mut failure = failures[0].clone();
+    let info = error::info_mut(&mut failure).expect("error info");
+    assert_eq!(info.code, code, "{info:?}");
+}
+
+#[then("every execution should succeed")]
+fn every_execution_succeeded(world: &mut SqlWorld) {
+    assert!(
+        world.executions.iter().all(Result::is_ok),
+        "{:?}",
+        world.executions
+    );
+}
+
+#[test]
+fn sql_operations() {
+    let _recording =
+        tracing::subscriber::set_default(tracing_subscriber::registry().with(Recorder));
+    futures::executor::block_on(
+        SqlWorld::cucumber()
+            .max_concurrent_scenarios(1)
+            .fail_on_skipped()
+            .with_default_cli()
+            .filter_run_and_exit(
+                concat!(
+                    env!("CARGO_MANIFEST_DIR"),
+                    "/features/sql-operations.feature"
+                ),
+                |_, _, scenario| scenario.tags.iter().any(|t| t == "native"),
+            ),
+    );
+}
@@ -0,0 +1,221 @@
+//! Adapter configuration read once per instance from Spin component variables.
+//!
+//! Variables are declared by the hosting app under
+//! `[component.<name>.variables]`; anything unset or unparseable falls back to
+//! the default.
+//!
+//! | variable                       | values            | default |
+//! |--------------------------------|-------------------|---------|
+//! | `sql_uuid_storage`             | `text` \| `blob`  | `text`  |
+//! | `sql_retry_max_attempts`       | integer >= 1      | `3`     |
+//! | `sql_retry_initial_backoff_ms` | integer           | `5`     |
+//! | `sql_retry_max_backoff_ms`     | integer           | `100`   |
+//! | `sql_warn_scan_rows`           | integer           | unset   |
+//! | `sql_slow_query_ms`            | integer           | unset   |
+//! | `sql_slow_query_capacity`      | integer           | `32`    |
+//! | `sql_read_only`                | `true` \| `false` | `false` |
+//! | `sql_allowed_statements`       | `;`-separated SQL | unset   |
+//! | `sql_tenant_required`          | `true` \| `false` | `false` |
+//!
+//! Setting `sql_retry_max_attempts` to `1` disables busy retries.
+//! `sql_warn_scan_rows` is a development aid: when set, statements are
+//! explained first and full scans of tables with at least that many rows are
+//! logged. `sql_slow_query_ms` turns on the slow query log returned by
+//! `diagnostics`, which keeps the last `sql_slow_query_capacity` entries.
+//! `sql_read_only` and `sql_allowed_statements` restrict what guest SQL may
+//! run; see [`crate::policy`]. `sql_tenant_required` refuses databases not
+//! opened through `connection.open-tenant`; see [`crate::tenant`].
+
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::diagnostics::SlowQueryLog;
+use crate::policy::StatementPolicy;
+use crate::retry::RetryPolicy;
+
+/// How `sql-value::uuid` parameters are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum UuidStorage {
+    /// Canonical lowercase hyphenated text.
+    #[default]
+    Text,
+    /// The 16 raw bytes, which halves index size for UUID keys.
+    Blob,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Config {
+    pub uuid_storage: UuidStorage,
+    pub retry: RetryPolicy,
+    pub warn_scan_rows: Option<u64>,
+    pub slow_queries: SlowQueryLog,
+    pub statements: StatementPolicy,
+    pub tenant_required: bool,
+}
+
+impl Config {
+    pub(crate) fn get() -> &'static Config {
+        #[cfg(test)]
+        if let Some(config) = THREAD_CONFIG.get() {
+            return config;
+        }
+        static CONFIG: OnceLock<Config> = OnceLock::new();
+        CONFIG.get_or_init(|| Config::from_lookup(variable))
+    }
+
+    /// Use `config` on this thread, or the defaults again with `None`. Tests
+    /// have no Spin variables, so this is how they configure the adapter.
+    #[cfg(test)]
+    pub(crate) fn set_for_thread(config: Option<Config>) {
+        THREAD_CONFIG.set(config.map(|c| &*Box::leak(Box::new(c))));
+    }
+
+    pub(crate) fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Config {
+        let mut config = Config::default();
+        if let Some(v) = lookup("sql_uuid_storage") {
+            match v.trim().to_ascii_lowercase().as_str() {
+                "text" => config.uuid_storage = UuidStorage::Text,
+                "blob" => config.uuid_storage = UuidStorage::Blob,
+                other => tracing::warn!(value = other, "ignoring unknown sql_uuid_storage"),
+            }
+        }
+        if let Some(n) = parse(&lookup, "sql_retry_max_attempts") {
+            config.retry.max_attempts = u32::max(n, 1);
+        }
+        if let Some(ms) = parse(&lookup, "sql_retry_initial_backoff_ms") {
+            config.retry.initial_backoff = Duration::from_millis(ms);
+        }
+        if let Some(ms) = parse(&lookup, "sql_retry_max_backoff_ms") {
+            config.retry.max_backoff = Duration::from_millis(ms);
+        }
+        config.warn_scan_rows = parse(&lookup, "sql_warn_scan_rows");
+        config.slow_queries.threshold =
+            parse(&lookup, "sql_slow_query_ms").map(Duration::from_millis);
+        if let Some(n) = parse(&lookup, "sql_slow_query_capacity") {
+            config.slow_queries.capacity = n;
+        }
+        if let Some(read_only) = parse(&lookup, "sql_read_only") {
+            config.statements.read_only = read_only;
+        }
+        config.statements.allowed =
+            lookup("sql_allowed_statements").map(|v| StatementPolicy::allow_list(&v));
+        if let Some(required) = parse(&lookup, "sql_tenant_required") {
+            config.tenant_required = required;
+        }
+        config
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    static THREAD_CONFIG: std::cell::Cell<Option<&'static Config>> =
+        const { std::cell::Cell::new(None) };
+}
+
+fn parse<T: FromStr>(lookup: &impl Fn(&str) -> Option<String>, name: &str) -> Option<T> {
+    let v = lookup(name)?;
+    let parsed = v.trim().parse().ok();
+    if parsed.is_none() {
+        tracing::warn!(variable = name, value = v, "ignoring unparseable variable");
+    }
+    parsed
+}
+
+#[cfg(target_arch = "wasm32")]
+fn variable(name: &str) -> Option<String> {
+    spin_sdk::variables::get(name).ok()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn variable(_name: &str) -> Option<String> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_lookup_parses_and_defaults() {
+        assert_eq!(Config::from_lookup(|_| None), Config::default());
+
+        let c = Config::from_lookup(|k| (k == "sql_uuid_storage").then(|| " BLOB ".to_string()));
+        assert_eq!(c.uuid_storage, UuidStorage::Blob);
+
+        let c = Config::from_lookup(|_| Some("binary".to_string()));
+        assert_eq!(c.uuid_storage, UuidStorage::Text);
+        assert_eq!(c.retry, RetryPolicy::default());
+    }
+
+    #[test]
+    fn from_lookup_reads_retry_policy() {
+        let c = Config::from_lookup(|k| match k {
+            "sql_retry_max_attempts" => Some("5".to_string()),
+            "sql_retry_initial_backoff_ms" => Some(" 20 ".to_string()),
+            "sql_retry_max_backoff_ms" => Some("250".to_string()),
+            _ => None,
+        });
+        assert_eq!(
+            c.retry,
+            RetryPolicy {
+                max_attempts: 5,
+                initial_backoff: Duration::from_millis(20),
+                max_backoff: Duration::from_millis(250),
+            }
+        );
+
+        let c = Config::from_lookup(|k| (k == "sql_retry_max_attempts").then(|| "0".to_string()));
+        assert_eq!(c.retry.max_attempts, 1);
+    }
+
+    #[test]
+    fn scan_warnings_are_off_unless_set() {
+        assert_eq!(Config::from_lookup(|_| None).warn_scan_rows, None);
+        let c = Config::from_lookup(|k| (k == "sql_warn_scan_rows").then(|| "10000".to_string()));
+        assert_eq!(c.warn_scan_rows, Some(10_000));
+    }
+
+    #[test]
+    fn from_lookup_reads_slow_query_log() {
+        assert_eq!(
+            Config::from_lookup(|_| None).slow_queries,
+            SlowQueryLog::default()
+        );
+        let c = Config::from_lookup(|k| match k {
+            "sql_slow_query_ms" => Some("250".to_string()),
+            "sql_slow_query_capacity" => Some("8".to_string()),
+            _ => None,
+        });
+        assert_eq!(
+            c.slow_queries,
+            SlowQueryLog {
+                threshold: Some(Duration::from_millis(250)),
+                capacity: 8,
+            }
+        );
+    }
+
+    #[test]
+    fn from_lookup_reads_statement_policy() {
+        assert_eq!(
+            Config::from_lookup(|_| None).statements,
+            StatementPolicy::default()
+        );
+        let c = Config::from_lookup(|k| match k {
+            "sql_read_only" => Some(" true ".to_string()),
+            "sql_allowed_statements" => Some("SELECT * FROM t WHERE id = 1; SELECT 2".to_string()),
+            _ => None,
+        });
+        assert!(c.statements.read_only);
+        let allowed: Vec<_> = c.statements.allowed.unwrap().into_iter().collect();
+        assert_eq!(allowed, vec!["SELECT * FROM t WHERE id = ?", "SELECT ?"]);
+    }
+
+    #[test]
+    fn tenant_scoping_is_optional() {
+        assert!(!Config::from_lookup(|_| None).tenant_required);
+        let c = Config::from_lookup(|k| (k == "sql_tenant_required").then(|| "true".to_string()));
+        assert!(c.tenant_required);
+    }
+}
@@ -0,0 +1,277 @@
+//! Instance-scoped connection cache.
+//!
+//! A component instance is single-threaded, so one connection per database
+//! label is kept in a thread-local and shared by top-level calls, `connection`
+//! resources and transactions. This keeps connection-scoped state (PRAGMAs,
+//! temp tables) alive between calls. A label's entry is dropped whenever an
+//! operation on it reports `connection-failed`, and the next call reopens.
+//!
+//! Prepared statement texts are cached per label alongside the connection
+//! and dropped with it.
+//!
+//! Calls made outside an explicit transaction retry busy/locked failures per
+//! the configured [`RetryPolicy`](crate::retry::RetryPolicy).
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::rc::Rc;
+
+use crate::config::Config;
+use crate::error::map_err;
+use crate::host::Connection;
+use crate::{tenant, wit_sql};
+
+/// Label used by the top-level `sql` functions.
+pub(crate) const DEFAULT_DATABASE: &str = "default";
+
+thread_local! {
+    static CONNECTIONS: Cache<Connection> = const { Cache::new() };
+    static STATEMENTS: Cache<StatementCache> = const { Cache::new() };
+    static IN_TRANSACTION: RefCell<BTreeSet<String>> = const { RefCell::new(BTreeSet::new()) };
+}
+
+/// Lazily-opened, invalidatable connections keyed by database label.
+pub(crate) struct Cache<C> {
+    slots: RefCell<BTreeMap<String, Rc<C>>>,
+}
+
+impl<C> Cache<C> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            slots: RefCell::new(BTreeMap::new()),
+        }
+    }
+
+    pub(crate) fn get_or_open<E>(
+        &self,
+        label: &str,
+        open: impl FnOnce() -> Result<C, E>,
+    ) -> Result<Rc<C>, E> {
+        let mut slots = self.slots.borrow_mut();
+        if let Some(conn) = slots.get(label) {
+            return Ok(Rc::clone(conn));
+        }
+        let conn = Rc::new(open()?);
+        slots.insert(label.to_string(), Rc::clone(&conn));
+        Ok(conn)
+    }
+
+    pub(crate) fn invalidate(&self, label: &str) {
+        self.slots.borrow_mut().remove(label);
+    }
+
+    #[cfg(test)]
+    fn clear(&self) {
+        self.slots.borrow_mut().clear();
+    }
+}
+
+/// The cached connection for `label`, opening it on first use. Labels the
+/// component has not been granted fail with `connection-failed`, as do
+/// labels that are not tenant-scoped when scoping is required.
+pub(crate) fn shared(label: &str) -> Result<Rc<Connection>, wit_sql::SqlError> {
+    if Config::get().tenant_required {
+        tenant::require_scoped(label)?;
+    }
+    CONNECTIONS.with(|cache| {
+        cache.get_or_open(label, || {
+            let conn = Connection::open(label).map_err(|e| map_err(e, "connection", None))?;
+            if Config::get().statements.read_only {
+                conn.execute("PRAGMA query_only = ON", &[])
+                    .map_err(|e| map_err(e, "connection", None))?;
+            }
+            Ok(conn)
+        })
+    })
+}
+
+/// Statement texts already validated on a connection, keyed by SQL.
+#[derive(Default)]
+pub(crate) struct StatementCache {
+    prepared: RefCell<BTreeMap<String, Rc<str>>>,
+}
+
+/// Entries kept per connection before an arbitrary one is evicted.
+pub(crate) const STATEMENT_CACHE_CAPACITY: usize = 64;
+
+impl StatementCache {
+    pub(crate) fn get_or_prepare<E>(
+        &self,
+        sql: &str,
+        prepare: impl FnOnce() -> Result<(), E>,
+    ) -> Result<Rc<str>, E> {
+        if let Some(text) = self.prepared.borrow().get(sql) {
+            return Ok(Rc::clone(text));
+        }
+        prepare()?;
+        let mut prepared = self.prepared.borrow_mut();
+        if prepared.len() >= STATEMENT_CACHE_CAPACITY {
+            prepared.pop_first();
+        }
+        let text: Rc<str> = Rc::from(sql);
+        prepared.insert(sql.to_string(), Rc::clone(&text));
+        Ok(text)
+    }
+}
+
+/// The shared text of `sql` on `label`, running `prepare` on first use.
+pub(crate) fn prepared(
+    label: &str,
+    sql: &str,
+    prepare: impl FnOnce() -> Result<(), wit_sql::SqlError>,
+) -> Result<Rc<str>, wit_sql::SqlError> {
+    let cache = STATEMENTS.with(|cache| {
+        cache.get_or_open(label, || {
+            Ok::<_, wit_sql::SqlError>(StatementCache::default())
+        })
+    })?;
+    cache.get_or_prepare(sql, prepare)
+}
+
+/// Run `f` on the cached connection for `label`, retrying lock contention
+/// unless an explicit transaction is open on it.
+pub(crate) fn with_shared<T>(
+    label: &str,
+    mut f: impl FnMut(&Connection) -> Result<T, wit_sql::SqlError>,
+) -> Result<T, wit_sql::SqlError> {
+    let conn = shared(label)?;
+    let result = if in_transaction(label) {
+        f(&conn)
+    } else {
+        Config::get().retry.run(|| f(&conn), std::thread::sleep)
+    };
+    observe(label, result)
+}
+
+/// Record whether an explicit transaction is open on `label`.
+pub(crate) fn set_in_transaction(label: &str, open: bool) {
+    IN_TRANSACTION.with(|labels| {
+        let mut labels = labels.borrow_mut();
+        if open {
+            labels.insert(label.to_string());
+        } else {
+            labels.remove(label);
+        }
+    });
+}
+
+pub(crate) fn in_transaction(label: &str) -> bool {
+    IN_TRANSACTION.with(|labels| labels.borrow().contains(label))
+}
+
+/// Close every cached connection, as a fresh instance would start. Tests use
+/// this to reopen connections under a changed configuration.
+#[cfg(test)]
+pub(crate) fn close_all() {
+    CONNECTIONS.with(Cache::clear);
+    STATEMENTS.with(Cache::clear);
+    IN_TRANSACTION.with(|labels| labels.borrow_mut().clear());
+}
+
+/// Drop the cached connection for `label` if `result` shows it has gone bad.
+pub(crate) fn observe<T>(
+    label: &str,
+    result: Result<T, wit_sql::SqlError>,
+) -> Result<T, wit_sql::SqlError> {
+    if matches!(result, Err(wit_sql::SqlError::ConnectionFailed(_))) {
+        CONNECTIONS.with(|cache| cache.invalidate(label));
+        STATEMENTS.with(|cache| cache.invalidate(label));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn cache_opens_lazily_and_reopens_after_invalidate() {
+        let opens = Cell::new(0);
+        let open = || -> Result<u32, ()> {
+            opens.set(opens.get() + 1);
+            Ok(opens.get())
+        };
+        let cache = Cache::new();
+        assert_eq!(opens.get(), 0);
+
+        let a = cache.get_or_open("default", open).unwrap();
+        let b = cache.get_or_open("default", open).unwrap();
+        assert_eq!((*a, *b, opens.get()), (1, 1, 1));
+
+        cache.invalidate("default");
+        let c = cache.get_or_open("default", open).unwrap();
+        assert_eq!((*c, opens.get()), (2, 2));
+        // Handles taken before invalidation stay usable by their holders.
+        assert_eq!(*a, 1);
+    }
+
+    #[test]
+    fn cache_keeps_one_connection_per_label() {
+        let cache = Cache::new();
+        let default = cache
+            .get_or_open("default", || Ok::<_, ()>("default-db"))
+            .unwrap();
+        let audit = cache
+            .get_or_open("audit", || Ok::<_, ()>("audit-db"))
+            .unwrap();
+        assert_eq!((*default, *audit), ("default-db", "audit-db"));
+
+        cache.invalidate("audit");
+        let default = cache
+            .get_or_open("default", || Ok::<_, ()>("reopened"))
+            .unwrap();
+        assert_eq!(*default, "default-db");
+    }
+
+    #[test]
+    fn statement_cache_prepares_once_per_sql() {
+        let cache = StatementCache::default();
+        let prepares = Cell::new(0);
+        let prepare = || -> Result<(), ()> {
+            prepares.set(prepares.get() + 1);
+            Ok(())
+        };
+        let a = cache.get_or_prepare("SELECT 1", prepare).unwrap();
+        let b = cache.get_or_prepare("SELECT 1", prepare).unwrap();
+        assert!(Rc::ptr_eq(&a, &b));
+        cache.get_or_prepare("SELECT 2", prepare).unwrap();
+        assert_eq!(prepares.get(), 2);
+
+        assert!(cache.get_or_prepare("SELEC", || Err("syntax")).is_err());
+        assert!(cache.get_or_prepare("SELEC", prepare).is_ok());
+        assert_eq!(prepares.get(), 3);
+    }
+
+    #[test]
+    fn statement_cache_is_bounded() {
+        let cache = StatementCache::default();
+        for i in 0..STATEMENT_CACHE_CAPACITY + 10 {
+            cache
+                .get_or_prepare(&format!("SELECT {i}"), || Ok::<_, ()>(()))
+                .unwrap();
+        }
+        assert_eq!(cache.prepared.borrow().len(), STATEMENT_CACHE_CAPACITY);
+    }
+
+    #[test]
+    fn in_transaction_is_tracked_per_label() {
+        assert!(!in_transaction("default"));
+        set_in_transaction("default", true);
+        assert!(in_transaction("default"));
+        assert!(!in_transaction("audit"));
+        set_in_transaction("default", false);
+        assert!(!in_transaction("default"));
+    }
+
+    #[test]
+    fn cache_does_not_store_failed_opens() {
+        let cache: Cache<u32> = Cache::new();
+        assert!(
+            cache
+                .get_or_open("audit", || Err("no such database"))
+                .is_err()
+        );
+        assert_eq!(*cache.get_or_open("audit", || Ok::<_, &str>(7)).unwrap(), 7);
+    }
+}
@@ -0,0 +1,235 @@
+//! The `row-cursor` resource behind `query-stream`.
+//!
+//! Spin's host API returns a statement's rows all at once, so streaming is
+//! done by re-running the query one page at a time: keyset paging
+//! (`WHERE key > last ORDER BY key`) when the caller names a key column,
+//! LIMIT/OFFSET otherwise, each page running the query as a
+//! [`subquery`](crate::subquery). Only the current page is ever materialized.
+
+use std::cell::RefCell;
+
+use spin_sdk::sqlite::Value as SpinValue;
+
+use crate::config::Config;
+use crate::error::{self, codes, map_err};
+use crate::params::normalize_positional;
+use crate::values::{resolve_hints, row_to_wit, values_from};
+use crate::{connection, policy, subquery, telemetry, wit_sql};
+
+pub(crate) struct RowCursor {
+    label: String,
+    sql: String,
+    params: Vec<wit_sql::SqlValue>,
+    values: Vec<SpinValue>,
+    key: Option<String>,
+    paging: RefCell<Paging>,
+}
+
+#[derive(Default)]
+struct Paging {
+    offset: u64,
+    last_key: Option<SpinValue>,
+    done: bool,
+}
+
+impl RowCursor {
+    pub(crate) fn new(
+        label: &str,
+        sql: &str,
+        params: &[wit_sql::SqlValue],
+        key: Option<String>,
+    ) -> Result<Self, wit_sql::SqlError> {
+        policy::check(sql)?;
+        if !policy::is_select(sql) {
+            return Err(wit_sql::SqlError::InvalidParameter(error::adapter_error(
+                codes::MISUSE,
+                "query-stream takes a SELECT, VALUES or WITH ... SELECT".to_string(),
+                Some(sql),
+            )));
+        }
+        Ok(Self {
+            label: label.to_string(),
+            sql: normalize_positional(sql).into_owned(),
+            values: values_from(params, Config::get().uuid_storage)?,
+            params: params.to_vec(),
+            key,
+            paging: RefCell::default(),
+        })
+    }
+
+    fn page(&self, max: u32) -> Result<Vec<wit_sql::SqlRow>, wit_sql::SqlError> {
+        let mut paging = self.paging.borrow_mut();
+        if paging.done || max == 0 {
+            return Ok(Vec::new());
+        }
+        let tail = page_tail(
+            self.key.as_deref(),
+            paging.last_key.is_some(),
+            max,
+            paging.offset,
+        );
+        let mut values = self.values.clone();
+        values.extend(paging.last_key.clone());
+        let qr = connection::with_shared(&self.label, |conn| {
+            subquery::execute(conn, &self.sql, &tail, &values)
+                .map_err(|e| map_err(e, "query", Some(&self.sql)))
+        })?;
+
+        if let (Some(key), Some(last)) = (&self.key, qr.rows.last()) {
+            let at = qr.columns.iter().position(|c| c == key).ok_or_else(|| {
+                wit_sql::SqlError::InvalidParameter(error::adapter_error(
+                    codes::MISUSE,
+                    format!("stream key column {key:?} is not in the result"),
+                    Some(&self.sql),
+                ))
+            })?;
+            paging.last_key = last.values.get(at).cloned();
+        }
+        paging.offset += qr.rows.len() as u64;
+        paging.done = qr.rows.len() < max as usize;
+        if !paging.done && matches!(paging.last_key, Some(SpinValue::Null)) {
+            // NULLs sort first and no row is `> NULL`: the next page would
+            // come back empty with rows still unread.
+            paging.done = true;
+            return Err(wit_sql::SqlError::InvalidParameter(error::adapter_error(
+                codes::MISUSE,
+                format!(
+                    "stream key column {:?} is NULL in a full page; key on a NOT NULL column",
+                    self.key.as_deref().unwrap_or_default()
+                ),
+                Some(&self.sql),
+            )));
+        }
+
+        let hints = resolve_hints(&qr.columns, &[]);
+        qr.rows
+            .iter()
+            .map(|row| {
+                row_to_wit(&qr.columns, &hints, &row.values)
+                    .map_err(|e| error::with_statement(e, &self.sql))
+            })
+            .collect()
+    }
+}
+
+impl wit_sql::GuestRowCursor for RowCursor {
+    fn next_batch(&self, max: u32) -> Result<Vec<wit_sql::SqlRow>, wit_sql::SqlError> {
+        telemetry::observe("query-stream", &self.label, &self.sql, &self.params, || {
+            self.page(max)
+        })
+    }
+}
+
+/// What follows the wrapped query to fetch its next page. With a `key`,
+/// `after` says whether a previous key value is bound as the last parameter.
+fn page_tail(key: Option<&str>, after: bool, max: u32, offset: u64) -> String {
+    match key {
+        Some(key) => {
+            let key = quote_ident(key);
+            let filter = if after {
+                format!(" WHERE {key} > ?")
+            } else {
+                String::new()
+            };
+            format!("{filter} ORDER BY {key} LIMIT {max}")
+        }
+        None => format!(" LIMIT {max} OFFSET {offset}"),
+    }
+}
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offset_pages_follow_the_query() {
+        assert_eq!(page_tail(None, false, 100, 200), " LIMIT 100 OFFSET 200");
+    }
+
+    #[test]
+    fn keyset_pages_continue_after_the_last_key() {
+        assert_eq!(
+            page_tail(Some("id"), false, 50, 0),
+            " ORDER BY \"id\" LIMIT 50"
+        );
+        assert_eq!(
+            page_tail(Some("id"), true, 50, 50),
+            " WHERE \"id\" > ? ORDER BY \"id\" LIMIT 50"
+        );
+    }
+
+    fn table(rows: &str) {
+        connection::with_shared("stream", |conn| {
+            conn.execute("DROP TABLE IF EXISTS t", &[]).unwrap();
+            conn.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, k INTEGER)", &[])
+                .unwrap();
+            conn.execute(&format!("INSERT INTO t (id, k) VALUES {rows}"), &[])
+                .unwrap();
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn queries_ending_in_a_line_comment_page() {
+        table("(1, 1), (2, 2), (3, 3)");
+        let sql = "SELECT id FROM t -- every row";
+        for key in [None, Some("id".to_string())] {
+            let cursor = RowCursor::new("stream", sql, &[], key).unwrap();
+            let pages: Vec<usize> = (0..3).map(|_| cursor.page(2).unwrap().len()).collect();
+            assert_eq!(pages, [2, 1, 0]);
+        }
+    }
+
+    #[test]
+    fn duplicate_columns_keep_their_names() {
+        table("(1, 1), (2, 2), (3, 3)");
+        let sql = "SELECT a.id, b.id FROM t a JOIN t b ON b.id = a.id + 1";
+        for key in [None, Some("id".to_string())] {
+            let cursor = RowCursor::new("stream", sql, &[], key).unwrap();
+            let rows = cursor.page(10).unwrap();
+            assert_eq!(rows.len(), 2);
+            let names: Vec<&str> = rows[0]
+                .columns
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect();
+            assert_eq!(names, ["id", "id"]);
+        }
+    }
+
+    #[test]
+    fn statements_that_are_not_queries_are_refused_up_front() {
+        for sql in ["DELETE FROM t", "UPDATE t SET k = 0 RETURNING id"] {
+            assert!(
+                matches!(
+                    RowCursor::new("stream", sql, &[], None),
+                    Err(wit_sql::SqlError::InvalidParameter(_))
+                ),
+                "{sql}"
+            );
+        }
+    }
+
+    #[test]
+    fn a_null_key_in_a_full_page_fails() {
+        table("(1, NULL), (2, NULL), (3, 3)");
+        let cursor =
+            RowCursor::new("stream", "SELECT id, k FROM t", &[], Some("k".into())).unwrap();
+        assert!(matches!(
+            cursor.page(2),
+            Err(wit_sql::SqlError::InvalidParameter(_))
+        ));
+        assert!(cursor.page(2).unwrap().is_empty());
+    }
+
+    #[test]
+    fn key_columns_are_quoted() {
+        assert_eq!(quote_ident("user id"), "\"user id\"");
+        assert_eq!(quote_ident("a\"b"), "\"a\"\"b\"");
+    }
+}
@@ -0,0 +1,148 @@
+//! The slow query log behind `diagnostics`.
+//!
+//! Calls at or above `sql_slow_query_ms` are kept in a ring buffer holding
+//! the last `sql_slow_query_capacity` of them. Entries carry the statement
+//! fingerprint instead of its text and only the type and size of each
+//! parameter, so no literal values are retained. The plan summary comes from
+//! an `EXPLAIN QUERY PLAN` issued after the slow call, which costs a round
+//! trip only for calls already over the threshold.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::values::values_from;
+use crate::{connection, plan, wit_sql};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SlowQueryLog {
+    /// Calls this slow or slower are logged; `None` disables the log.
+    pub threshold: Option<Duration>,
+    pub capacity: usize,
+}
+
+impl Default for SlowQueryLog {
+    fn default() -> Self {
+        Self {
+            threshold: None,
+            capacity: 32,
+        }
+    }
+}
+
+thread_local! {
+    static SLOW_QUERIES: RefCell<VecDeque<wit_sql::SlowQuery>> = RefCell::default();
+}
+
+impl SlowQueryLog {
+    pub(crate) fn is_slow(&self, elapsed: Duration) -> bool {
+        self.threshold.is_some_and(|t| elapsed >= t)
+    }
+
+    /// Append `entry`, evicting the oldest entries beyond the capacity.
+    pub(crate) fn push(&self, entry: wit_sql::SlowQuery) {
+        SLOW_QUERIES.with(|log| push_bounded(&mut log.borrow_mut(), entry, self.capacity));
+    }
+}
+
+fn push_bounded<T>(ring: &mut VecDeque<T>, entry: T, capacity: usize) {
+    if capacity == 0 {
+        return;
+    }
+    while ring.len() >= capacity {
+        ring.pop_front();
+    }
+    ring.push_back(entry);
+}
+
+pub(crate) fn report() -> wit_sql::DiagnosticsReport {
+    let log = Config::get().slow_queries;
+    wit_sql::DiagnosticsReport {
+        slow_threshold_ms: log.threshold.map(|t| t.as_millis() as u64),
+        capacity: log.capacity as u32,
+        slow_queries: SLOW_QUERIES.with(|log| log.borrow().iter().rev().cloned().collect()),
+    }
+}
+
+/// Empty this thread's slow query log, as a new instance would start.
+#[cfg(test)]
+pub(crate) fn clear() {
+    SLOW_QUERIES.take();
+}
+
+/// A parameter's type, plus its length for text and bytes, never its value.
+pub(crate) fn redact(value: &wit_sql::SqlValue) -> String {
+    match value {
+        wit_sql::SqlValue::Null => "null".to_string(),
+        wit_sql::SqlValue::Boolean(_) => "boolean".to_string(),
+        wit_sql::SqlValue::Int32(_) => "int32".to_string(),
+        wit_sql::SqlValue::Int64(_) => "int64".to_string(),
+        wit_sql::SqlValue::Float32(_) => "float32".to_string(),
+        wit_sql::SqlValue::Float64(_) => "float64".to_string(),
+        wit_sql::SqlValue::Text(s) => format!("text({})", s.len()),
+        wit_sql::SqlValue::Bytes(b) => format!("bytes({})", b.len()),
+        wit_sql::SqlValue::Timestamp(_) => "timestamp".to_string(),
+        wit_sql::SqlValue::Uuid(_) => "uuid".to_string(),
+    }
+}
+
+/// The plan details of a single statement `op`; empty for transaction
+/// control and scripts, or when the statement cannot be explained.
+pub(crate) fn plan_summary(
+    op: &str,
+    label: &str,
+    sql: &str,
+    params: &[wit_sql::SqlValue],
+) -> Vec<String> {
+    if !matches!(op, "query" | "execute" | "execute-batch" | "query-stream") {
+        return Vec::new();
+    }
+    let Ok(values) = values_from(params, Config::get().uuid_storage) else {
+        return Vec::new();
+    };
+    connection::with_shared(label, |conn| plan::explain_on(conn, sql, &values))
+        .map(|nodes| nodes.into_iter().map(|n| n.detail).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_keeps_the_most_recent_entries() {
+        let mut ring = VecDeque::new();
+        for i in 0..5 {
+            push_bounded(&mut ring, i, 3);
+        }
+        assert_eq!(ring, [2, 3, 4]);
+        push_bounded(&mut ring, 5, 0);
+        assert_eq!(ring, [2, 3, 4]);
+    }
+
+    #[test]
+    fn redaction_keeps_types_and_sizes_only() {
+        let redacted: Vec<_> = [
+            wit_sql::SqlValue::Text("hunter2".to_string()),
+            wit_sql::SqlValue::Bytes(vec![0; 16]),
+            wit_sql::SqlValue::Int64(42),
+            wit_sql::SqlValue::Null,
+        ]
+        .iter()
+        .map(redact)
+        .collect();
+        assert_eq!(redacted, vec!["text(7)", "bytes(16)", "int64", "null"]);
+    }
+
+    #[test]
+    fn threshold_is_inclusive_and_optional() {
+        let log = SlowQueryLog {
+            threshold: Some(Duration::from_millis(50)),
+            capacity: 8,
+        };
+        assert!(log.is_slow(Duration::from_millis(50)));
+        assert!(!log.is_slow(Duration::from_millis(49)));
+        assert!(!SlowQueryLog::default().is_slow(Duration::MAX));
+    }
+}
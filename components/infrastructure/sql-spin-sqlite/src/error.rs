@@ -0,0 +1,352 @@
+//! Translation of Spin SQLite failures into the structured `sql-error` variant.
+//!
+//! Spin's host interface only forwards SQLite's message text (`error::io`), not
+//! the result code, so codes are recovered from the well-known messages that
+//! `sqlite3_errstr()` and the constraint checks produce.
+
+use spin_sdk::sqlite::Error as SpinError;
+
+use crate::wit_sql;
+
+/// SQLite primary and extended result codes (https://sqlite.org/rescode.html).
+pub(crate) mod codes {
+    pub const ERROR: i32 = 1;
+    pub const PERM: i32 = 3;
+    pub const BUSY: i32 = 5;
+    pub const LOCKED: i32 = 6;
+    pub const NOMEM: i32 = 7;
+    pub const READONLY: i32 = 8;
+    pub const INTERRUPT: i32 = 9;
+    pub const IOERR: i32 = 10;
+    pub const CORRUPT: i32 = 11;
+    pub const FULL: i32 = 13;
+    pub const CANTOPEN: i32 = 14;
+    pub const SCHEMA: i32 = 17;
+    pub const TOOBIG: i32 = 18;
+    pub const CONSTRAINT: i32 = 19;
+    pub const MISMATCH: i32 = 20;
+    pub const MISUSE: i32 = 21;
+    pub const AUTH: i32 = 23;
+    pub const RANGE: i32 = 25;
+    pub const NOTADB: i32 = 26;
+
+    pub const CONSTRAINT_CHECK: i32 = CONSTRAINT | (1 << 8);
+    pub const CONSTRAINT_FOREIGNKEY: i32 = CONSTRAINT | (3 << 8);
+    pub const CONSTRAINT_NOTNULL: i32 = CONSTRAINT | (5 << 8);
+    pub const CONSTRAINT_UNIQUE: i32 = CONSTRAINT | (8 << 8);
+    pub const CONSTRAINT_DATATYPE: i32 = CONSTRAINT | (12 << 8);
+}
+
+pub(crate) fn map_err(
+    e: SpinError,
+    kind: &'static str,
+    statement: Option<&str>,
+) -> wit_sql::SqlError {
+    let (code, extended_code) = classify(&e);
+    let info = wit_sql::ErrorInfo {
+        code,
+        extended_code,
+        message: describe(&e),
+        statement: statement.map(str::to_string),
+        parameter_index: None,
+    };
+    if kind != "connection"
+        && let Some(details) = parse_constraint_violation(info.clone())
+    {
+        return wit_sql::SqlError::ConstraintViolation(details);
+    }
+    // A handle the host no longer recognises is a connection failure whatever
+    // the caller was doing; it tells the cache to reopen.
+    let kind = if matches!(e, SpinError::InvalidConnection) {
+        "connection"
+    } else {
+        kind
+    };
+    match kind {
+        "connection" => wit_sql::SqlError::ConnectionFailed(info),
+        "query" => wit_sql::SqlError::QueryFailed(info),
+        "transaction" => wit_sql::SqlError::TransactionFailed(info),
+        _ => wit_sql::SqlError::QueryFailed(info),
+    }
+}
+
+/// Attach the offending statement to an error raised outside `map_err`.
+pub(crate) fn with_statement(mut e: wit_sql::SqlError, statement: &str) -> wit_sql::SqlError {
+    if let Some(info) = info_mut(&mut e) {
+        info.statement = Some(statement.to_string());
+    }
+    e
+}
+
+/// Build an `error-info` for failures detected inside the adapter rather than
+/// reported by SQLite.
+pub(crate) fn adapter_error(
+    code: i32,
+    message: impl Into<String>,
+    statement: Option<&str>,
+) -> wit_sql::ErrorInfo {
+    wit_sql::ErrorInfo {
+        code,
+        extended_code: code,
+        message: message.into(),
+        statement: statement.map(str::to_string),
+        parameter_index: None,
+    }
+}
+
+/// The `error-info` carried by any variant, if it has one.
+pub(crate) fn info_mut(e: &mut wit_sql::SqlError) -> Option<&mut wit_sql::ErrorInfo> {
+    match e {
+        wit_sql::SqlError::ConnectionFailed(info)
+        | wit_sql::SqlError::QueryFailed(info)
+        | wit_sql::SqlError::TransactionFailed(info)
+        | wit_sql::SqlError::InvalidParameter(info) => Some(info),
+        wit_sql::SqlError::ConstraintViolation(details) => Some(&mut details.info),
+        wit_sql::SqlError::LimitExceeded(details) => Some(&mut details.info),
+        wit_sql::SqlError::NotFound => None,
+    }
+}
+
+/// The variant's WIT case name, for logs and metrics.
+pub(crate) fn kind(e: &wit_sql::SqlError) -> &'static str {
+    match e {
+        wit_sql::SqlError::ConnectionFailed(_) => "connection-failed",
+        wit_sql::SqlError::QueryFailed(_) => "query-failed",
+        wit_sql::SqlError::TransactionFailed(_) => "transaction-failed",
+        wit_sql::SqlError::ConstraintViolation(_) => "constraint-violation",
+        wit_sql::SqlError::InvalidParameter(_) => "invalid-parameter",
+        wit_sql::SqlError::NotFound => "not-found",
+        wit_sql::SqlError::LimitExceeded(_) => "limit-exceeded",
+    }
+}
+
+fn describe(e: &SpinError) -> String {
+    match e {
+        SpinError::NoSuchDatabase => "no such database".to_string(),
+        SpinError::AccessDenied => "access denied".to_string(),
+        SpinError::InvalidConnection => "invalid connection".to_string(),
+        SpinError::DatabaseFull => "database full".to_string(),
+        SpinError::Io(msg) => msg.clone(),
+    }
+}
+
+/// Returns `(primary, extended)` result codes for a host error.
+fn classify(e: &SpinError) -> (i32, i32) {
+    match e {
+        SpinError::NoSuchDatabase | SpinError::InvalidConnection => {
+            (codes::CANTOPEN, codes::CANTOPEN)
+        }
+        SpinError::AccessDenied => (codes::AUTH, codes::AUTH),
+        SpinError::DatabaseFull => (codes::FULL, codes::FULL),
+        SpinError::Io(msg) => classify_message(msg),
+    }
+}
+
+fn classify_message(msg: &str) -> (i32, i32) {
+    // Order matters: constraint messages first, then the longer
+    // "database table is locked" before "database is locked".
+    const MESSAGES: [(&str, i32, i32); 22] = [
+        (
+            "UNIQUE constraint failed",
+            codes::CONSTRAINT,
+            codes::CONSTRAINT_UNIQUE,
+        ),
+        (
+            "FOREIGN KEY constraint failed",
+            codes::CONSTRAINT,
+            codes::CONSTRAINT_FOREIGNKEY,
+        ),
+        (
+            "NOT NULL constraint failed",
+            codes::CONSTRAINT,
+            codes::CONSTRAINT_NOTNULL,
+        ),
+        (
+            "CHECK constraint failed",
+            codes::CONSTRAINT,
+            codes::CONSTRAINT_CHECK,
+        ),
+        (
+            "cannot store",
+            codes::CONSTRAINT,
+            codes::CONSTRAINT_DATATYPE,
+        ),
+        ("constraint failed", codes::CONSTRAINT, codes::CONSTRAINT),
+        ("database table is locked", codes::LOCKED, codes::LOCKED),
+        ("database is locked", codes::BUSY, codes::BUSY),
+        (
+            "attempt to write a readonly database",
+            codes::READONLY,
+            codes::READONLY,
+        ),
+        ("interrupted", codes::INTERRUPT, codes::INTERRUPT),
+        ("disk I/O error", codes::IOERR, codes::IOERR),
+        (
+            "database disk image is malformed",
+            codes::CORRUPT,
+            codes::CORRUPT,
+        ),
+        ("database or disk is full", codes::FULL, codes::FULL),
+        (
+            "unable to open database file",
+            codes::CANTOPEN,
+            codes::CANTOPEN,
+        ),
+        ("database schema has changed", codes::SCHEMA, codes::SCHEMA),
+        ("string or blob too big", codes::TOOBIG, codes::TOOBIG),
+        ("datatype mismatch", codes::MISMATCH, codes::MISMATCH),
+        ("out of memory", codes::NOMEM, codes::NOMEM),
+        ("not authorized", codes::AUTH, codes::AUTH),
+        ("access permission denied", codes::PERM, codes::PERM),
+        ("column index out of range", codes::RANGE, codes::RANGE),
+        ("file is not a database", codes::NOTADB, codes::NOTADB),
+    ];
+    MESSAGES
+        .iter()
+        .find(|(needle, _, _)| msg.contains(needle))
+        .map(|(_, code, extended)| (*code, *extended))
+        .unwrap_or((codes::ERROR, codes::ERROR))
+}
+
+/// Classify SQLite's constraint failure messages, e.g.
+/// `UNIQUE constraint failed: users.email` or `FOREIGN KEY constraint failed`.
+fn parse_constraint_violation(info: wit_sql::ErrorInfo) -> Option<wit_sql::ConstraintDetails> {
+    use wit_sql::ConstraintKind as K;
+
+    const PREFIXES: [(&str, K); 4] = [
+        ("UNIQUE constraint failed", K::Unique),
+        ("FOREIGN KEY constraint failed", K::ForeignKey),
+        ("NOT NULL constraint failed", K::NotNull),
+        ("CHECK constraint failed", K::Check),
+    ];
+    let msg = info.message.as_str();
+    let (kind, rest) = PREFIXES.iter().find_map(|(prefix, kind)| {
+        msg.find(prefix)
+            .map(|at| (*kind, &msg[at + prefix.len()..]))
+    })?;
+    let rest = rest.trim_start_matches(':').trim().to_string();
+
+    let mut details = wit_sql::ConstraintDetails {
+        kind,
+        table: None,
+        columns: Vec::new(),
+        constraint: None,
+        info,
+    };
+    match kind {
+        K::ForeignKey => {}
+        K::Check => details.constraint = (!rest.is_empty()).then_some(rest),
+        K::Unique | K::NotNull => {
+            if let Some(index) = rest.strip_prefix("index ") {
+                details.constraint = Some(index.trim_matches('\'').to_string());
+            } else {
+                for qualified in rest.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+                    match qualified.split_once('.') {
+                        Some((table, column)) => {
+                            details.table.get_or_insert_with(|| table.to_string());
+                            details.columns.push(column.to_string());
+                        }
+                        None => details.columns.push(qualified.to_string()),
+                    }
+                }
+            }
+        }
+    }
+    Some(details)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn constraint(msg: &str) -> wit_sql::ConstraintDetails {
+        match map_err(SpinError::Io(msg.into()), "query", None) {
+            wit_sql::SqlError::ConstraintViolation(d) => d,
+            other => panic!("expected constraint violation for {msg:?}, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn parse_constraint_violation_classifies_sqlite_messages() {
+        use wit_sql::ConstraintKind as K;
+
+        let d = constraint("UNIQUE constraint failed: users.email");
+        assert_eq!(d.kind, K::Unique);
+        assert_eq!(d.table.as_deref(), Some("users"));
+        assert_eq!(d.columns, vec!["email".to_string()]);
+        assert_eq!(d.constraint, None);
+        assert_eq!(d.info.extended_code, codes::CONSTRAINT_UNIQUE);
+
+        let d = constraint("UNIQUE constraint failed: members.org_id, members.user_id");
+        assert_eq!(d.table.as_deref(), Some("members"));
+        assert_eq!(d.columns, vec!["org_id".to_string(), "user_id".to_string()]);
+
+        let d = constraint("UNIQUE constraint failed: index 'idx_lower_email'");
+        assert_eq!(d.kind, K::Unique);
+        assert_eq!(d.constraint.as_deref(), Some("idx_lower_email"));
+        assert!(d.columns.is_empty());
+
+        let d = constraint("NOT NULL constraint failed: users.name");
+        assert_eq!(d.kind, K::NotNull);
+        assert_eq!(d.table.as_deref(), Some("users"));
+        assert_eq!(d.columns, vec!["name".to_string()]);
+        assert_eq!(d.info.extended_code, codes::CONSTRAINT_NOTNULL);
+
+        let d = constraint("CHECK constraint failed: balance >= 0");
+        assert_eq!(d.kind, K::Check);
+        assert_eq!(d.constraint.as_deref(), Some("balance >= 0"));
+
+        let d = constraint("FOREIGN KEY constraint failed");
+        assert_eq!(d.kind, K::ForeignKey);
+        assert_eq!(d.table, None);
+        assert_eq!(d.info.message, "FOREIGN KEY constraint failed");
+        assert_eq!(d.info.code, codes::CONSTRAINT);
+    }
+
+    #[test]
+    fn map_err_routes_kinds_with_codes() {
+        let e = map_err(
+            SpinError::Io("no such table: users".into()),
+            "query",
+            Some("SELECT * FROM users"),
+        );
+        let wit_sql::SqlError::QueryFailed(info) = e else {
+            panic!("expected query-failed");
+        };
+        assert_eq!(info.code, codes::ERROR);
+        assert_eq!(info.message, "no such table: users");
+        assert_eq!(info.statement.as_deref(), Some("SELECT * FROM users"));
+
+        let e = map_err(
+            SpinError::Io("database is locked".into()),
+            "transaction",
+            None,
+        );
+        assert!(
+            matches!(e, wit_sql::SqlError::TransactionFailed(info) if info.code == codes::BUSY)
+        );
+
+        let e = map_err(
+            SpinError::Io("database table is locked".into()),
+            "query",
+            None,
+        );
+        assert!(matches!(e, wit_sql::SqlError::QueryFailed(info) if info.code == codes::LOCKED));
+
+        let e = map_err(SpinError::NoSuchDatabase, "connection", None);
+        assert!(
+            matches!(e, wit_sql::SqlError::ConnectionFailed(info) if info.code == codes::CANTOPEN)
+        );
+
+        // Connection failures are never reclassified as constraint violations.
+        let e = map_err(
+            SpinError::Io("UNIQUE constraint failed: t.c".into()),
+            "connection",
+            None,
+        );
+        assert!(matches!(e, wit_sql::SqlError::ConnectionFailed(_)));
+
+        let e = map_err(SpinError::InvalidConnection, "query", Some("SELECT 1"));
+        assert!(matches!(e, wit_sql::SqlError::ConnectionFailed(_)));
+    }
+}
@@ -0,0 +1,125 @@
+//! The SQLite connection provided by the host.
+//!
+//! Components reach SQLite through Spin's `sqlite` import. Unit tests run
+//! natively, where there is no Spin host, so they get [`local::Connection`]
+//! instead: the same `open`/`execute` pair over a local SQLite, returning
+//! Spin's result and error types the way Spin's in-process host fills them.
+
+#[cfg(not(test))]
+pub(crate) use spin_sdk::sqlite::Connection;
+
+#[cfg(test)]
+pub(crate) use local::Connection;
+
+#[cfg(test)]
+pub(crate) mod local {
+    use std::cell::RefCell;
+    use std::collections::BTreeSet;
+    use std::path::PathBuf;
+
+    use spin_sdk::sqlite::{Error, QueryResult, RowResult, Value};
+
+    use rusqlite::types::ValueRef;
+
+    /// A connection to a database file in a per-thread scratch directory.
+    ///
+    /// A test thread plays one component instance. Threads with the same name
+    /// open the same files, which is how tests give several instances one
+    /// database.
+    pub(crate) struct Connection(rusqlite::Connection);
+
+    thread_local! {
+        static GRANTED: RefCell<Option<BTreeSet<String>>> = const { RefCell::new(None) };
+    }
+
+    /// Limit the labels this thread may open, as a component's
+    /// `sqlite_databases` does in Spin, or allow every label again with `None`.
+    pub(crate) fn grant(labels: Option<&[&str]>) {
+        let labels = labels.map(|labels| labels.iter().map(|l| l.to_string()).collect());
+        GRANTED.with(|granted| *granted.borrow_mut() = labels);
+    }
+
+    impl Connection {
+        pub(crate) fn open(label: &str) -> Result<Self, Error> {
+            let granted = GRANTED.with(|granted| {
+                granted
+                    .borrow()
+                    .as_ref()
+                    .is_none_or(|labels| labels.contains(label))
+            });
+            if !granted {
+                return Err(Error::AccessDenied);
+            }
+            let dir = scratch_dir();
+            std::fs::create_dir_all(&dir).map_err(|e| Error::Io(e.to_string()))?;
+            rusqlite::Connection::open(dir.join(format!("{label}.db")))
+                .map(Self)
+                .map_err(io)
+        }
+
+        pub(crate) fn execute(
+            &self,
+            query: &str,
+            parameters: &[Value],
+        ) -> Result<QueryResult, Error> {
+            let mut statement = self.0.prepare(query).map_err(io)?;
+            let columns: Vec<String> = statement
+                .column_names()
+                .into_iter()
+                .map(str::to_string)
+                .collect();
+            let mut rows = statement
+                .query(rusqlite::params_from_iter(parameters.iter().map(to_sql)))
+                .map_err(io)?;
+            let mut results = Vec::new();
+            while let Some(row) = rows.next().map_err(io)? {
+                let values = (0..columns.len())
+                    .map(|i| row.get_ref(i).map(from_sql))
+                    .collect::<Result<_, _>>()
+                    .map_err(io)?;
+                results.push(RowResult { values });
+            }
+            Ok(QueryResult {
+                columns,
+                rows: results,
+            })
+        }
+    }
+
+    fn scratch_dir() -> PathBuf {
+        let thread = std::thread::current();
+        let name = thread
+            .name()
+            .map_or_else(|| format!("{:?}", thread.id()), str::to_string)
+            .replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+        std::env::temp_dir()
+            .join("sql-spin-sqlite-tests")
+            .join(std::process::id().to_string())
+            .join(name)
+    }
+
+    /// Spin forwards SQLite failures as their message text.
+    fn io(e: rusqlite::Error) -> Error {
+        Error::Io(e.to_string())
+    }
+
+    fn to_sql(value: &Value) -> rusqlite::types::Value {
+        match value {
+            Value::Integer(i) => rusqlite::types::Value::Integer(*i),
+            Value::Real(r) => rusqlite::types::Value::Real(*r),
+            Value::Text(s) => rusqlite::types::Value::Text(s.clone()),
+            Value::Blob(b) => rusqlite::types::Value::Blob(b.clone()),
+            Value::Null => rusqlite::types::Value::Null,
+        }
+    }
+
+    fn from_sql(value: ValueRef<'_>) -> Value {
+        match value {
+            ValueRef::Null => Value::Null,
+            ValueRef::Integer(i) => Value::Integer(i),
+            ValueRef::Real(r) => Value::Real(r),
+            ValueRef::Text(s) => Value::Text(String::from_utf8_lossy(s).into_owned()),
+            ValueRef::Blob(b) => Value::Blob(b.to_vec()),
+        }
+    }
+}
@@ -0,0 +1,214 @@
+//! SQL tokens.
+//!
+//! The adapter reads guest SQL without parsing it: for statement keywords,
+//! placeholders, script boundaries and fingerprints. All of that goes through
+//! [`tokens`], which keeps quoted text, quoted identifiers and comments whole
+//! so nothing inside them is mistaken for SQL.
+
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Kind<'a> {
+    Space,
+    /// `-- ...` up to and including the newline, or `/* ... */`.
+    Comment,
+    /// A string or blob literal: `'it''s'`, `x'ff'`.
+    Literal,
+    /// `42`, `1.5e-3`, `.5`, `0x1F`.
+    Number,
+    /// `"name"`, `` `name` `` or `[name]`.
+    QuotedName,
+    /// A keyword or bare identifier; identifiers may contain `$` (`a$b`).
+    Word,
+    Placeholder(Placeholder<'a>),
+    /// Any other character, e.g. `(`, `;` or `,`.
+    Symbol,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Placeholder<'a> {
+    /// `?` or `?N`.
+    Question,
+    /// `$N`, holding the digits.
+    Dollar(&'a str),
+    /// `:name`, `@name` or `$name`, holding the name without its sigil.
+    Named(&'a str),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Token<'a> {
+    pub kind: Kind<'a>,
+    pub text: &'a str,
+    pub start: usize,
+}
+
+impl Token<'_> {
+    pub(crate) fn range(&self) -> Range<usize> {
+        self.start..self.start + self.text.len()
+    }
+
+    /// Whether this is the keyword or bare identifier `word`, in any case.
+    pub(crate) fn is(&self, word: &str) -> bool {
+        self.kind == Kind::Word && self.text.eq_ignore_ascii_case(word)
+    }
+}
+
+/// The tokens of `sql`, which together cover all of it. Unterminated quotes
+/// and comments run to the end.
+pub(crate) fn tokens(sql: &str) -> impl Iterator<Item = Token<'_>> {
+    let mut at = 0;
+    std::iter::from_fn(move || {
+        let (kind, end) = next(sql, at)?;
+        let token = Token {
+            kind,
+            text: &sql[at..end],
+            start: at,
+        };
+        at = end;
+        Some(token)
+    })
+}
+
+fn next(sql: &str, i: usize) -> Option<(Kind<'_>, usize)> {
+    let b = sql.as_bytes();
+    let c = *b.get(i)?;
+    let next = b.get(i + 1).copied().unwrap_or(0);
+    let run = |from: usize, f: fn(u8) -> bool| {
+        b[from..]
+            .iter()
+            .position(|c| !f(*c))
+            .map_or(b.len(), |n| from + n)
+    };
+    Some(match c {
+        c if c.is_ascii_whitespace() => (Kind::Space, run(i, |c| c.is_ascii_whitespace())),
+        b'-' if next == b'-' => {
+            let end = sql[i..].find('\n').map_or(b.len(), |n| i + n + 1);
+            (Kind::Comment, end)
+        }
+        b'/' if next == b'*' => {
+            let end = sql[i + 2..].find("*/").map_or(b.len(), |n| i + 2 + n + 2);
+            (Kind::Comment, end)
+        }
+        b'\'' => (Kind::Literal, quoted(b, i, b'\'')),
+        b'x' | b'X' if next == b'\'' => (Kind::Literal, quoted(b, i + 1, b'\'')),
+        b'"' | b'`' => (Kind::QuotedName, quoted(b, i, c)),
+        b'[' => (Kind::QuotedName, quoted(b, i, b']')),
+        c if c.is_ascii_digit() || (c == b'.' && next.is_ascii_digit()) => {
+            let mut end = i + 1;
+            while end < b.len() {
+                let c = b[end];
+                let exponent_sign = matches!(c, b'+' | b'-') && matches!(b[end - 1], b'e' | b'E');
+                if !(c.is_ascii_alphanumeric() || c == b'.' || exponent_sign) {
+                    break;
+                }
+                end += 1;
+            }
+            (Kind::Number, end)
+        }
+        b'?' => {
+            let end = run(i + 1, |c| c.is_ascii_digit());
+            (Kind::Placeholder(Placeholder::Question), end)
+        }
+        b'$' if next.is_ascii_digit() => {
+            let end = run(i + 1, |c| c.is_ascii_digit());
+            (
+                Kind::Placeholder(Placeholder::Dollar(&sql[i + 1..end])),
+                end,
+            )
+        }
+        b':' | b'@' | b'$' if next.is_ascii_alphabetic() || next == b'_' => {
+            let end = run(i + 1, |c| c.is_ascii_alphanumeric() || c == b'_');
+            (Kind::Placeholder(Placeholder::Named(&sql[i + 1..end])), end)
+        }
+        c if c.is_ascii_alphabetic() || c == b'_' => {
+            let end = run(i, |c| c.is_ascii_alphanumeric() || c == b'_' || c == b'$');
+            (Kind::Word, end)
+        }
+        // One character, or more when it is not ASCII.
+        _ => (
+            Kind::Symbol,
+            i + sql[i..].chars().next().map_or(1, char::len_utf8),
+        ),
+    })
+}
+
+/// Index just past the closing `close` of the quoted run starting at `open`;
+/// doubled quotes are escapes.
+fn quoted(b: &[u8], open: usize, close: u8) -> usize {
+    let mut i = open + 1;
+    while i < b.len() {
+        if b[i] == close {
+            if close != b']' && b.get(i + 1) == Some(&close) {
+                i += 2;
+                continue;
+            }
+            return i + 1;
+        }
+        i += 1;
+    }
+    b.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(sql: &str) -> Vec<(Kind<'_>, &str)> {
+        tokens(sql)
+            .filter(|t| t.kind != Kind::Space)
+            .map(|t| (t.kind, t.text))
+            .collect()
+    }
+
+    #[test]
+    fn quotes_and_comments_are_single_tokens() {
+        assert_eq!(
+            kinds("SELECT 'a;--b''c', \"d\"\"e\", [f g], `h` -- i;\n/* j; */ x'ff'"),
+            [
+                (Kind::Word, "SELECT"),
+                (Kind::Literal, "'a;--b''c'"),
+                (Kind::Symbol, ","),
+                (Kind::QuotedName, "\"d\"\"e\""),
+                (Kind::Symbol, ","),
+                (Kind::QuotedName, "[f g]"),
+                (Kind::Symbol, ","),
+                (Kind::QuotedName, "`h`"),
+                (Kind::Comment, "-- i;\n"),
+                (Kind::Comment, "/* j; */"),
+                (Kind::Literal, "x'ff'"),
+            ]
+        );
+    }
+
+    #[test]
+    fn placeholders_and_numbers() {
+        assert_eq!(
+            kinds("a$b = ?2 + $1 + :c + @d + $e + 1.5e-3 + ?"),
+            [
+                (Kind::Word, "a$b"),
+                (Kind::Symbol, "="),
+                (Kind::Placeholder(Placeholder::Question), "?2"),
+                (Kind::Symbol, "+"),
+                (Kind::Placeholder(Placeholder::Dollar("1")), "$1"),
+                (Kind::Symbol, "+"),
+                (Kind::Placeholder(Placeholder::Named("c")), ":c"),
+                (Kind::Symbol, "+"),
+                (Kind::Placeholder(Placeholder::Named("d")), "@d"),
+                (Kind::Symbol, "+"),
+                (Kind::Placeholder(Placeholder::Named("e")), "$e"),
+                (Kind::Symbol, "+"),
+                (Kind::Number, "1.5e-3"),
+                (Kind::Symbol, "+"),
+                (Kind::Placeholder(Placeholder::Question), "?"),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokens_cover_the_input() {
+        for sql in ["SELECT 'unterminated", "a /* open", "é -- x", ""] {
+            let joined: String = tokens(sql).map(|t| t.text).collect();
+            assert_eq!(joined, sql);
+        }
+    }
+}
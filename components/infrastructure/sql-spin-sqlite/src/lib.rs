@@ -1,10 +1,12 @@
 #![cfg_attr(not(target_arch = "wasm32"), deny(unsafe_code))]
 #![cfg_attr(target_arch = "wasm32", allow(unsafe_code))]
 #![cfg_attr(not(target_arch = "wasm32"), allow(dead_code, unused_imports))]
+// `sql-error` is fixed by the WIT interface and returned by value across it.
+#![allow(clippy::result_large_err)]
 //! Spin-backed SQLite implementation of the `sql` WIT interface.
 //! Uses Spin's host-provided SQLite for performance and simplicity.
 
-use spin_sdk::sqlite::{Connection, QueryResult as SpinQueryResult, Value as SpinValue};
+use spin_sdk::sqlite::{QueryResult as SpinQueryResult, Value as SpinValue};
 
 #[macro_use]
 mod bindings {
@@ -15,95 +17,380 @@ mod bindings {
     wit_bindgen::generate!({
         world: "sql-adapter",
         path: "wit",
+        additional_derives: [PartialEq],
     });
 }
 
+mod batch;
+#[cfg(test)]
+mod bdd_tests;
+mod config;
+mod connection;
+mod cursor;
+mod diagnostics;
+mod error;
+mod host;
+mod lexer;
+mod limits;
+mod params;
+mod plan;
+mod policy;
+mod retry;
+mod schema;
+mod statement;
+mod subquery;
+mod telemetry;
+mod tenant;
+mod transaction;
+mod values;
+
 use crate::bindings::exports::keel::infrastructure::sql::{self as wit_sql};
+use crate::config::Config;
+use crate::cursor::RowCursor;
+use crate::error::map_err;
+use crate::host::Connection;
+use crate::limits::Limits;
+use crate::params::normalize_positional;
+use crate::statement::Statement;
+use crate::transaction::Transaction;
+use crate::values::{column_meta, resolve_hints, row_to_wit, row_values_to_wit, values_from};
 
-// Map WIT sql-value to Spin SQLite Value
-fn to_spin_value(v: &wit_sql::SqlValue) -> Result<SpinValue, wit_sql::SqlError> {
-    use wit_sql::SqlValue as W;
-    Ok(match v {
-        W::Null => SpinValue::Null,
-        W::Boolean(b) => SpinValue::Integer(if *b { 1 } else { 0 }),
-        W::Int32(i) => SpinValue::Integer(*i as i64),
-        W::Int64(i) => SpinValue::Integer(*i),
-        W::Float32(f) => SpinValue::Real(*f as f64),
-        W::Float64(f) => SpinValue::Real(*f),
-        W::Text(s) => SpinValue::Text(s.clone()),
-        W::Bytes(b) => SpinValue::Blob(b.clone()),
-        W::Timestamp(ts) => SpinValue::Integer(*ts),
-        W::Uuid(u) => SpinValue::Text(u.clone()),
-    })
-}
+struct Adapter;
+
+impl wit_sql::Guest for Adapter {
+    type Connection = Database;
+    type Transaction = Transaction;
+    type RowCursor = RowCursor;
+    type Statement = Statement;
+
+    fn query(
+        sql: String,
+        params: Vec<wit_sql::SqlValue>,
+    ) -> Result<wit_sql::QueryResult, wit_sql::SqlError> {
+        Database::default().run_query(&sql, &params, &[])
+    }
+
+    fn query_typed(
+        sql: String,
+        params: Vec<wit_sql::SqlValue>,
+        schema: Vec<wit_sql::ColumnHint>,
+    ) -> Result<wit_sql::QueryResult, wit_sql::SqlError> {
+        Database::default().run_query(&sql, &params, &schema)
+    }
+
+    fn query_with(
+        sql: String,
+        params: Vec<wit_sql::SqlValue>,
+        schema: Vec<wit_sql::ColumnHint>,
+        options: wit_sql::CallOptions,
+    ) -> Result<wit_sql::QueryResult, wit_sql::SqlError> {
+        Database::default().run_query_with(&sql, &params, &schema, &options)
+    }
 
-fn map_err<E: std::error::Error>(e: E, kind: &'static str) -> wit_sql::SqlError {
-    match kind {
-        "connection" => wit_sql::SqlError::ConnectionFailed(e.to_string()),
-        "query" => wit_sql::SqlError::QueryFailed(e.to_string()),
-        "transaction" => wit_sql::SqlError::TransactionFailed(e.to_string()),
-        _ => wit_sql::SqlError::QueryFailed(e.to_string()),
+    fn query_named(
+        sql: String,
+        params: Vec<(String, wit_sql::SqlValue)>,
+    ) -> Result<wit_sql::QueryResult, wit_sql::SqlError> {
+        Database::default().run_query_named(&sql, &params)
+    }
+
+    fn query_columnar(
+        sql: String,
+        params: Vec<wit_sql::SqlValue>,
+        schema: Vec<wit_sql::ColumnHint>,
+    ) -> Result<wit_sql::ColumnarResult, wit_sql::SqlError> {
+        Database::default().run_query_columnar(&sql, &params, &schema)
+    }
+
+    fn execute(sql: String, params: Vec<wit_sql::SqlValue>) -> Result<u64, wit_sql::SqlError> {
+        Database::default().run_execute(&sql, &params)
+    }
+
+    fn execute_named(
+        sql: String,
+        params: Vec<(String, wit_sql::SqlValue)>,
+    ) -> Result<u64, wit_sql::SqlError> {
+        Database::default().run_execute_named(&sql, &params)
+    }
+
+    fn execute_returning(
+        sql: String,
+        params: Vec<wit_sql::SqlValue>,
+    ) -> Result<wit_sql::ExecuteResult, wit_sql::SqlError> {
+        Database::default().run_execute_returning(&sql, &params)
+    }
+
+    fn execute_batch(
+        sql: String,
+        rows: Vec<Vec<wit_sql::SqlValue>>,
+    ) -> Result<Vec<u64>, wit_sql::SqlError> {
+        Database::default().run_execute_batch(&sql, &rows)
+    }
+
+    fn execute_script(sql: String) -> Result<Vec<u64>, wit_sql::SqlError> {
+        Database::default().run_execute_script(&sql)
+    }
+
+    fn query_stream(
+        sql: String,
+        params: Vec<wit_sql::SqlValue>,
+        key: Option<String>,
+    ) -> Result<wit_sql::RowCursor, wit_sql::SqlError> {
+        Database::default().stream(&sql, &params, key)
+    }
+
+    fn prepare(sql: String) -> Result<wit_sql::Statement, wit_sql::SqlError> {
+        Database::default().prepare(&sql)
+    }
+
+    fn list_tables() -> Result<Vec<String>, wit_sql::SqlError> {
+        Database::default().with_conn(schema::list_tables_on)
+    }
+
+    fn describe_table(name: String) -> Result<wit_sql::TableInfo, wit_sql::SqlError> {
+        Database::default().with_conn(|conn| schema::describe_table_on(conn, &name))
+    }
+
+    fn list_indexes(table: String) -> Result<Vec<wit_sql::IndexInfo>, wit_sql::SqlError> {
+        Database::default().with_conn(|conn| schema::list_indexes_on(conn, &table))
+    }
+
+    fn explain(
+        sql: String,
+        params: Vec<wit_sql::SqlValue>,
+    ) -> Result<Vec<wit_sql::PlanNode>, wit_sql::SqlError> {
+        Database::default().run_explain(&sql, &params)
+    }
+
+    fn begin_transaction(
+        options: Option<wit_sql::TransactionOptions>,
+    ) -> Result<wit_sql::Transaction, wit_sql::SqlError> {
+        Database::default().begin(options)
+    }
+
+    fn metrics() -> wit_sql::MetricsSnapshot {
+        telemetry::snapshot()
+    }
+
+    fn diagnostics() -> wit_sql::DiagnosticsReport {
+        diagnostics::report()
     }
 }
 
-fn values_from(params: &[wit_sql::SqlValue]) -> Result<Vec<SpinValue>, wit_sql::SqlError> {
-    params.iter().map(to_spin_value).collect()
+/// A database label; the underlying connection lives in the instance cache.
+struct Database {
+    label: String,
 }
 
-fn row_to_wit(columns: &[String], row_values: &[SpinValue]) -> wit_sql::SqlRow {
-    let cols = columns
-        .iter()
-        .cloned()
-        .zip(row_values.iter().cloned())
-        .map(|(name, v)| {
-            let val = match v {
-                SpinValue::Null => wit_sql::SqlValue::Null,
-                SpinValue::Integer(i) => wit_sql::SqlValue::Int64(i),
-                SpinValue::Real(f) => wit_sql::SqlValue::Float64(f),
-                SpinValue::Text(s) => wit_sql::SqlValue::Text(s),
-                SpinValue::Blob(b) => wit_sql::SqlValue::Bytes(b),
-            };
-            (name, val)
+impl Default for Database {
+    fn default() -> Self {
+        Self {
+            label: connection::DEFAULT_DATABASE.to_string(),
+        }
+    }
+}
+
+impl Database {
+    /// The database behind `label`, opened eagerly so unknown or ungranted
+    /// labels fail here.
+    fn connect(label: String) -> Result<Self, wit_sql::SqlError> {
+        connection::shared(&label)?;
+        Ok(Database { label })
+    }
+
+    /// `tenant`'s copy of database `name`.
+    fn connect_tenant(name: &str, tenant: &str) -> Result<Self, wit_sql::SqlError> {
+        Self::connect(tenant::open(name, tenant)?)
+    }
+
+    fn run_query(
+        &self,
+        sql: &str,
+        params: &[wit_sql::SqlValue],
+        schema: &[wit_sql::ColumnHint],
+    ) -> Result<wit_sql::QueryResult, wit_sql::SqlError> {
+        telemetry::observe("query", &self.label, sql, params, || {
+            connection::with_shared(&self.label, |conn| exec_query_on(conn, sql, params, schema))
+        })
+    }
+
+    fn run_query_with(
+        &self,
+        sql: &str,
+        params: &[wit_sql::SqlValue],
+        schema: &[wit_sql::ColumnHint],
+        options: &wit_sql::CallOptions,
+    ) -> Result<wit_sql::QueryResult, wit_sql::SqlError> {
+        telemetry::observe("query", &self.label, sql, params, || {
+            let limits = Limits::start(options);
+            connection::with_shared(&self.label, |conn| {
+                exec_query_limited_on(conn, sql, params, schema, &limits)
+            })
+        })
+    }
+
+    fn with_conn<T>(
+        &self,
+        f: impl FnMut(&Connection) -> Result<T, wit_sql::SqlError>,
+    ) -> Result<T, wit_sql::SqlError> {
+        connection::with_shared(&self.label, f)
+    }
+
+    fn run_explain(
+        &self,
+        sql: &str,
+        params: &[wit_sql::SqlValue],
+    ) -> Result<Vec<wit_sql::PlanNode>, wit_sql::SqlError> {
+        let values = values_from(params, Config::get().uuid_storage)?;
+        self.with_conn(|conn| plan::explain_on(conn, sql, &values))
+    }
+
+    fn run_query_named(
+        &self,
+        sql: &str,
+        params: &[(String, wit_sql::SqlValue)],
+    ) -> Result<wit_sql::QueryResult, wit_sql::SqlError> {
+        let (sql, params) = params::bind_named(sql, params)?;
+        self.run_query(&sql, &params, &[])
+    }
+
+    fn run_query_columnar(
+        &self,
+        sql: &str,
+        params: &[wit_sql::SqlValue],
+        schema: &[wit_sql::ColumnHint],
+    ) -> Result<wit_sql::ColumnarResult, wit_sql::SqlError> {
+        telemetry::observe("query", &self.label, sql, params, || {
+            connection::with_shared(&self.label, |conn| {
+                exec_query_columnar_on(conn, sql, params, schema)
+            })
+        })
+    }
+
+    fn run_execute(
+        &self,
+        sql: &str,
+        params: &[wit_sql::SqlValue],
+    ) -> Result<u64, wit_sql::SqlError> {
+        telemetry::observe("execute", &self.label, sql, params, || {
+            connection::with_shared(&self.label, |conn| exec_execute_on(conn, sql, params))
         })
-        .collect();
-    wit_sql::SqlRow { columns: cols }
+    }
+
+    fn run_execute_named(
+        &self,
+        sql: &str,
+        params: &[(String, wit_sql::SqlValue)],
+    ) -> Result<u64, wit_sql::SqlError> {
+        let (sql, params) = params::bind_named(sql, params)?;
+        self.run_execute(&sql, &params)
+    }
+
+    fn run_execute_returning(
+        &self,
+        sql: &str,
+        params: &[wit_sql::SqlValue],
+    ) -> Result<wit_sql::ExecuteResult, wit_sql::SqlError> {
+        telemetry::observe("execute", &self.label, sql, params, || {
+            connection::with_shared(&self.label, |conn| {
+                exec_execute_returning_on(conn, sql, params)
+            })
+        })
+    }
+
+    fn run_execute_batch(
+        &self,
+        sql: &str,
+        rows: &[Vec<wit_sql::SqlValue>],
+    ) -> Result<Vec<u64>, wit_sql::SqlError> {
+        let nested = connection::in_transaction(&self.label);
+        let first = rows.first().map_or(&[][..], Vec::as_slice);
+        telemetry::observe("execute-batch", &self.label, sql, first, || {
+            connection::with_shared(&self.label, |conn| {
+                batch::exec_batch_on(conn, sql, rows, nested)
+            })
+        })
+    }
+
+    fn run_execute_script(&self, sql: &str) -> Result<Vec<u64>, wit_sql::SqlError> {
+        let nested = connection::in_transaction(&self.label);
+        telemetry::observe("execute-script", &self.label, sql, &[], || {
+            connection::with_shared(&self.label, |conn| batch::exec_script_on(conn, sql, nested))
+        })
+    }
+
+    fn stream(
+        &self,
+        sql: &str,
+        params: &[wit_sql::SqlValue],
+        key: Option<String>,
+    ) -> Result<wit_sql::RowCursor, wit_sql::SqlError> {
+        RowCursor::new(&self.label, sql, params, key).map(wit_sql::RowCursor::new)
+    }
+
+    fn prepare(&self, sql: &str) -> Result<wit_sql::Statement, wit_sql::SqlError> {
+        Statement::prepare(&self.label, sql).map(wit_sql::Statement::new)
+    }
+
+    fn begin(
+        &self,
+        options: Option<wit_sql::TransactionOptions>,
+    ) -> Result<wit_sql::Transaction, wit_sql::SqlError> {
+        Transaction::begin(&self.label, options).map(wit_sql::Transaction::new)
+    }
 }
 
-struct Adapter;
+impl wit_sql::GuestConnection for Database {
+    fn open(name: String) -> Result<wit_sql::Connection, wit_sql::SqlError> {
+        Database::connect(name).map(wit_sql::Connection::new)
+    }
 
-impl wit_sql::Guest for Adapter {
-    type Transaction = Transaction;
+    fn open_tenant(name: String, tenant: String) -> Result<wit_sql::Connection, wit_sql::SqlError> {
+        Database::connect_tenant(&name, &tenant).map(wit_sql::Connection::new)
+    }
 
     fn query(
+        &self,
         sql: String,
         params: Vec<wit_sql::SqlValue>,
     ) -> Result<wit_sql::QueryResult, wit_sql::SqlError> {
-        exec_query(&sql, &params)
+        self.run_query(&sql, &params, &[])
     }
 
-    fn execute(sql: String, params: Vec<wit_sql::SqlValue>) -> Result<u64, wit_sql::SqlError> {
-        exec_execute(&sql, &params)
+    fn query_typed(
+        &self,
+        sql: String,
+        params: Vec<wit_sql::SqlValue>,
+        schema: Vec<wit_sql::ColumnHint>,
+    ) -> Result<wit_sql::QueryResult, wit_sql::SqlError> {
+        self.run_query(&sql, &params, &schema)
     }
 
-    fn begin_transaction() -> Result<wit_sql::Transaction, wit_sql::SqlError> {
-        let conn = Connection::open_default().map_err(|e| map_err(e, "connection"))?;
-        conn.execute("BEGIN", &[])
-            .map_err(|e| map_err(e, "transaction"))?;
-        Ok(wit_sql::Transaction::new(Transaction { conn }))
+    fn query_with(
+        &self,
+        sql: String,
+        params: Vec<wit_sql::SqlValue>,
+        schema: Vec<wit_sql::ColumnHint>,
+        options: wit_sql::CallOptions,
+    ) -> Result<wit_sql::QueryResult, wit_sql::SqlError> {
+        self.run_query_with(&sql, &params, &schema, &options)
     }
-}
 
-struct Transaction {
-    conn: Connection,
-}
+    fn query_named(
+        &self,
+        sql: String,
+        params: Vec<(String, wit_sql::SqlValue)>,
+    ) -> Result<wit_sql::QueryResult, wit_sql::SqlError> {
+        self.run_query_named(&sql, &params)
+    }
 
-impl wit_sql::GuestTransaction for Transaction {
-    fn query(
+    fn query_columnar(
         &self,
         sql: String,
         params: Vec<wit_sql::SqlValue>,
-    ) -> Result<wit_sql::QueryResult, wit_sql::SqlError> {
-        exec_query_on(&self.conn, &sql, &params)
+        schema: Vec<wit_sql::ColumnHint>,
+    ) -> Result<wit_sql::ColumnarResult, wit_sql::SqlError> {
+        self.run_query_columnar(&sql, &params, &schema)
     }
 
     fn execute(
@@ -111,21 +398,75 @@ impl wit_sql::GuestTransaction for Transaction {
         sql: String,
         params: Vec<wit_sql::SqlValue>,
     ) -> Result<u64, wit_sql::SqlError> {
-        exec_execute_on(&self.conn, &sql, &params)
+        self.run_execute(&sql, &params)
+    }
+
+    fn execute_named(
+        &self,
+        sql: String,
+        params: Vec<(String, wit_sql::SqlValue)>,
+    ) -> Result<u64, wit_sql::SqlError> {
+        self.run_execute_named(&sql, &params)
+    }
+
+    fn execute_returning(
+        &self,
+        sql: String,
+        params: Vec<wit_sql::SqlValue>,
+    ) -> Result<wit_sql::ExecuteResult, wit_sql::SqlError> {
+        self.run_execute_returning(&sql, &params)
+    }
+
+    fn execute_batch(
+        &self,
+        sql: String,
+        rows: Vec<Vec<wit_sql::SqlValue>>,
+    ) -> Result<Vec<u64>, wit_sql::SqlError> {
+        self.run_execute_batch(&sql, &rows)
     }
 
-    fn commit(&self) -> Result<(), wit_sql::SqlError> {
-        self.conn
-            .execute("COMMIT", &[])
-            .map_err(|e| map_err(e, "transaction"))?;
-        Ok(())
+    fn execute_script(&self, sql: String) -> Result<Vec<u64>, wit_sql::SqlError> {
+        self.run_execute_script(&sql)
     }
 
-    fn rollback(&self) -> Result<(), wit_sql::SqlError> {
-        self.conn
-            .execute("ROLLBACK", &[])
-            .map_err(|e| map_err(e, "transaction"))?;
-        Ok(())
+    fn query_stream(
+        &self,
+        sql: String,
+        params: Vec<wit_sql::SqlValue>,
+        key: Option<String>,
+    ) -> Result<wit_sql::RowCursor, wit_sql::SqlError> {
+        self.stream(&sql, &params, key)
+    }
+
+    fn prepare(&self, sql: String) -> Result<wit_sql::Statement, wit_sql::SqlError> {
+        Database::prepare(self, &sql)
+    }
+
+    fn list_tables(&self) -> Result<Vec<String>, wit_sql::SqlError> {
+        self.with_conn(schema::list_tables_on)
+    }
+
+    fn describe_table(&self, name: String) -> Result<wit_sql::TableInfo, wit_sql::SqlError> {
+        self.with_conn(|conn| schema::describe_table_on(conn, &name))
+    }
+
+    fn list_indexes(&self, table: String) -> Result<Vec<wit_sql::IndexInfo>, wit_sql::SqlError> {
+        self.with_conn(|conn| schema::list_indexes_on(conn, &table))
+    }
+
+    fn explain(
+        &self,
+        sql: String,
+        params: Vec<wit_sql::SqlValue>,
+    ) -> Result<Vec<wit_sql::PlanNode>, wit_sql::SqlError> {
+        self.run_explain(&sql, &params)
+    }
+
+    fn begin_transaction(
+        &self,
+        options: Option<wit_sql::TransactionOptions>,
+    ) -> Result<wit_sql::Transaction, wit_sql::SqlError> {
+        self.begin(options)
     }
 }
 
@@ -133,46 +474,89 @@ impl wit_sql::GuestTransaction for Transaction {
 #[cfg(target_arch = "wasm32")]
 bindings::export!(Adapter with_types_in bindings);
 
-fn exec_query(
+fn exec_query_on(
+    conn: &Connection,
     sql: &str,
     params: &[wit_sql::SqlValue],
+    schema: &[wit_sql::ColumnHint],
 ) -> Result<wit_sql::QueryResult, wit_sql::SqlError> {
-    let conn = Connection::open_default().map_err(|e| map_err(e, "connection"))?;
-    let values = values_from(params)?;
-    let qr: SpinQueryResult = conn
-        .execute(sql, values.as_slice())
-        .map_err(|e| map_err(e, "query"))?;
-    let mut out_rows = Vec::new();
-    for row in &qr.rows {
-        out_rows.push(row_to_wit(&qr.columns, &row.values));
-    }
-    Ok(wit_sql::QueryResult {
-        rows: out_rows,
-        rows_affected: 0,
-    })
+    exec_query_limited_on(conn, sql, params, schema, &Limits::NONE)
 }
 
-fn exec_execute(sql: &str, params: &[wit_sql::SqlValue]) -> Result<u64, wit_sql::SqlError> {
-    let conn = Connection::open_default().map_err(|e| map_err(e, "connection"))?;
-    exec_execute_on(&conn, sql, params)
+fn exec_query_limited_on(
+    conn: &Connection,
+    sql: &str,
+    params: &[wit_sql::SqlValue],
+    schema: &[wit_sql::ColumnHint],
+    limits: &Limits,
+) -> Result<wit_sql::QueryResult, wit_sql::SqlError> {
+    policy::check(sql)?;
+    limits.check_applies(sql)?;
+    let values = values_from(params, Config::get().uuid_storage)?;
+    check_plan(conn, sql, &values);
+    exec_prepared_query_on(conn, &normalize_positional(sql), &values, schema, limits)
 }
 
-fn exec_query_on(
+/// Run a query that already passed the policy check and had its placeholders
+/// normalized, as a prepared `statement` has.
+fn exec_prepared_query_on(
     conn: &Connection,
     sql: &str,
-    params: &[wit_sql::SqlValue],
+    values: &[SpinValue],
+    schema: &[wit_sql::ColumnHint],
+    limits: &Limits,
 ) -> Result<wit_sql::QueryResult, wit_sql::SqlError> {
-    let values = values_from(params)?;
-    let qr: SpinQueryResult = conn
-        .execute(sql, values.as_slice())
-        .map_err(|e| map_err(e, "query"))?;
+    limits.check_time(sql)?;
+    let before = read_counters(conn)?;
+    let qr: SpinQueryResult = limits
+        .execute(conn, sql, values)
+        .map_err(|e| map_err(e, "query", Some(sql)))?;
+    limits.check_time(sql)?;
+    limits.check_rows(qr.rows.len(), sql)?;
+    let after = read_counters(conn)?;
+    let hints = resolve_hints(&qr.columns, schema);
+    let mut bytes = 0;
     let mut out_rows = Vec::new();
     for row in &qr.rows {
-        out_rows.push(row_to_wit(&qr.columns, &row.values));
+        limits.add_bytes(&mut bytes, &row.values, sql)?;
+        out_rows.push(
+            row_to_wit(&qr.columns, &hints, &row.values)
+                .map_err(|e| error::with_statement(e, sql))?,
+        );
     }
     Ok(wit_sql::QueryResult {
         rows: out_rows,
-        rows_affected: 0,
+        rows_affected: rows_affected(&before, &after),
+    })
+}
+
+fn exec_query_columnar_on(
+    conn: &Connection,
+    sql: &str,
+    params: &[wit_sql::SqlValue],
+    schema: &[wit_sql::ColumnHint],
+) -> Result<wit_sql::ColumnarResult, wit_sql::SqlError> {
+    policy::check(sql)?;
+    let values = values_from(params, Config::get().uuid_storage)?;
+    check_plan(conn, sql, &values);
+    let before = read_counters(conn)?;
+    let qr: SpinQueryResult = conn
+        .execute(&normalize_positional(sql), values.as_slice())
+        .map_err(|e| map_err(e, "query", Some(sql)))?;
+    let after = read_counters(conn)?;
+    let hints = resolve_hints(&qr.columns, schema);
+    let rows = qr
+        .rows
+        .iter()
+        .map(|row| {
+            row_values_to_wit(&qr.columns, &hints, &row.values)
+                .map_err(|e| error::with_statement(e, sql))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(wit_sql::ColumnarResult {
+        columns: column_meta(&qr.columns, &hints, &rows),
+        rows,
+        rows_affected: rows_affected(&before, &after),
     })
 }
 
@@ -181,87 +565,251 @@ fn exec_execute_on(
     sql: &str,
     params: &[wit_sql::SqlValue],
 ) -> Result<u64, wit_sql::SqlError> {
-    let values = values_from(params)?;
-    conn.execute(sql, values.as_slice())
-        .map_err(|e| map_err(e, "query"))?;
-    Ok(0)
+    Ok(exec_execute_returning_on(conn, sql, params)?.rows_affected)
+}
+
+fn exec_execute_returning_on(
+    conn: &Connection,
+    sql: &str,
+    params: &[wit_sql::SqlValue],
+) -> Result<wit_sql::ExecuteResult, wit_sql::SqlError> {
+    policy::check(sql)?;
+    let values = values_from(params, Config::get().uuid_storage)?;
+    check_plan(conn, sql, &values);
+    exec_prepared_execute_on(conn, &normalize_positional(sql), &values)
+}
+
+/// The `execute` counterpart of [`exec_prepared_query_on`].
+fn exec_prepared_execute_on(
+    conn: &Connection,
+    sql: &str,
+    values: &[SpinValue],
+) -> Result<wit_sql::ExecuteResult, wit_sql::SqlError> {
+    let before = read_counters(conn)?;
+    conn.execute(sql, values)
+        .map_err(|e| map_err(e, "query", Some(sql)))?;
+    let after = read_counters(conn)?;
+    Ok(wit_sql::ExecuteResult {
+        rows_affected: rows_affected(&before, &after),
+        last_insert_id: last_insert_id(sql, &before, &after),
+    })
+}
+
+/// Log full scans of large tables when `sql_warn_scan_rows` is configured.
+fn check_plan(conn: &Connection, sql: &str, values: &[SpinValue]) {
+    if let Some(threshold) = Config::get().warn_scan_rows {
+        plan::warn_on_full_scans(conn, sql, values, threshold);
+    }
+}
+
+/// Per-connection change counters. Spin's host API does not surface
+/// `sqlite3_changes()` or `sqlite3_last_insert_rowid()`, so they are read back
+/// through SQL on the same connection around each statement.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Counters {
+    total_changes: i64,
+    changes: i64,
+    last_insert_rowid: i64,
+}
+
+fn read_counters(conn: &Connection) -> Result<Counters, wit_sql::SqlError> {
+    let qr = conn
+        .execute(
+            "SELECT total_changes(), changes(), last_insert_rowid()",
+            &[],
+        )
+        .map_err(|e| map_err(e, "query", None))?;
+    let Some(row) = qr.rows.first() else {
+        return Ok(Counters::default());
+    };
+    Ok(Counters {
+        total_changes: row.get::<i64>(0).unwrap_or(0),
+        changes: row.get::<i64>(1).unwrap_or(0),
+        last_insert_rowid: row.get::<i64>(2).unwrap_or(0),
+    })
+}
+
+/// `changes()` keeps reporting the last INSERT/UPDATE/DELETE even after a
+/// SELECT or DDL statement, so only trust it when `total_changes()` moved.
+fn rows_affected(before: &Counters, after: &Counters) -> u64 {
+    if after.total_changes == before.total_changes {
+        0
+    } else {
+        u64::try_from(after.changes).unwrap_or(0)
+    }
+}
+
+/// `last_insert_rowid()` is sticky per connection, so it is only reported
+/// when an INSERT moved it: an upsert that updated a row, or an ignored
+/// insert whose trigger changed other rows, leaves it where an earlier
+/// statement put it. A REPLACE always inserts, possibly under the rowid it
+/// just deleted, so for those a change is enough.
+fn last_insert_id(sql: &str, before: &Counters, after: &Counters) -> Option<i64> {
+    let moved = after.last_insert_rowid != before.last_insert_rowid;
+    let inserted = if policy::replaces(sql) {
+        moved || after.total_changes != before.total_changes
+    } else {
+        moved && policy::main_keyword(sql).is_some_and(|k| k.eq_ignore_ascii_case("INSERT"))
+    };
+    inserted.then_some(after.last_insert_rowid)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn counters(total_changes: i64, changes: i64, last_insert_rowid: i64) -> Counters {
+        Counters {
+            total_changes,
+            changes,
+            last_insert_rowid,
+        }
+    }
+
+    #[test]
+    fn rows_affected_ignores_stale_changes() {
+        // A DML statement moved the counter: report changes().
+        assert_eq!(rows_affected(&counters(10, 0, 0), &counters(11, 1, 0)), 1);
+        assert_eq!(rows_affected(&counters(10, 0, 0), &counters(13, 3, 0)), 3);
+        // SELECT/DDL after an earlier UPDATE: changes() is stale.
+        assert_eq!(rows_affected(&counters(10, 5, 0), &counters(10, 5, 0)), 0);
+        // UPDATE that matched nothing.
+        assert_eq!(rows_affected(&counters(10, 0, 0), &counters(10, 0, 0)), 0);
+    }
+
     #[test]
-    fn to_spin_value_maps_all_variants() {
-        use wit_sql::SqlValue as W;
-
-        assert!(matches!(to_spin_value(&W::Null).unwrap(), SpinValue::Null));
-        assert!(matches!(
-            to_spin_value(&W::Boolean(true)).unwrap(),
-            SpinValue::Integer(1)
-        ));
-        assert!(matches!(
-            to_spin_value(&W::Boolean(false)).unwrap(),
-            SpinValue::Integer(0)
-        ));
-        assert!(matches!(
-            to_spin_value(&W::Int32(7)).unwrap(),
-            SpinValue::Integer(7)
-        ));
-        assert!(matches!(
-            to_spin_value(&W::Int64(9)).unwrap(),
-            SpinValue::Integer(9)
-        ));
-        assert!(
-            matches!(to_spin_value(&W::Float32(1.5)).unwrap(), SpinValue::Real(x) if (x-1.5).abs() < 1e-6)
+    fn last_insert_id_only_for_inserting_statements() {
+        let insert = "INSERT INTO t (v) VALUES (1)";
+        // Fresh connection, first INSERT.
+        assert_eq!(
+            last_insert_id(insert, &counters(0, 0, 0), &counters(1, 1, 7)),
+            Some(7)
         );
-        assert!(
-            matches!(to_spin_value(&W::Float64(2.5)).unwrap(), SpinValue::Real(x) if (x-2.5).abs() < 1e-12)
+        // UPDATE after an INSERT in the same transaction: rowid is stale.
+        let update = "UPDATE t SET v = 2";
+        assert_eq!(
+            last_insert_id(update, &counters(1, 1, 7), &counters(2, 1, 7)),
+            None
         );
-        assert!(
-            matches!(to_spin_value(&W::Text("hi".into())).unwrap(), SpinValue::Text(s) if s=="hi")
+        // SELECT after an INSERT.
+        assert_eq!(
+            last_insert_id("SELECT 1", &counters(1, 1, 7), &counters(1, 1, 7)),
+            None
         );
-        assert!(
-            matches!(to_spin_value(&W::Bytes(vec![1,2])).unwrap(), SpinValue::Blob(b) if b==vec![1,2])
+        // INSERT OR REPLACE of the last inserted row keeps its rowid.
+        for replace in [
+            "INSERT OR REPLACE INTO t (id, v) VALUES (7, 3)",
+            "REPLACE INTO t (id, v) VALUES (7, 3)",
+            "WITH n AS (SELECT 7 AS id) INSERT OR REPLACE INTO t (id) SELECT id FROM n",
+        ] {
+            assert_eq!(
+                last_insert_id(replace, &counters(1, 1, 7), &counters(2, 1, 7)),
+                Some(7),
+                "{replace}"
+            );
+        }
+        // INSERT ... SELECT that matched nothing.
+        assert_eq!(
+            last_insert_id(insert, &counters(1, 1, 7), &counters(1, 0, 7)),
+            None
         );
-        assert!(matches!(
-            to_spin_value(&W::Timestamp(123)).unwrap(),
-            SpinValue::Integer(123)
-        ));
-        assert!(
-            matches!(to_spin_value(&W::Uuid("abc".into())).unwrap(), SpinValue::Text(s) if s=="abc")
+        // An upsert that updated, or an ignored insert whose trigger wrote:
+        // rows changed but the rowid is an earlier insert's.
+        for kept in [
+            "INSERT INTO t (id, v) VALUES (1, 3) ON CONFLICT (id) DO UPDATE SET v = 3",
+            "INSERT OR IGNORE INTO t (id, v) VALUES (1, 3)",
+        ] {
+            assert_eq!(
+                last_insert_id(kept, &counters(2, 1, 2), &counters(3, 1, 2)),
+                None,
+                "{kept}"
+            );
+        }
+        assert_eq!(
+            last_insert_id(insert, &counters(2, 1, 2), &counters(3, 1, 3)),
+            Some(3)
         );
     }
 
     #[test]
-    fn row_to_wit_roundtrip_basic() {
-        let cols = vec![
-            "a".to_string(),
-            "b".to_string(),
-            "c".to_string(),
-            "d".to_string(),
-            "e".to_string(),
-        ];
-        let values = vec![
-            SpinValue::Null,
-            SpinValue::Integer(42),
-            SpinValue::Real(3.5),
-            SpinValue::Text("ok".into()),
-            SpinValue::Blob(vec![9, 8, 7]),
-        ];
-
-        let row = row_to_wit(&cols, &values);
-        assert_eq!(row.columns.len(), cols.len());
-        for (i, (name, v)) in row.columns.iter().enumerate() {
-            assert_eq!(name, &cols[i]);
-            match (i, v) {
-                (0, wit_sql::SqlValue::Null) => {}
-                (1, wit_sql::SqlValue::Int64(n)) => assert_eq!(*n, 42),
-                (2, wit_sql::SqlValue::Float64(f)) => assert!((f - 3.5).abs() < 1e-12),
-                (3, wit_sql::SqlValue::Text(s)) => assert_eq!(s, "ok"),
-                (4, wit_sql::SqlValue::Bytes(b)) => assert_eq!(b, &vec![9, 8, 7]),
-                _ => panic!("unexpected mapping at index {}: {:?}", i, v),
-            }
+    fn replacing_the_last_inserted_row_reports_its_id() {
+        let db = Database::default();
+        db.run_execute("CREATE TABLE t (id INTEGER PRIMARY KEY, v TEXT)", &[])
+            .unwrap();
+        let inserted = db
+            .run_execute_returning("INSERT INTO t (id, v) VALUES (7, 'a')", &[])
+            .unwrap();
+        assert_eq!(inserted.last_insert_id, Some(7));
+        let replaced = db
+            .run_execute_returning("INSERT OR REPLACE INTO t (id, v) VALUES (7, 'b')", &[])
+            .unwrap();
+        assert_eq!(
+            (replaced.rows_affected, replaced.last_insert_id),
+            (1, Some(7))
+        );
+        let updated = db
+            .run_execute_returning("UPDATE t SET v = 'c'", &[])
+            .unwrap();
+        assert_eq!((updated.rows_affected, updated.last_insert_id), (1, None));
+    }
+
+    #[test]
+    fn upserts_and_ignored_inserts_report_no_id() {
+        let db = Database::connect("upsert".to_string()).unwrap();
+        for ddl in [
+            "DROP TABLE IF EXISTS t",
+            "DROP TABLE IF EXISTS log",
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, v TEXT)",
+            "CREATE TABLE log (id INTEGER PRIMARY KEY, note TEXT)",
+            "CREATE TRIGGER t_log BEFORE INSERT ON t BEGIN \
+             INSERT INTO log (note) VALUES ('attempt'); END",
+            "INSERT INTO t (id, v) VALUES (1, 'a')",
+        ] {
+            db.run_execute(ddl, &[]).unwrap();
         }
+        let inserted = db
+            .run_execute_returning("INSERT INTO t (id, v) VALUES (2, 'b')", &[])
+            .unwrap();
+        assert_eq!(inserted.last_insert_id, Some(2));
+
+        let upserted = db
+            .run_execute_returning(
+                "INSERT INTO t (id, v) VALUES (1, 'c') ON CONFLICT (id) DO UPDATE SET v = excluded.v",
+                &[],
+            )
+            .unwrap();
+        assert_eq!(upserted.last_insert_id, None);
+        let ignored = db
+            .run_execute_returning("INSERT OR IGNORE INTO t (id, v) VALUES (1, 'd')", &[])
+            .unwrap();
+        assert_eq!(ignored.last_insert_id, None);
+    }
+
+    #[test]
+    fn bounded_queries_apply_schema_hints() {
+        let options = wit_sql::CallOptions {
+            max_duration_ms: None,
+            max_rows: Some(10),
+            max_bytes: None,
+        };
+        let schema = [wit_sql::ColumnHint {
+            name: "done".to_string(),
+            decode_as: wit_sql::SqlType::Boolean,
+        }];
+        let result = Database::default()
+            .run_query_with(
+                "SELECT 1 AS id, 1 AS done -- flagged",
+                &[],
+                &schema,
+                &options,
+            )
+            .unwrap();
+        assert_eq!(
+            result.rows[0].columns,
+            [
+                ("id".to_string(), wit_sql::SqlValue::Int64(1)),
+                ("done".to_string(), wit_sql::SqlValue::Boolean(true)),
+            ]
+        );
     }
 }
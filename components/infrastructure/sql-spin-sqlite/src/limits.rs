@@ -0,0 +1,249 @@
+//! Per-call `call-options` limits.
+//!
+//! Spin's SQLite API has no interrupt or progress handler, so nothing can stop
+//! a statement once it is sent. Limits are enforced around the host call
+//! instead, and only for plain queries: a statement that writes would have
+//! taken effect by the time a limit failed it, so setting any limit on one is
+//! refused up front.
+//!
+//! - `max-rows` runs a plain SELECT as `SELECT * FROM (..) LIMIT max + 1`,
+//!   as `query-stream` pages are (see [`subquery`](crate::subquery)), so the
+//!   host never returns more than one row past the limit. Other statements
+//!   are checked once they return.
+//! - `max-bytes` is summed over result values as they are converted.
+//! - `max-duration-ms` is a deadline checked before the statement, by every
+//!   busy retry, and after the statement returns. It is a post-hoc check,
+//!   not a timeout: a statement that overruns still completes, but its result
+//!   is discarded.
+//!
+//! Any of them fails the call with `limit-exceeded`.
+
+use std::time::{Duration, Instant};
+
+use spin_sdk::sqlite::{Error as SpinError, QueryResult as SpinQueryResult, Value as SpinValue};
+
+use crate::error::{self, codes};
+use crate::host::Connection;
+use crate::{policy, subquery, wit_sql};
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Limits {
+    deadline: Option<(Instant, Duration)>,
+    max_rows: Option<u32>,
+    max_bytes: Option<u64>,
+}
+
+impl Limits {
+    pub(crate) const NONE: Limits = Limits {
+        deadline: None,
+        max_rows: None,
+        max_bytes: None,
+    };
+
+    /// Limits for a call starting now.
+    pub(crate) fn start(options: &wit_sql::CallOptions) -> Self {
+        let max_duration = options
+            .max_duration_ms
+            .map(|ms| Duration::from_millis(u64::from(ms)));
+        Self {
+            deadline: max_duration.map(|d| (Instant::now() + d, d)),
+            max_rows: options.max_rows,
+            max_bytes: options.max_bytes,
+        }
+    }
+
+    /// Refuse limits on `sql` unless it is a plain query.
+    pub(crate) fn check_applies(&self, sql: &str) -> Result<(), wit_sql::SqlError> {
+        let set = self.deadline.is_some() || self.max_rows.is_some() || self.max_bytes.is_some();
+        if !set || policy::is_select(sql) {
+            return Ok(());
+        }
+        Err(wit_sql::SqlError::InvalidParameter(error::adapter_error(
+            codes::MISUSE,
+            "call-options limits apply only to SELECT, VALUES and WITH ... SELECT; \
+             a write would take effect before a limit could fail it"
+                .to_string(),
+            Some(sql),
+        )))
+    }
+
+    /// Run `sql`, bounded to one row past `max-rows` when it is a plain query.
+    pub(crate) fn execute(
+        &self,
+        conn: &Connection,
+        sql: &str,
+        values: &[SpinValue],
+    ) -> Result<SpinQueryResult, SpinError> {
+        match self.max_rows.filter(|_| policy::is_select(sql)) {
+            Some(max) => {
+                subquery::execute(conn, sql, &format!(" LIMIT {}", u64::from(max) + 1), values)
+            }
+            None => conn.execute(sql, values),
+        }
+    }
+
+    pub(crate) fn check_time(&self, sql: &str) -> Result<(), wit_sql::SqlError> {
+        match self.deadline {
+            Some((deadline, max)) if Instant::now() >= deadline => Err(exceeded(
+                wit_sql::LimitKind::Duration,
+                max.as_millis() as u64,
+                format!("call ran longer than {} ms", max.as_millis()),
+                sql,
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn check_rows(&self, rows: usize, sql: &str) -> Result<(), wit_sql::SqlError> {
+        match self.max_rows {
+            Some(max) if rows as u64 > u64::from(max) => Err(exceeded(
+                wit_sql::LimitKind::Rows,
+                u64::from(max),
+                format!("result has more than {max} rows"),
+                sql,
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Add `row`'s size to the running `total` and check it.
+    pub(crate) fn add_bytes(
+        &self,
+        total: &mut u64,
+        row: &[SpinValue],
+        sql: &str,
+    ) -> Result<(), wit_sql::SqlError> {
+        *total = row.iter().map(value_size).fold(*total, u64::saturating_add);
+        match self.max_bytes {
+            Some(max) if *total > max => Err(exceeded(
+                wit_sql::LimitKind::Bytes,
+                max,
+                format!("result is larger than {max} bytes"),
+                sql,
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn value_size(value: &SpinValue) -> u64 {
+    match value {
+        SpinValue::Null => 0,
+        SpinValue::Integer(_) | SpinValue::Real(_) => 8,
+        SpinValue::Text(s) => s.len() as u64,
+        SpinValue::Blob(b) => b.len() as u64,
+    }
+}
+
+fn exceeded(kind: wit_sql::LimitKind, limit: u64, message: String, sql: &str) -> wit_sql::SqlError {
+    let code = match kind {
+        wit_sql::LimitKind::Duration => codes::INTERRUPT,
+        wit_sql::LimitKind::Rows | wit_sql::LimitKind::Bytes => codes::TOOBIG,
+    };
+    wit_sql::SqlError::LimitExceeded(wit_sql::LimitDetails {
+        kind,
+        limit,
+        info: error::adapter_error(code, message, Some(sql)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(
+        max_duration_ms: Option<u32>,
+        max_rows: Option<u32>,
+        max_bytes: Option<u64>,
+    ) -> wit_sql::CallOptions {
+        wit_sql::CallOptions {
+            max_duration_ms,
+            max_rows,
+            max_bytes,
+        }
+    }
+
+    fn kind(result: Result<(), wit_sql::SqlError>) -> Option<wit_sql::LimitKind> {
+        match result {
+            Err(wit_sql::SqlError::LimitExceeded(details)) => Some(details.kind),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn plain_queries_stop_one_past_max_rows() {
+        let conn = Connection::open("limits").unwrap();
+        let sql = "WITH RECURSIVE n(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM n) \
+                   SELECT x FROM n";
+        let limits = Limits::start(&options(None, Some(100), None));
+        assert_eq!(limits.execute(&conn, sql, &[]).unwrap().rows.len(), 101);
+        let qr = Limits::NONE.execute(&conn, "SELECT 1", &[]).unwrap();
+        assert_eq!(qr.rows.len(), 1);
+    }
+
+    #[test]
+    fn limits_are_refused_on_writes() {
+        let limits = Limits::start(&options(None, Some(1), None));
+        assert!(limits.check_applies("SELECT id FROM t").is_ok());
+        for sql in [
+            "DELETE FROM t RETURNING id",
+            "UPDATE t SET a = 1 RETURNING a",
+        ] {
+            assert!(
+                matches!(
+                    limits.check_applies(sql),
+                    Err(wit_sql::SqlError::InvalidParameter(_))
+                ),
+                "{sql}"
+            );
+        }
+        assert!(
+            Limits::NONE
+                .check_applies("DELETE FROM t RETURNING id")
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn rows_over_the_limit_fail() {
+        let limits = Limits::start(&options(None, Some(2), None));
+        assert!(limits.check_rows(2, "q").is_ok());
+        assert_eq!(
+            kind(limits.check_rows(3, "q")),
+            Some(wit_sql::LimitKind::Rows)
+        );
+        assert!(Limits::NONE.check_rows(usize::MAX, "q").is_ok());
+    }
+
+    #[test]
+    fn bytes_accumulate_across_rows() {
+        let limits = Limits::start(&options(None, None, Some(20)));
+        let row = [
+            SpinValue::Integer(1),
+            SpinValue::Text("hello".to_string()),
+            SpinValue::Null,
+        ];
+        let mut total = 0;
+        assert!(limits.add_bytes(&mut total, &row, "q").is_ok());
+        assert_eq!(total, 13);
+        assert_eq!(
+            kind(limits.add_bytes(&mut total, &row, "q")),
+            Some(wit_sql::LimitKind::Bytes)
+        );
+    }
+
+    #[test]
+    fn deadline_passes() {
+        let limits = Limits::start(&options(Some(0), None, None));
+        let Err(wit_sql::SqlError::LimitExceeded(details)) = limits.check_time("q") else {
+            panic!("expected limit-exceeded");
+        };
+        assert_eq!(details.kind, wit_sql::LimitKind::Duration);
+        assert_eq!(details.info.code, codes::INTERRUPT);
+        assert!(
+            Limits::start(&options(Some(60_000), None, None))
+                .check_time("q")
+                .is_ok()
+        );
+    }
+}
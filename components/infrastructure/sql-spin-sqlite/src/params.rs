@@ -0,0 +1,226 @@
+//! Placeholder rewriting.
+//!
+//! SQLite reads `$1` as a parameter *named* "$1" and numbers it by first
+//! appearance, so `WHERE a = $2 AND b = $1` would bind backwards. Positional
+//! SQL therefore has `$N` rewritten to SQLite's `?N`, which keeps statements
+//! written for Postgres portable. Spin's host API only binds by position, so
+//! named parameters (`:name`, `@name`, `$name`) are rewritten to `?N` too.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use crate::error::{self, codes};
+use crate::lexer::{self, Kind, Placeholder};
+use crate::wit_sql;
+
+/// `sql` with `$N` placeholders rewritten to `?N`.
+pub(crate) fn normalize_positional(sql: &str) -> Cow<'_, str> {
+    let found = placeholders(sql);
+    if !found
+        .iter()
+        .any(|(_, p)| matches!(p, Placeholder::Dollar(_)))
+    {
+        return Cow::Borrowed(sql);
+    }
+    Cow::Owned(splice(sql, &found, |p| match p {
+        Placeholder::Dollar(n) => Some(format!("?{n}")),
+        _ => None,
+    }))
+}
+
+/// The number of parameters SQLite expects for `sql`: the highest index any
+/// placeholder gets, numbering `?` and first-seen names the way SQLite does.
+pub(crate) fn parameter_count(sql: &str) -> usize {
+    let mut names = BTreeMap::new();
+    let mut count = 0;
+    for (range, placeholder) in placeholders(sql) {
+        let index = match placeholder {
+            Placeholder::Question => sql[range.start + 1..range.end].parse().unwrap_or(count + 1),
+            Placeholder::Dollar(_) | Placeholder::Named(_) => {
+                *names.entry(&sql[range]).or_insert(count + 1)
+            }
+        };
+        count = count.max(index);
+    }
+    count
+}
+
+/// Rewrite named placeholders in `sql` to `?N` and order `named` to match.
+/// Names may be given with or without their sigil; every placeholder needs a
+/// value and every value must be used.
+pub(crate) fn bind_named(
+    sql: &str,
+    named: &[(String, wit_sql::SqlValue)],
+) -> Result<(String, Vec<wit_sql::SqlValue>), wit_sql::SqlError> {
+    let mut given = BTreeMap::new();
+    for (name, value) in named {
+        let bare = name.trim_start_matches([':', '@', '$']);
+        if given.insert(bare, value).is_some() {
+            return Err(invalid(
+                codes::MISUSE,
+                format!("named parameter `{bare}` given more than once"),
+                sql,
+            ));
+        }
+    }
+
+    let found = placeholders(sql);
+    let mut order: Vec<&str> = Vec::new();
+    for (_, p) in &found {
+        match p {
+            Placeholder::Named(name) => {
+                if !given.contains_key(name) {
+                    return Err(invalid(
+                        codes::RANGE,
+                        format!("no value for named parameter `{name}`"),
+                        sql,
+                    ));
+                }
+                if !order.contains(name) {
+                    order.push(name);
+                }
+            }
+            Placeholder::Question | Placeholder::Dollar(_) => {
+                return Err(invalid(
+                    codes::MISUSE,
+                    "cannot mix positional placeholders with named parameters".to_string(),
+                    sql,
+                ));
+            }
+        }
+    }
+    if let Some(unused) = given.keys().find(|name| !order.contains(name)) {
+        return Err(invalid(
+            codes::RANGE,
+            format!("named parameter `{unused}` is not used by the statement"),
+            sql,
+        ));
+    }
+
+    let rewritten = splice(sql, &found, |p| match p {
+        Placeholder::Named(name) => order
+            .iter()
+            .position(|n| n == name)
+            .map(|i| format!("?{}", i + 1)),
+        _ => None,
+    });
+    let values = order.iter().map(|name| given[name].clone()).collect();
+    Ok((rewritten, values))
+}
+
+fn invalid(code: i32, message: String, sql: &str) -> wit_sql::SqlError {
+    wit_sql::SqlError::InvalidParameter(error::adapter_error(code, message, Some(sql)))
+}
+
+/// Replace each placeholder `f` returns text for.
+fn splice(
+    sql: &str,
+    found: &[(Range<usize>, Placeholder<'_>)],
+    f: impl Fn(&Placeholder<'_>) -> Option<String>,
+) -> String {
+    let mut out = String::with_capacity(sql.len());
+    let mut copied = 0;
+    for (range, p) in found {
+        if let Some(text) = f(p) {
+            out.push_str(&sql[copied..range.start]);
+            out.push_str(&text);
+            copied = range.end;
+        }
+    }
+    out.push_str(&sql[copied..]);
+    out
+}
+
+/// Placeholders in `sql` outside quoted text and comments.
+fn placeholders(sql: &str) -> Vec<(Range<usize>, Placeholder<'_>)> {
+    lexer::tokens(sql)
+        .filter_map(|token| match token.kind {
+            Kind::Placeholder(p) => Some((token.range(), p)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wit_sql::SqlValue as W;
+
+    fn named(pairs: &[(&str, i64)]) -> Vec<(String, W)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), W::Int64(*v)))
+            .collect()
+    }
+
+    #[test]
+    fn dollar_placeholders_become_numbered() {
+        assert_eq!(
+            normalize_positional("SELECT * FROM t WHERE a = $2 AND b = $1"),
+            "SELECT * FROM t WHERE a = ?2 AND b = ?1"
+        );
+        assert!(matches!(
+            normalize_positional("SELECT ? , ?2"),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn parameters_are_counted_as_sqlite_numbers_them() {
+        assert_eq!(parameter_count("SELECT 1"), 0);
+        assert_eq!(parameter_count("SELECT ?, ?"), 2);
+        assert_eq!(parameter_count("SELECT ?3, ?"), 4);
+        assert_eq!(parameter_count("SELECT :a, @b, :a, $1"), 3);
+        assert_eq!(parameter_count("SELECT '?' -- ?\n, ?"), 1);
+    }
+
+    #[test]
+    fn quoted_text_and_comments_are_left_alone() {
+        let sql = "SELECT '$1', \"$2\", [$3] -- $4\n FROM t /* :x */ WHERE a = $1";
+        assert_eq!(
+            normalize_positional(sql),
+            "SELECT '$1', \"$2\", [$3] -- $4\n FROM t /* :x */ WHERE a = ?1"
+        );
+    }
+
+    #[test]
+    fn named_parameters_are_numbered_by_first_use() {
+        let (sql, values) = bind_named(
+            "UPDATE users SET email = :email WHERE id = @id OR alt = $email",
+            &named(&[("id", 7), (":email", 1)]),
+        )
+        .unwrap();
+        assert_eq!(sql, "UPDATE users SET email = ?1 WHERE id = ?2 OR alt = ?1");
+        assert_eq!(values, vec![W::Int64(1), W::Int64(7)]);
+    }
+
+    #[test]
+    fn identifiers_containing_sigils_are_not_parameters() {
+        let (sql, values) =
+            bind_named("SELECT a$b, x1 FROM t WHERE c = :c", &named(&[("c", 1)])).unwrap();
+        assert_eq!(sql, "SELECT a$b, x1 FROM t WHERE c = ?1");
+        assert_eq!(values.len(), 1);
+    }
+
+    #[test]
+    fn named_binding_reports_mistakes() {
+        let err = |sql: &str, pairs: &[(&str, i64)]| match bind_named(sql, &named(pairs)) {
+            Err(wit_sql::SqlError::InvalidParameter(info)) => (info.code, info.message),
+            other => panic!("expected invalid-parameter, got {other:?}"),
+        };
+        assert_eq!(
+            err("SELECT :a", &[]),
+            (codes::RANGE, "no value for named parameter `a`".into())
+        );
+        assert_eq!(
+            err("SELECT :a", &[("a", 1), ("b", 2)]),
+            (
+                codes::RANGE,
+                "named parameter `b` is not used by the statement".into()
+            )
+        );
+        assert_eq!(err("SELECT :a, ?", &[("a", 1)]).0, codes::MISUSE);
+        assert_eq!(err("SELECT :a", &[("a", 1), ("@a", 2)]).0, codes::MISUSE);
+    }
+}
@@ -0,0 +1,109 @@
+//! `EXPLAIN QUERY PLAN` inspection.
+//!
+//! Besides the `explain` call, setting `sql_warn_scan_rows` makes every
+//! statement get explained first and logs a `tracing` warning when its plan
+//! fully scans a table holding at least that many rows. It costs an extra
+//! round trip per statement, so it is meant for development.
+
+use spin_sdk::sqlite::Value as SpinValue;
+
+use crate::error::map_err;
+use crate::host::Connection;
+use crate::params::normalize_positional;
+use crate::wit_sql;
+
+pub(crate) fn explain_on(
+    conn: &Connection,
+    sql: &str,
+    values: &[SpinValue],
+) -> Result<Vec<wit_sql::PlanNode>, wit_sql::SqlError> {
+    let explain = format!("EXPLAIN QUERY PLAN {}", normalize_positional(sql));
+    let qr = conn
+        .execute(&explain, values)
+        .map_err(|e| map_err(e, "query", Some(sql)))?;
+    Ok(qr
+        .rows
+        .iter()
+        .map(|r| {
+            let detail = r.get::<&str>(3).unwrap_or_default().to_string();
+            wit_sql::PlanNode {
+                id: r.get::<i32>(0).unwrap_or(0),
+                parent: r.get::<i32>(1).unwrap_or(0),
+                full_scan: full_scan_table(&detail).map(str::to_string),
+                detail,
+            }
+        })
+        .collect())
+}
+
+/// Warn about full scans of tables with at least `threshold` rows. Failures
+/// are only logged; the statement itself still runs.
+pub(crate) fn warn_on_full_scans(
+    conn: &Connection,
+    sql: &str,
+    values: &[SpinValue],
+    threshold: u64,
+) {
+    let nodes = match explain_on(conn, sql, values) {
+        Ok(nodes) => nodes,
+        Err(e) => {
+            tracing::debug!(error = ?e, "could not explain statement");
+            return;
+        }
+    };
+    for table in nodes.iter().filter_map(|n| n.full_scan.as_deref()) {
+        // Counting stops at the threshold, so large tables stay cheap to check.
+        let count = format!(
+            "SELECT COUNT(*) FROM (SELECT 1 FROM \"{}\" LIMIT {threshold})",
+            table.replace('"', "\"\"")
+        );
+        let rows = conn
+            .execute(&count, &[])
+            .ok()
+            .and_then(|qr| qr.rows.first().and_then(|r| r.get::<u64>(0)));
+        if rows.is_some_and(|n| n >= threshold) {
+            tracing::warn!(table, threshold, sql, "query plan scans a large table");
+        }
+    }
+}
+
+/// The table named by a `SCAN` plan line, whether or not it reads through a
+/// covering index. Subqueries and constant rows return `None`; newer SQLite
+/// names aliased tables and CTEs by their alias, which the size check skips.
+pub(crate) fn full_scan_table(detail: &str) -> Option<&str> {
+    let rest = detail.strip_prefix("SCAN ")?;
+    // SQLite before 3.36 wrote `SCAN TABLE t`.
+    let rest = rest.strip_prefix("TABLE ").unwrap_or(rest);
+    let table = rest.split_whitespace().next()?;
+    let not_a_table =
+        table.starts_with('(') || rest.starts_with("SUBQUERY") || rest.starts_with("CONSTANT ROW");
+    (!not_a_table).then_some(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_scans_name_their_table() {
+        assert_eq!(full_scan_table("SCAN users"), Some("users"));
+        assert_eq!(full_scan_table("SCAN TABLE users"), Some("users"));
+        assert_eq!(
+            full_scan_table("SCAN users USING COVERING INDEX users_email"),
+            Some("users")
+        );
+        assert_eq!(full_scan_table("SCAN TABLE users AS u"), Some("users"));
+    }
+
+    #[test]
+    fn searches_and_non_tables_are_not_full_scans() {
+        assert_eq!(
+            full_scan_table("SEARCH users USING INDEX users_email (email=?)"),
+            None
+        );
+        assert_eq!(full_scan_table("SCAN CONSTANT ROW"), None);
+        assert_eq!(full_scan_table("SCAN SUBQUERY 1"), None);
+        assert_eq!(full_scan_table("SCAN (subquery-1)"), None);
+        assert_eq!(full_scan_table("USE TEMP B-TREE FOR ORDER BY"), None);
+    }
+}
@@ -0,0 +1,305 @@
+//! Statement policy: read-only mode and the statement allow-list.
+//!
+//! With `sql_read_only` set, only SELECT, VALUES, EXPLAIN and WITH ... SELECT
+//! statements are accepted. The classification is lexical, so every
+//! connection is also opened with `PRAGMA query_only = ON`, which makes
+//! SQLite itself refuse any write the classification lets through.
+//!
+//! With `sql_allowed_statements` set, a statement runs only when its
+//! fingerprint matches one of the listed statements; literal values and
+//! placeholder styles may differ, anything else may not.
+//!
+//! With `sql_tenant_required` set, ATTACH and DETACH are refused as well; see
+//! [`crate::tenant`].
+//!
+//! All of these apply to guest SQL only. Transaction control, savepoints and the
+//! adapter's own introspection queries are not checked.
+
+use std::collections::BTreeSet;
+
+use crate::config::Config;
+use crate::error::{self, codes};
+use crate::lexer::{self, Kind};
+use crate::{batch, telemetry, tenant, wit_sql};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct StatementPolicy {
+    pub read_only: bool,
+    /// Fingerprints of the permitted statements; `None` permits any.
+    pub allowed: Option<BTreeSet<String>>,
+}
+
+impl StatementPolicy {
+    /// The fingerprints of a `;`-separated list of statements.
+    pub(crate) fn allow_list(script: &str) -> BTreeSet<String> {
+        batch::split_script(script)
+            .into_iter()
+            .map(telemetry::fingerprint)
+            .collect()
+    }
+
+    /// Reject `sql`, or any statement of it, that the policy does not permit.
+    pub(crate) fn check(&self, sql: &str) -> Result<(), wit_sql::SqlError> {
+        if !self.read_only && self.allowed.is_none() {
+            return Ok(());
+        }
+        for statement in batch::split_script(sql) {
+            if self.read_only
+                && let Some(keyword) = writing_keyword(statement)
+            {
+                return Err(denied(
+                    codes::READONLY,
+                    format!("read-only mode rejects {keyword} statements"),
+                    statement,
+                ));
+            }
+            if let Some(allowed) = &self.allowed {
+                let fingerprint = telemetry::fingerprint(statement);
+                if !allowed.contains(&fingerprint) {
+                    return Err(denied(
+                        codes::AUTH,
+                        format!("statement is not in the allow-list: {fingerprint}"),
+                        statement,
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check `sql` against the configured policy and, when tenant scoping is
+/// required, refuse statements that could reach another database.
+pub(crate) fn check(sql: &str) -> Result<(), wit_sql::SqlError> {
+    let config = Config::get();
+    config.statements.check(sql)?;
+    if config.tenant_required {
+        for statement in batch::split_script(sql) {
+            if let Some((_, keyword)) = words(statement).first() {
+                tenant::check_statement(statement, keyword)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn denied(code: i32, message: String, statement: &str) -> wit_sql::SqlError {
+    wit_sql::SqlError::QueryFailed(error::adapter_error(code, message, Some(statement)))
+}
+
+/// Whether `statement` is a plain query (SELECT, VALUES or WITH ... SELECT)
+/// that can be wrapped as a subquery.
+pub(crate) fn is_select(statement: &str) -> bool {
+    main_keyword(statement)
+        .is_some_and(|k| k.eq_ignore_ascii_case("SELECT") || k.eq_ignore_ascii_case("VALUES"))
+}
+
+/// The keyword naming what `statement` does, uppercased, unless it only
+/// reads.
+fn writing_keyword(statement: &str) -> Option<String> {
+    let main = main_keyword(statement)?;
+    let reads = ["SELECT", "VALUES", "EXPLAIN"]
+        .iter()
+        .any(|k| main.eq_ignore_ascii_case(k));
+    (!reads).then(|| main.to_ascii_uppercase())
+}
+
+/// The leading keyword of `statement`, looking through a WITH clause to its
+/// main statement.
+pub(crate) fn main_keyword(statement: &str) -> Option<&str> {
+    let words = words(statement);
+    let (depth, first) = *words.first()?;
+    let main = if first.eq_ignore_ascii_case("WITH") {
+        words
+            .iter()
+            .filter(|(d, _)| *d == depth)
+            .map(|(_, w)| *w)
+            .find(|w| {
+                ["SELECT", "VALUES", "INSERT", "REPLACE", "UPDATE", "DELETE"]
+                    .iter()
+                    .any(|k| w.eq_ignore_ascii_case(k))
+            })
+            .unwrap_or(first)
+    } else {
+        first
+    };
+    Some(main)
+}
+
+/// Whether `statement` is a REPLACE or INSERT OR REPLACE, which deletes any
+/// conflicting row and always inserts.
+pub(crate) fn replaces(statement: &str) -> bool {
+    let words = words(statement);
+    main_keyword(statement).is_some_and(|k| k.eq_ignore_ascii_case("REPLACE"))
+        || words.windows(3).any(|w| {
+            w[0].1.eq_ignore_ascii_case("INSERT")
+                && w[1].1.eq_ignore_ascii_case("OR")
+                && w[2].1.eq_ignore_ascii_case("REPLACE")
+        })
+}
+
+/// Bare words of `sql` with their parenthesis depth, skipping quoted text,
+/// comments and placeholders.
+pub(crate) fn words(sql: &str) -> Vec<(usize, &str)> {
+    let mut depth = 0_usize;
+    let mut words = Vec::new();
+    for token in lexer::tokens(sql) {
+        match (token.kind, token.text) {
+            (Kind::Symbol, "(") => depth += 1,
+            (Kind::Symbol, ")") => depth = depth.saturating_sub(1),
+            (Kind::Word, word) => words.push((depth, word)),
+            _ => {}
+        }
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Database, connection};
+
+    fn read_only() -> StatementPolicy {
+        StatementPolicy {
+            read_only: true,
+            allowed: None,
+        }
+    }
+
+    fn code(result: Result<(), wit_sql::SqlError>) -> i32 {
+        match result {
+            Err(wit_sql::SqlError::QueryFailed(info)) => info.code,
+            other => panic!("expected query-failed, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn read_only_accepts_reads() {
+        let policy = read_only();
+        for sql in [
+            "SELECT * FROM users",
+            "  -- comment\n select 1",
+            "VALUES (1), (2)",
+            "EXPLAIN QUERY PLAN DELETE FROM users",
+            "WITH recent AS (SELECT * FROM events) SELECT count(*) FROM recent",
+            "WITH RECURSIVE n(x) AS (VALUES (1) UNION ALL SELECT x + 1 FROM n) SELECT x FROM n",
+        ] {
+            assert!(policy.check(sql).is_ok(), "{sql}");
+        }
+    }
+
+    #[test]
+    fn read_only_rejects_writes_anywhere_in_the_sql() {
+        let policy = read_only();
+        for sql in [
+            "DROP TABLE users",
+            "delete from users where id = ?",
+            "WITH gone AS (SELECT id FROM users) DELETE FROM users WHERE id IN gone",
+            "SELECT 1; UPDATE users SET name = 'x'",
+            "PRAGMA foreign_keys = OFF",
+            "ATTACH 'other.db' AS other",
+        ] {
+            assert_eq!(code(policy.check(sql)), codes::READONLY, "{sql}");
+        }
+        let Err(wit_sql::SqlError::QueryFailed(info)) = policy.check("DROP TABLE users") else {
+            unreachable!();
+        };
+        assert_eq!(info.message, "read-only mode rejects DROP statements");
+    }
+
+    #[test]
+    fn allow_list_matches_by_fingerprint() {
+        let policy = StatementPolicy {
+            read_only: false,
+            allowed: Some(StatementPolicy::allow_list(
+                "SELECT name FROM users WHERE id = ?;\n\
+                 INSERT INTO audit (event) VALUES ('login');",
+            )),
+        };
+        assert!(policy.check("SELECT name FROM users WHERE id = 42").is_ok());
+        assert!(
+            policy
+                .check("SELECT name FROM users WHERE id = :id")
+                .is_ok()
+        );
+        assert!(policy.check("select name from users where id = ?").is_err());
+        assert!(
+            policy
+                .check("INSERT INTO audit (event)\n  VALUES ($1)")
+                .is_ok()
+        );
+        assert_eq!(
+            code(policy.check("SELECT name FROM users WHERE id = ? OR 1 = 1")),
+            codes::AUTH
+        );
+        assert_eq!(
+            code(policy.check("SELECT 1; DELETE FROM audit")),
+            codes::AUTH
+        );
+    }
+
+    #[test]
+    fn plain_queries_are_selects() {
+        assert!(is_select("SELECT * FROM t ORDER BY id"));
+        assert!(is_select("values (1)"));
+        assert!(is_select("WITH x AS (SELECT 1) SELECT * FROM x"));
+        assert!(!is_select("EXPLAIN SELECT 1"));
+        assert!(!is_select("PRAGMA table_info(t)"));
+        assert!(!is_select("DELETE FROM t RETURNING id"));
+        assert!(!is_select("WITH x AS (SELECT 1) DELETE FROM t"));
+    }
+
+    #[test]
+    fn read_only_mode_guards_every_call() {
+        Config::set_for_thread(Some(Config {
+            statements: read_only(),
+            ..Config::default()
+        }));
+        let db = Database::connect("policy_read_only".to_string()).unwrap();
+        assert!(db.run_query("SELECT 1", &[], &[]).is_ok());
+        let Err(wit_sql::SqlError::QueryFailed(info)) =
+            db.run_query("DROP TABLE IF EXISTS t", &[], &[])
+        else {
+            panic!("DROP ran through query");
+        };
+        assert_eq!(info.code, codes::READONLY);
+        // Writes that get past the lexical check meet `PRAGMA query_only`.
+        let unchecked = connection::with_shared("policy_read_only", |conn| {
+            conn.execute("CREATE TABLE t (a INTEGER)", &[])
+                .map_err(|e| error::map_err(e, "query", None))
+        });
+        assert!(unchecked.is_err());
+    }
+
+    #[test]
+    fn allow_list_mode_refuses_unlisted_statements() {
+        Config::set_for_thread(Some(Config {
+            statements: StatementPolicy {
+                read_only: false,
+                allowed: Some(StatementPolicy::allow_list(
+                    "DROP TABLE IF EXISTS t; CREATE TABLE t (a INTEGER); \
+                     SELECT a FROM t WHERE a = ?",
+                )),
+            },
+            ..Config::default()
+        }));
+        let db = Database::connect("policy_allow_list".to_string()).unwrap();
+        db.run_execute("DROP TABLE IF EXISTS t", &[]).unwrap();
+        db.run_execute("CREATE TABLE t (a INTEGER)", &[]).unwrap();
+        assert!(
+            db.run_query("SELECT a FROM t WHERE a = 2", &[], &[])
+                .is_ok()
+        );
+        let Err(wit_sql::SqlError::QueryFailed(info)) = db.run_query("SELECT a FROM t", &[], &[])
+        else {
+            panic!("an unlisted statement ran");
+        };
+        assert_eq!(info.code, codes::AUTH);
+        assert!(db.run_execute("DELETE FROM t", &[]).is_err());
+    }
+
+    #[test]
+    fn no_policy_accepts_anything() {
+        assert!(StatementPolicy::default().check("DROP TABLE users").is_ok());
+    }
+}
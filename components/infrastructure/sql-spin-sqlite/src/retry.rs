@@ -0,0 +1,178 @@
+//! Retry with exponential backoff for transient lock contention.
+//!
+//! Only `SQLITE_BUSY` and `SQLITE_LOCKED` are retried, and only for statements
+//! running in autocommit mode: inside an explicit transaction a retry could
+//! replay half of the guest's unit of work, so the error is surfaced instead.
+
+use std::time::Duration;
+
+use crate::error::codes;
+use crate::wit_sql;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RetryPolicy {
+    /// Total attempts including the first; 1 disables retries.
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(5),
+            max_backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before attempt `retry + 1`, doubling from `initial_backoff`.
+    pub(crate) fn backoff(&self, retry: u32) -> Duration {
+        let factor = 1u32
+            .checked_shl(retry.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+
+    /// Run `op`, retrying busy/locked failures with `sleep` between attempts.
+    pub(crate) fn run<T>(
+        &self,
+        mut op: impl FnMut() -> Result<T, wit_sql::SqlError>,
+        mut sleep: impl FnMut(Duration),
+    ) -> Result<T, wit_sql::SqlError> {
+        let mut retry = 0;
+        loop {
+            match op() {
+                Err(e) if retry + 1 < self.max_attempts && is_retryable(&e) => {
+                    retry += 1;
+                    #[cfg(test)]
+                    RETRIES.set(RETRIES.get() + 1);
+                    let delay = self.backoff(retry);
+                    tracing::debug!(retry, ?delay, "retrying after lock contention");
+                    sleep(delay);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    static RETRIES: std::cell::Cell<u32> = const { std::cell::Cell::new(0) };
+}
+
+/// Retries made on this thread since the last call. Tests use this to see
+/// whether a call against a locked database was retried.
+#[cfg(test)]
+pub(crate) fn take_retries() -> u32 {
+    RETRIES.take()
+}
+
+pub(crate) fn is_retryable(e: &wit_sql::SqlError) -> bool {
+    match e {
+        wit_sql::SqlError::ConnectionFailed(info)
+        | wit_sql::SqlError::QueryFailed(info)
+        | wit_sql::SqlError::TransactionFailed(info) => {
+            matches!(info.code, codes::BUSY | codes::LOCKED)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error;
+    use std::cell::Cell;
+
+    fn failure(code: i32) -> wit_sql::SqlError {
+        wit_sql::SqlError::QueryFailed(error::adapter_error(code, "failure", None))
+    }
+
+    fn policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(35),
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_and_caps() {
+        let p = policy(10);
+        let delays: Vec<_> = (1..=4).map(|r| p.backoff(r).as_millis()).collect();
+        assert_eq!(delays, vec![10, 20, 35, 35]);
+        assert_eq!(p.backoff(64), Duration::from_millis(35));
+    }
+
+    #[test]
+    fn retries_busy_until_success() {
+        let calls = Cell::new(0);
+        let mut slept = Vec::new();
+        let result = policy(3).run(
+            || {
+                calls.set(calls.get() + 1);
+                if calls.get() < 3 {
+                    Err(failure(codes::BUSY))
+                } else {
+                    Ok(calls.get())
+                }
+            },
+            |d| slept.push(d.as_millis()),
+        );
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(slept, vec![10, 20]);
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let calls = Cell::new(0);
+        let result: Result<(), _> = policy(2).run(
+            || {
+                calls.set(calls.get() + 1);
+                Err(failure(codes::LOCKED))
+            },
+            |_| {},
+        );
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn does_not_retry_other_errors() {
+        for e in [
+            failure(codes::ERROR),
+            failure(codes::CONSTRAINT),
+            wit_sql::SqlError::NotFound,
+        ] {
+            let calls = Cell::new(0);
+            let result: Result<(), _> = policy(5).run(
+                || {
+                    calls.set(calls.get() + 1);
+                    Err(e.clone())
+                },
+                |_| panic!("should not sleep"),
+            );
+            assert!(result.is_err());
+            assert_eq!(calls.get(), 1);
+        }
+    }
+
+    #[test]
+    fn single_attempt_disables_retries() {
+        let calls = Cell::new(0);
+        let _ = policy(1).run(
+            || -> Result<(), _> {
+                calls.set(calls.get() + 1);
+                Err(failure(codes::BUSY))
+            },
+            |_| panic!("should not sleep"),
+        );
+        assert_eq!(calls.get(), 1);
+    }
+}
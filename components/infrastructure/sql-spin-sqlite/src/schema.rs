@@ -0,0 +1,199 @@
+//! Schema introspection through `sqlite_master` and the table-valued PRAGMA
+//! functions, which take the table name as a bound parameter rather than
+//! spliced-in text.
+
+use spin_sdk::sqlite::{QueryResult as SpinQueryResult, Value as SpinValue};
+
+use crate::error::map_err;
+use crate::host::Connection;
+use crate::wit_sql;
+
+pub(crate) fn list_tables_on(conn: &Connection) -> Result<Vec<String>, wit_sql::SqlError> {
+    let qr = run(
+        conn,
+        "SELECT name FROM sqlite_master \
+         WHERE type = 'table' AND name NOT LIKE 'sqlite\\_%' ESCAPE '\\' ORDER BY name",
+        &[],
+    )?;
+    Ok(qr.rows.iter().map(|r| text(r.get(0))).collect())
+}
+
+pub(crate) fn describe_table_on(
+    conn: &Connection,
+    name: &str,
+) -> Result<wit_sql::TableInfo, wit_sql::SqlError> {
+    let table = [SpinValue::Text(name.to_string())];
+    let columns = run(
+        conn,
+        "SELECT name, type, \"notnull\", dflt_value, pk FROM pragma_table_info(?) ORDER BY cid",
+        &table,
+    )?;
+    if columns.rows.is_empty() {
+        return Err(wit_sql::SqlError::NotFound);
+    }
+    let columns = columns
+        .rows
+        .iter()
+        .map(|r| wit_sql::ColumnInfo {
+            name: text(r.get(0)),
+            declared_type: text(r.get(1)),
+            not_null: r.get::<i64>(2).unwrap_or(0) != 0,
+            default_value: r.get::<&str>(3).map(str::to_string),
+            primary_key: r.get::<u32>(4).unwrap_or(0),
+        })
+        .collect();
+    let keys = run(
+        conn,
+        "SELECT id, \"table\", \"from\", \"to\", on_update, on_delete \
+         FROM pragma_foreign_key_list(?) ORDER BY id, seq",
+        &table,
+    )?;
+    let keys = keys
+        .rows
+        .iter()
+        .map(|r| ForeignKeyRow {
+            id: r.get::<i64>(0).unwrap_or(0),
+            table: text(r.get(1)),
+            from: text(r.get(2)),
+            to: r.get::<&str>(3).map(str::to_string),
+            on_update: text(r.get(4)),
+            on_delete: text(r.get(5)),
+        })
+        .collect();
+    Ok(wit_sql::TableInfo {
+        name: name.to_string(),
+        columns,
+        foreign_keys: group_foreign_keys(keys),
+    })
+}
+
+pub(crate) fn list_indexes_on(
+    conn: &Connection,
+    table: &str,
+) -> Result<Vec<wit_sql::IndexInfo>, wit_sql::SqlError> {
+    let indexes = run(
+        conn,
+        "SELECT name, \"unique\", origin, partial FROM pragma_index_list(?) ORDER BY name",
+        &[SpinValue::Text(table.to_string())],
+    )?;
+    indexes
+        .rows
+        .iter()
+        .map(|r| {
+            let name = text(r.get(0));
+            let columns = run(
+                conn,
+                "SELECT name FROM pragma_index_info(?) ORDER BY seqno",
+                &[SpinValue::Text(name.clone())],
+            )?
+            .rows
+            .iter()
+            .map(|c| text(c.get(0)))
+            .collect();
+            Ok(wit_sql::IndexInfo {
+                name,
+                unique: r.get::<i64>(1).unwrap_or(0) != 0,
+                origin: index_origin(r.get(2).unwrap_or("c")),
+                partial: r.get::<i64>(3).unwrap_or(0) != 0,
+                columns,
+            })
+        })
+        .collect()
+}
+
+fn run(
+    conn: &Connection,
+    sql: &str,
+    params: &[SpinValue],
+) -> Result<SpinQueryResult, wit_sql::SqlError> {
+    conn.execute(sql, params)
+        .map_err(|e| map_err(e, "query", Some(sql)))
+}
+
+fn text(v: Option<&str>) -> String {
+    v.unwrap_or_default().to_string()
+}
+
+fn index_origin(origin: &str) -> wit_sql::IndexOrigin {
+    match origin {
+        "u" => wit_sql::IndexOrigin::UniqueConstraint,
+        "pk" => wit_sql::IndexOrigin::PrimaryKey,
+        _ => wit_sql::IndexOrigin::Explicit,
+    }
+}
+
+/// One row of `PRAGMA foreign_key_list`; composite keys span several rows
+/// sharing an `id`.
+#[derive(Debug, Clone)]
+struct ForeignKeyRow {
+    id: i64,
+    table: String,
+    from: String,
+    to: Option<String>,
+    on_update: String,
+    on_delete: String,
+}
+
+fn group_foreign_keys(rows: Vec<ForeignKeyRow>) -> Vec<wit_sql::ForeignKeyInfo> {
+    let mut keys: Vec<(i64, wit_sql::ForeignKeyInfo)> = Vec::new();
+    for row in rows {
+        let key = match keys.last_mut() {
+            Some((id, key)) if *id == row.id => key,
+            _ => {
+                keys.push((
+                    row.id,
+                    wit_sql::ForeignKeyInfo {
+                        columns: Vec::new(),
+                        referenced_table: row.table,
+                        referenced_columns: Vec::new(),
+                        on_update: row.on_update,
+                        on_delete: row.on_delete,
+                    },
+                ));
+                &mut keys.last_mut().expect("just pushed").1
+            }
+        };
+        key.columns.push(row.from);
+        key.referenced_columns.extend(row.to);
+    }
+    keys.into_iter().map(|(_, key)| key).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fk(id: i64, table: &str, from: &str, to: Option<&str>) -> ForeignKeyRow {
+        ForeignKeyRow {
+            id,
+            table: table.to_string(),
+            from: from.to_string(),
+            to: to.map(str::to_string),
+            on_update: "NO ACTION".to_string(),
+            on_delete: "CASCADE".to_string(),
+        }
+    }
+
+    #[test]
+    fn composite_foreign_keys_are_grouped_by_id() {
+        let keys = group_foreign_keys(vec![
+            fk(0, "orgs", "org_id", None),
+            fk(1, "memberships", "org_id", Some("org")),
+            fk(1, "memberships", "user_id", Some("user")),
+        ]);
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].referenced_table, "orgs");
+        assert_eq!(keys[0].columns, vec!["org_id"]);
+        assert!(keys[0].referenced_columns.is_empty());
+        assert_eq!(keys[1].columns, vec!["org_id", "user_id"]);
+        assert_eq!(keys[1].referenced_columns, vec!["org", "user"]);
+        assert_eq!(keys[1].on_delete, "CASCADE");
+    }
+
+    #[test]
+    fn index_origins_map_from_pragma_codes() {
+        assert_eq!(index_origin("c"), wit_sql::IndexOrigin::Explicit);
+        assert_eq!(index_origin("u"), wit_sql::IndexOrigin::UniqueConstraint);
+        assert_eq!(index_origin("pk"), wit_sql::IndexOrigin::PrimaryKey);
+    }
+}
@@ -0,0 +1,121 @@
+//! The `statement` resource.
+//!
+//! Spin's host API takes SQL text on every call, so "preparing" does the
+//! per-statement work once: the policy check and placeholder normalization
+//! run in `prepare`, and the first prepare of a text on a connection compiles
+//! it with `EXPLAIN` to surface syntax and schema errors early (and checks
+//! its plan when `sql_warn_scan_rows` is set). The text is cached per
+//! connection so repeat prepares of a hot-path query skip that step.
+//! Bindings are converted and checked when `bind` is called, so `query` and
+//! `execute` only send the statement.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use spin_sdk::sqlite::Value as SpinValue;
+
+use crate::config::Config;
+use crate::error::map_err;
+use crate::limits::Limits;
+use crate::params::{normalize_positional, parameter_count};
+use crate::values::values_from;
+use crate::{connection, policy, telemetry, wit_sql};
+
+pub(crate) struct Statement {
+    label: String,
+    sql: Rc<str>,
+    bound: RefCell<Bound>,
+}
+
+/// The bound parameters, as given (for telemetry) and as sent to the host.
+#[derive(Default)]
+struct Bound {
+    params: Vec<wit_sql::SqlValue>,
+    values: Vec<SpinValue>,
+}
+
+impl Statement {
+    pub(crate) fn prepare(label: &str, sql: &str) -> Result<Self, wit_sql::SqlError> {
+        policy::check(sql)?;
+        let sql = normalize_positional(sql);
+        let sql = connection::prepared(label, &sql, || {
+            // Unbound parameters read as NULL, which is all compiling needs.
+            let nulls = vec![SpinValue::Null; parameter_count(&sql)];
+            let explain = format!("EXPLAIN {sql}");
+            connection::with_shared(label, |conn| {
+                conn.execute(&explain, &nulls)
+                    .map_err(|e| map_err(e, "query", Some(&sql)))?;
+                crate::check_plan(conn, &sql, &nulls);
+                Ok(())
+            })
+        })?;
+        Ok(Self {
+            label: label.to_string(),
+            sql,
+            bound: RefCell::default(),
+        })
+    }
+}
+
+impl wit_sql::GuestStatement for Statement {
+    fn bind(&self, params: Vec<wit_sql::SqlValue>) -> Result<(), wit_sql::SqlError> {
+        let values = values_from(&params, Config::get().uuid_storage)?;
+        *self.bound.borrow_mut() = Bound { params, values };
+        Ok(())
+    }
+
+    fn query(&self) -> Result<wit_sql::QueryResult, wit_sql::SqlError> {
+        let bound = self.bound.borrow();
+        telemetry::observe("query", &self.label, &self.sql, &bound.params, || {
+            connection::with_shared(&self.label, |conn| {
+                crate::exec_prepared_query_on(conn, &self.sql, &bound.values, &[], &Limits::NONE)
+            })
+        })
+    }
+
+    fn execute(&self) -> Result<u64, wit_sql::SqlError> {
+        let bound = self.bound.borrow();
+        telemetry::observe("execute", &self.label, &self.sql, &bound.params, || {
+            connection::with_shared(&self.label, |conn| {
+                crate::exec_prepared_execute_on(conn, &self.sql, &bound.values)
+                    .map(|r| r.rows_affected)
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wit_sql::GuestStatement;
+
+    #[test]
+    fn prepared_statements_bind_dollar_placeholders() {
+        connection::with_shared("prepared", |conn| {
+            conn.execute("DROP TABLE IF EXISTS t", &[]).unwrap();
+            conn.execute("CREATE TABLE t (a INTEGER, b TEXT)", &[])
+                .unwrap();
+            Ok(())
+        })
+        .unwrap();
+        let insert =
+            Statement::prepare("prepared", "INSERT INTO t (b, a) VALUES ($2, $1)").unwrap();
+        for (a, b) in [(1, "one"), (2, "two")] {
+            insert
+                .bind(vec![
+                    wit_sql::SqlValue::Int64(a),
+                    wit_sql::SqlValue::Text(b.to_string()),
+                ])
+                .unwrap();
+            assert_eq!(insert.execute().unwrap(), 1);
+        }
+        let select = Statement::prepare("prepared", "SELECT b FROM t WHERE a = $1").unwrap();
+        select.bind(vec![wit_sql::SqlValue::Int64(2)]).unwrap();
+        let rows = select.query().unwrap().rows;
+        assert_eq!(rows.len(), 1);
+        assert!(matches!(
+            &rows[0].columns[0],
+            (name, wit_sql::SqlValue::Text(b)) if name == "b" && b == "two"
+        ));
+    }
+}
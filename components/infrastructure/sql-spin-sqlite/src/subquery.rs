@@ -0,0 +1,81 @@
+//! Guest queries run as subqueries.
+//!
+//! `max-rows` limits and `query-stream` pages run the guest's query as
+//! `SELECT * FROM (<sql>) <tail>`, since a LIMIT or a keyset filter cannot be
+//! spliced into arbitrary SQL. Two things keep the wrapped query's result the
+//! same as the bare query's:
+//!
+//! - the subquery closes on its own line, so a trailing `--` comment in the
+//!   guest's SQL cannot swallow the closing parenthesis;
+//! - SQLite names duplicate result columns of a subquery apart (a second `id`
+//!   comes back as `id:1`, or `id:<n>`), so those names are given back.
+
+use spin_sdk::sqlite::{Error as SpinError, QueryResult as SpinQueryResult, Value as SpinValue};
+
+use crate::host::Connection;
+
+/// Run `sql` wrapped as `SELECT * FROM (sql) tail`.
+pub(crate) fn execute(
+    conn: &Connection,
+    sql: &str,
+    tail: &str,
+    values: &[SpinValue],
+) -> Result<SpinQueryResult, SpinError> {
+    let mut qr = conn.execute(&wrap(sql, tail), values)?;
+    restore_names(&mut qr.columns);
+    Ok(qr)
+}
+
+fn wrap(sql: &str, tail: &str) -> String {
+    format!(
+        "SELECT * FROM ({}\n){tail}",
+        sql.trim().trim_end_matches(';')
+    )
+}
+
+/// Undo SQLite's renaming of duplicate columns: `name:<digits>` after an
+/// earlier `name` becomes `name` again.
+fn restore_names(columns: &mut [String]) {
+    for i in 1..columns.len() {
+        let renamed = columns[i].rsplit_once(':').and_then(|(base, n)| {
+            let numbered = !n.is_empty() && n.bytes().all(|c| c.is_ascii_digit());
+            (numbered && columns[..i].iter().any(|c| c == base)).then(|| base.to_string())
+        });
+        if let Some(base) = renamed {
+            columns[i] = base;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_query_is_wrapped_with_its_tail() {
+        assert_eq!(
+            wrap("SELECT id FROM t ORDER BY id;", " LIMIT 101"),
+            "SELECT * FROM (SELECT id FROM t ORDER BY id\n) LIMIT 101"
+        );
+    }
+
+    #[test]
+    fn queries_may_end_in_a_line_comment() {
+        let conn = Connection::open("subquery").unwrap();
+        let qr = execute(&conn, "SELECT 1 AS n -- one row", " LIMIT 1", &[]).unwrap();
+        assert_eq!(qr.rows.len(), 1);
+    }
+
+    #[test]
+    fn duplicate_columns_keep_their_names() {
+        let sql = "SELECT 1 AS id, 2 AS id, 3 AS id, 4 AS \"n:1\"";
+        let conn = Connection::open("subquery").unwrap();
+        assert_eq!(
+            conn.execute(&wrap(sql, ""), &[]).unwrap().columns[1],
+            "id:1"
+        );
+        let columns = execute(&conn, sql, "", &[]).unwrap().columns;
+        assert_eq!(columns, ["id", "id", "id", "n:1"]);
+        assert_eq!(columns, conn.execute(sql, &[]).unwrap().columns);
+    }
+}
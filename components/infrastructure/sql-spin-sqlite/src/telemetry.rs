@@ -0,0 +1,328 @@
+//! Tracing spans and in-instance metrics for every database call.
+//!
+//! Each call runs inside an `sql` span carrying the operation, the statement
+//! fingerprint and parameter count, and records the row count, duration and
+//! error kind once it finishes. The same numbers feed per-operation and
+//! per-fingerprint counters returned by `metrics`, and calls over the slow
+//! query threshold go to the log in [`crate::diagnostics`]. Both live as long
+//! as the component instance, which for Spin is usually one request.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use tracing::field::Empty;
+
+use crate::config::Config;
+use crate::lexer::{self, Kind};
+use crate::{diagnostics, error, wit_sql};
+
+/// Upper bounds, in microseconds, of the latency histogram buckets.
+const BUCKET_BOUNDS_US: [u64; 8] = [100, 500, 1_000, 5_000, 10_000, 50_000, 100_000, 1_000_000];
+
+/// Distinct fingerprints tracked; later ones only count toward their operation.
+const MAX_STATEMENTS: usize = 256;
+
+thread_local! {
+    static METRICS: RefCell<Registry> = RefCell::default();
+}
+
+/// Rows a call produced or touched, for the span and the counters.
+pub(crate) trait RowCount {
+    fn row_count(&self) -> u64;
+}
+
+impl RowCount for () {
+    fn row_count(&self) -> u64 {
+        0
+    }
+}
+
+impl RowCount for u64 {
+    fn row_count(&self) -> u64 {
+        *self
+    }
+}
+
+impl RowCount for Vec<u64> {
+    fn row_count(&self) -> u64 {
+        self.iter().sum()
+    }
+}
+
+impl RowCount for Vec<wit_sql::SqlRow> {
+    fn row_count(&self) -> u64 {
+        self.len() as u64
+    }
+}
+
+impl RowCount for wit_sql::QueryResult {
+    fn row_count(&self) -> u64 {
+        self.rows.len() as u64
+    }
+}
+
+impl RowCount for wit_sql::ColumnarResult {
+    fn row_count(&self) -> u64 {
+        self.rows.len() as u64
+    }
+}
+
+impl RowCount for wit_sql::ExecuteResult {
+    fn row_count(&self) -> u64 {
+        self.rows_affected
+    }
+}
+
+/// Run `f` as operation `op` of `sql` on database `label`, tracing and
+/// counting the call and logging it if it was slow.
+pub(crate) fn observe<T: RowCount>(
+    op: &'static str,
+    label: &str,
+    sql: &str,
+    params: &[wit_sql::SqlValue],
+    f: impl FnOnce() -> Result<T, wit_sql::SqlError>,
+) -> Result<T, wit_sql::SqlError> {
+    let fingerprint = fingerprint(sql);
+    let span = tracing::info_span!(
+        "sql",
+        op,
+        fingerprint = %fingerprint,
+        params = params.len(),
+        rows = Empty,
+        duration_us = Empty,
+        error = Empty,
+    );
+    let started = Instant::now();
+    let result = span.in_scope(f);
+    let elapsed = started.elapsed();
+    let duration_us = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+    let rows = result.as_ref().map_or(0, RowCount::row_count);
+    span.record("rows", rows);
+    span.record("duration_us", duration_us);
+    if let Err(e) = &result {
+        span.record("error", error::kind(e));
+    }
+    METRICS.with(|m| {
+        m.borrow_mut()
+            .record(op, &fingerprint, duration_us, rows, result.is_err())
+    });
+    let slow = Config::get().slow_queries;
+    if slow.is_slow(elapsed) {
+        let started_at = SystemTime::now().checked_sub(elapsed).unwrap_or(UNIX_EPOCH);
+        slow.push(wit_sql::SlowQuery {
+            fingerprint,
+            operation: op.to_string(),
+            database: label.to_string(),
+            params: params.iter().map(diagnostics::redact).collect(),
+            started_at_ms: started_at
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64),
+            duration_us,
+            rows,
+            error: result.as_ref().err().map(|e| error::kind(e).to_string()),
+            plan: diagnostics::plan_summary(op, label, sql, params),
+        });
+    }
+    result
+}
+
+pub(crate) fn snapshot() -> wit_sql::MetricsSnapshot {
+    METRICS.with(|m| m.borrow().snapshot())
+}
+
+/// Forget this thread's metrics, as a new instance would start without them.
+#[cfg(test)]
+pub(crate) fn reset() {
+    METRICS.take();
+}
+
+/// `sql` with literals and placeholders replaced by `?`, comments dropped and
+/// whitespace collapsed, so calls differing only in their values group
+/// together. Identifiers and keywords keep their case.
+pub(crate) fn fingerprint(sql: &str) -> String {
+    let mut out = String::with_capacity(sql.len());
+    let mut space = false;
+    for token in lexer::tokens(sql) {
+        match token.kind {
+            Kind::Space | Kind::Comment => space = true,
+            Kind::Literal | Kind::Number | Kind::Placeholder(_) => {
+                push(&mut out, &mut space, "?");
+            }
+            _ => push(&mut out, &mut space, token.text),
+        }
+    }
+    let trimmed = out.trim_end_matches([';', ' ']).len();
+    out.truncate(trimmed);
+    out
+}
+
+/// Append a token, separated by one space where the input had any and after
+/// commas, but never just inside parentheses or before a comma.
+fn push(out: &mut String, space: &mut bool, token: &str) {
+    let glued = out.is_empty() || out.ends_with('(') || token == ")" || token == ",";
+    if *space && !glued {
+        out.push(' ');
+    }
+    out.push_str(token);
+    *space = token == ",";
+}
+
+#[derive(Debug, Default, Clone)]
+struct Stats {
+    calls: u64,
+    errors: u64,
+    rows: u64,
+    total_us: u64,
+    max_us: u64,
+    buckets: [u64; BUCKET_BOUNDS_US.len() + 1],
+}
+
+impl Stats {
+    fn record(&mut self, duration_us: u64, rows: u64, failed: bool) {
+        self.calls += 1;
+        self.errors += u64::from(failed);
+        self.rows = self.rows.saturating_add(rows);
+        self.total_us = self.total_us.saturating_add(duration_us);
+        self.max_us = self.max_us.max(duration_us);
+        let bucket = BUCKET_BOUNDS_US.partition_point(|bound| *bound < duration_us);
+        self.buckets[bucket] += 1;
+    }
+
+    fn to_wit(&self, name: &str) -> wit_sql::CallMetrics {
+        wit_sql::CallMetrics {
+            name: name.to_string(),
+            calls: self.calls,
+            errors: self.errors,
+            rows: self.rows,
+            total_us: self.total_us,
+            max_us: self.max_us,
+            latency_buckets: self.buckets.to_vec(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    operations: BTreeMap<&'static str, Stats>,
+    statements: BTreeMap<String, Stats>,
+}
+
+impl Registry {
+    fn record(
+        &mut self,
+        op: &'static str,
+        fingerprint: &str,
+        duration_us: u64,
+        rows: u64,
+        failed: bool,
+    ) {
+        self.operations
+            .entry(op)
+            .or_default()
+            .record(duration_us, rows, failed);
+        if !self.statements.contains_key(fingerprint) && self.statements.len() >= MAX_STATEMENTS {
+            return;
+        }
+        self.statements
+            .entry(fingerprint.to_string())
+            .or_default()
+            .record(duration_us, rows, failed);
+    }
+
+    fn snapshot(&self) -> wit_sql::MetricsSnapshot {
+        wit_sql::MetricsSnapshot {
+            bucket_bounds_us: BUCKET_BOUNDS_US.to_vec(),
+            operations: self
+                .operations
+                .iter()
+                .map(|(op, stats)| stats.to_wit(op))
+                .collect(),
+            statements: self
+                .statements
+                .iter()
+                .map(|(fingerprint, stats)| stats.to_wit(fingerprint))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprints_replace_literals_and_placeholders() {
+        assert_eq!(
+            fingerprint("SELECT * FROM users WHERE id = 42 AND name = 'it''s'"),
+            "SELECT * FROM users WHERE id = ? AND name = ?"
+        );
+        assert_eq!(
+            fingerprint("select a from t where b in (?1,$2 , :c,@d) and e = x'ff' and f > 1.5e-3;"),
+            "select a from t where b in (?, ?, ?, ?) and e = ? and f > ?"
+        );
+        assert_eq!(
+            fingerprint("SELECT \"col 1\", [x y], t2.c3$ FROM t2 -- trailing\n"),
+            "SELECT \"col 1\", [x y], t2.c3$ FROM t2"
+        );
+    }
+
+    #[test]
+    fn fingerprints_ignore_layout() {
+        let a = fingerprint("INSERT INTO t (a, b)\n   VALUES (1,  'x')");
+        let b = fingerprint("INSERT INTO t ( a,b ) /* note */ VALUES ( 2 , 'y' );");
+        assert_eq!(a, "INSERT INTO t (a, b) VALUES (?, ?)");
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn stats_bucket_latencies_by_upper_bound() {
+        let mut stats = Stats::default();
+        for us in [0, 100, 101, 2_000, 5_000_000] {
+            stats.record(us, 1, false);
+        }
+        assert_eq!(stats.buckets, [2, 1, 0, 1, 0, 0, 0, 0, 1]);
+        assert_eq!((stats.calls, stats.rows, stats.max_us), (5, 5, 5_000_000));
+    }
+
+    #[test]
+    fn observe_counts_calls_rows_and_errors() {
+        observe("query", "default", "SELECT 1", &[], || Ok(vec![1_u64, 2])).unwrap();
+        observe("query", "default", "SELECT 2", &[], || Ok(3_u64)).unwrap();
+        observe(
+            "execute",
+            "default",
+            "DELETE FROM t",
+            &[],
+            || -> Result<u64, _> { Err(wit_sql::SqlError::NotFound) },
+        )
+        .unwrap_err();
+
+        let snapshot = snapshot();
+        let query = &snapshot.operations[1];
+        assert_eq!(
+            (query.name.as_str(), query.calls, query.rows),
+            ("query", 2, 6)
+        );
+        let execute = &snapshot.operations[0];
+        assert_eq!((execute.name.as_str(), execute.errors), ("execute", 1));
+        let statements: Vec<_> = snapshot
+            .statements
+            .iter()
+            .map(|s| s.name.as_str())
+            .collect();
+        assert_eq!(statements, vec!["DELETE FROM t", "SELECT ?"]);
+        assert_eq!(snapshot.statements[1].calls, 2);
+    }
+
+    #[test]
+    fn statements_beyond_the_cap_count_only_per_operation() {
+        let mut registry = Registry::default();
+        for i in 0..=MAX_STATEMENTS {
+            registry.record("query", &format!("SELECT c{i} FROM t"), 1, 0, false);
+        }
+        let snapshot = registry.snapshot();
+        assert_eq!(snapshot.statements.len(), MAX_STATEMENTS);
+        assert_eq!(snapshot.operations[0].calls, MAX_STATEMENTS as u64 + 1);
+    }
+}
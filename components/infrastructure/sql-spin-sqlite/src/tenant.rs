@@ -0,0 +1,196 @@
+//! Tenant-scoped databases.
+//!
+//! `connection.open-tenant(name, tenant)` routes to the database label
+//! `<name>.<tenant>`, so every tenant's rows live in a database of their own.
+//! Each tenant label has to be granted to the component like any other
+//! (`sqlite_databases` in Spin).
+//!
+//! That alone does not isolate tenants: a guest can still `connection.open`
+//! another tenant's label directly, or ATTACH its database file. Isolation
+//! holds with `sql_tenant_required` set, which refuses every label the
+//! instance did not open through `open-tenant` (including the `default`
+//! database behind the top-level functions) and refuses ATTACH and DETACH.
+//! Then nothing run on a tenant's connection, its transactions, statements or
+//! cursors can read another tenant's rows.
+
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+
+use crate::error::{self, codes};
+use crate::wit_sql;
+
+/// Joins a database name and a tenant id in a tenant label. Tenant ids
+/// cannot contain it, so a label has exactly one reading.
+const SEPARATOR: char = '.';
+
+const MAX_TENANT_LEN: usize = 64;
+
+thread_local! {
+    /// Labels this instance opened through `open-tenant`.
+    static OPENED: RefCell<BTreeSet<String>> = const { RefCell::new(BTreeSet::new()) };
+}
+
+/// The label of `tenant`'s copy of database `name`.
+pub(crate) fn label(name: &str, tenant: &str) -> Result<String, wit_sql::SqlError> {
+    validate(tenant)?;
+    Ok(format!("{name}{SEPARATOR}{tenant}"))
+}
+
+/// [`label`], recorded as opened through `open-tenant`.
+pub(crate) fn open(name: &str, tenant: &str) -> Result<String, wit_sql::SqlError> {
+    let label = label(name, tenant)?;
+    OPENED.with(|opened| opened.borrow_mut().insert(label.clone()));
+    Ok(label)
+}
+
+/// Forget the labels this thread opened, as a new instance would start.
+#[cfg(test)]
+pub(crate) fn forget_opened() {
+    OPENED.take();
+}
+
+/// Reject `label` unless it was opened through `open-tenant`. A label that
+/// merely looks like a tenant's is not enough.
+pub(crate) fn require_scoped(label: &str) -> Result<(), wit_sql::SqlError> {
+    if OPENED.with(|opened| opened.borrow().contains(label)) {
+        return Ok(());
+    }
+    Err(wit_sql::SqlError::ConnectionFailed(error::adapter_error(
+        codes::AUTH,
+        format!("database {label:?} is not tenant-scoped; use connection.open-tenant"),
+        None,
+    )))
+}
+
+/// Reject statements that could open another database file.
+pub(crate) fn check_statement(statement: &str, keyword: &str) -> Result<(), wit_sql::SqlError> {
+    if keyword.eq_ignore_ascii_case("ATTACH") || keyword.eq_ignore_ascii_case("DETACH") {
+        return Err(wit_sql::SqlError::QueryFailed(error::adapter_error(
+            codes::AUTH,
+            format!(
+                "{} is not allowed on tenant-scoped databases",
+                keyword.to_ascii_uppercase()
+            ),
+            Some(statement),
+        )));
+    }
+    Ok(())
+}
+
+fn validate(tenant: &str) -> Result<(), wit_sql::SqlError> {
+    let valid = !tenant.is_empty()
+        && tenant.len() <= MAX_TENANT_LEN
+        && tenant
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || c == b'_' || c == b'-');
+    if valid {
+        return Ok(());
+    }
+    Err(wit_sql::SqlError::InvalidParameter(error::adapter_error(
+        codes::MISUSE,
+        format!(
+            "tenant id {tenant:?} must be 1-{MAX_TENANT_LEN} ASCII letters, digits, '_' or '-'"
+        ),
+        None,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Database;
+    use crate::config::Config;
+
+    #[test]
+    fn tenants_get_distinct_labels() {
+        assert_eq!(label("default", "acme").unwrap(), "default.acme");
+        assert_ne!(
+            label("default", "acme").unwrap(),
+            label("default", "globex").unwrap()
+        );
+        assert_eq!(label("audit", "acme-eu").unwrap(), "audit.acme-eu");
+    }
+
+    #[test]
+    fn tenant_ids_cannot_escape_their_label() {
+        for tenant in ["", "acme.globex", "../globex", "acme globex", "ac/me"] {
+            assert!(
+                matches!(
+                    label("default", tenant),
+                    Err(wit_sql::SqlError::InvalidParameter(_))
+                ),
+                "{tenant:?}"
+            );
+        }
+        assert!(label("default", &"a".repeat(MAX_TENANT_LEN + 1)).is_err());
+    }
+
+    #[test]
+    fn tenants_cannot_read_each_others_rows() {
+        let ddl = "CREATE TABLE IF NOT EXISTS notes (body TEXT)";
+        let acme = Database::connect_tenant("default", "acme").unwrap();
+        acme.run_execute(ddl, &[]).unwrap();
+        acme.run_execute("INSERT INTO notes (body) VALUES ('acme only')", &[])
+            .unwrap();
+
+        let globex = Database::connect_tenant("default", "globex").unwrap();
+        assert!(
+            globex
+                .run_query("SELECT body FROM notes", &[], &[])
+                .is_err()
+        );
+        globex.run_execute(ddl, &[]).unwrap();
+        let seen = globex
+            .run_query("SELECT body FROM notes", &[], &[])
+            .unwrap();
+        assert!(seen.rows.is_empty());
+
+        let again = Database::connect_tenant("default", "acme").unwrap();
+        let seen = again.run_query("SELECT body FROM notes", &[], &[]).unwrap();
+        assert_eq!(seen.rows.len(), 1);
+    }
+
+    #[test]
+    fn required_mode_accepts_only_labels_opened_for_a_tenant() {
+        let acme = open("default", "acme").unwrap();
+        assert!(require_scoped(&acme).is_ok());
+        for unscoped in [
+            "default",
+            "audit",
+            "default.globex",
+            ".acme",
+            "default.a.b c",
+        ] {
+            assert!(
+                matches!(
+                    require_scoped(unscoped),
+                    Err(wit_sql::SqlError::ConnectionFailed(_))
+                ),
+                "{unscoped:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn required_mode_refuses_opening_a_tenant_label_directly() {
+        Config::set_for_thread(Some(Config {
+            tenant_required: true,
+            ..Config::default()
+        }));
+        let Err(wit_sql::SqlError::ConnectionFailed(info)) =
+            Database::connect("default.globex".to_string())
+        else {
+            panic!("opened another tenant's database by label");
+        };
+        assert_eq!(info.code, codes::AUTH);
+        assert!(Database::connect_tenant("default", "globex").is_ok());
+        assert!(Database::default().run_query("SELECT 1", &[], &[]).is_err());
+    }
+
+    #[test]
+    fn attach_and_detach_are_refused() {
+        assert!(check_statement("ATTACH 'globex.db' AS g", "attach").is_err());
+        assert!(check_statement("DETACH g", "DETACH").is_err());
+        assert!(check_statement("SELECT 1", "SELECT").is_ok());
+    }
+}
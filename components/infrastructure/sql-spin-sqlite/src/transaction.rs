@@ -0,0 +1,804 @@
+//! The `transaction` resource and its lifecycle.
+//!
+//! A transaction is `active` until `commit` succeeds or `rollback` is issued;
+//! any call after that is rejected with `transaction-failed` instead of being
+//! passed to SQLite, where it would run outside the transaction. A resource
+//! dropped while still active is rolled back so the shared connection is not
+//! left mid-BEGIN.
+//!
+//! Only one transaction can be open on a database's shared connection, so a
+//! second `begin-transaction` on it is rejected before reaching SQLite.
+//!
+//! `transaction-options` pick the BEGIN mode and may set `query_only` and a
+//! busy timeout on the shared connection for the transaction's duration. They
+//! are applied before BEGIN, so an IMMEDIATE or EXCLUSIVE BEGIN waits for the
+//! lock up to the requested timeout, and the previous settings are restored
+//! once the transaction finishes or if BEGIN fails.
+//!
+//! Savepoints nest inside the transaction so composed components can each own
+//! an atomic unit; the open ones are tracked here so `release`/`rollback-to`
+//! of an unknown name fails before reaching SQLite.
+//!
+//! BEGIN, COMMIT, END, ROLLBACK, SAVEPOINT, RELEASE and ROLLBACK TO sent as
+//! SQL through the resource are rejected, since they would nest or end the
+//! transaction, or open and close savepoints, behind the lifecycle's back.
+//!
+//! Only BEGIN itself is retried on lock contention; statements inside the
+//! transaction surface `SQLITE_BUSY` to the guest, which owns the unit of work.
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use crate::config::Config;
+use crate::error::{self, codes, map_err};
+use crate::host::Connection;
+use crate::limits::Limits;
+use crate::telemetry::{self, RowCount};
+use crate::{batch, connection, params, policy, wit_sql};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum State {
+    Active,
+    Committed,
+    RolledBack,
+}
+
+/// State machine guarding a transaction's operations.
+#[derive(Debug)]
+pub(crate) struct Lifecycle {
+    state: Cell<State>,
+}
+
+impl Lifecycle {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: Cell::new(State::Active),
+        }
+    }
+
+    pub(crate) fn state(&self) -> State {
+        self.state.get()
+    }
+
+    /// Reject `op` unless the transaction is still active.
+    pub(crate) fn ensure_active(&self, op: &str) -> Result<(), wit_sql::SqlError> {
+        let finished = match self.state.get() {
+            State::Active => return Ok(()),
+            State::Committed => "committed",
+            State::RolledBack => "rolled back",
+        };
+        Err(wit_sql::SqlError::TransactionFailed(error::adapter_error(
+            codes::MISUSE,
+            format!("cannot {op}: transaction already {finished}"),
+            None,
+        )))
+    }
+
+    /// Mark a transaction that never started as finished.
+    pub(crate) fn abandon(&self) {
+        self.state.set(State::RolledBack);
+    }
+
+    /// Record the outcome of a COMMIT. A failed COMMIT (e.g. SQLITE_BUSY or a
+    /// deferred foreign key) leaves the transaction open in SQLite.
+    pub(crate) fn committed<T>(
+        &self,
+        result: Result<T, wit_sql::SqlError>,
+    ) -> Result<T, wit_sql::SqlError> {
+        if result.is_ok() {
+            self.state.set(State::Committed);
+        }
+        result
+    }
+
+    /// Record a ROLLBACK attempt. Even a failed ROLLBACK ends the
+    /// transaction from the guest's point of view: SQLite only refuses it when
+    /// no transaction is open any more.
+    pub(crate) fn rolled_back<T>(
+        &self,
+        result: Result<T, wit_sql::SqlError>,
+    ) -> Result<T, wit_sql::SqlError> {
+        self.state.set(State::RolledBack);
+        result
+    }
+}
+
+/// Statements that apply `options` before BEGIN and the ones that undo them
+/// afterwards. `previous_timeout_ms` is the connection's current busy timeout.
+pub(crate) fn session_statements(
+    options: &wit_sql::TransactionOptions,
+    previous_timeout_ms: i64,
+) -> (Vec<String>, Vec<String>) {
+    let mut apply = Vec::new();
+    let mut restore = Vec::new();
+    if let Some(ms) = options.busy_timeout_ms {
+        apply.push(format!("PRAGMA busy_timeout = {ms}"));
+        restore.push(format!("PRAGMA busy_timeout = {previous_timeout_ms}"));
+    }
+    if options.read_only {
+        apply.push("PRAGMA query_only = ON".to_string());
+        restore.push("PRAGMA query_only = OFF".to_string());
+    }
+    (apply, restore)
+}
+
+pub(crate) fn begin_statement(mode: wit_sql::TransactionMode) -> &'static str {
+    match mode {
+        wit_sql::TransactionMode::Deferred => "BEGIN DEFERRED",
+        wit_sql::TransactionMode::Immediate => "BEGIN IMMEDIATE",
+        wit_sql::TransactionMode::Exclusive => "BEGIN EXCLUSIVE",
+    }
+}
+
+/// Reject transaction control in guest SQL run inside a transaction.
+pub(crate) fn check_control(sql: &str) -> Result<(), wit_sql::SqlError> {
+    for statement in batch::split_script(sql) {
+        let words = policy::words(statement);
+        let mut keywords = words.iter().map(|(_, w)| *w);
+        let Some(first) = keywords.next() else {
+            continue;
+        };
+        let keyword = first.to_ascii_uppercase();
+        // ROLLBACK [TRANSACTION] TO [SAVEPOINT] name
+        let to_savepoint =
+            keyword == "ROLLBACK" && keywords.take(2).any(|w| w.eq_ignore_ascii_case("TO"));
+        let instead = match keyword.as_str() {
+            "ROLLBACK" if to_savepoint => "its rollback-to",
+            "BEGIN" | "COMMIT" | "END" | "ROLLBACK" => "its commit or rollback",
+            "SAVEPOINT" | "RELEASE" => "its savepoint, release and rollback-to",
+            _ => continue,
+        };
+        let keyword = if to_savepoint {
+            "ROLLBACK TO"
+        } else {
+            &keyword
+        };
+        return Err(wit_sql::SqlError::TransactionFailed(error::adapter_error(
+            codes::MISUSE,
+            format!("{keyword} cannot run inside a transaction; use {instead}"),
+            Some(statement),
+        )));
+    }
+    Ok(())
+}
+
+fn default_options() -> wit_sql::TransactionOptions {
+    wit_sql::TransactionOptions {
+        mode: wit_sql::TransactionMode::Deferred,
+        read_only: false,
+        busy_timeout_ms: None,
+    }
+}
+
+/// Stack of open savepoint names, innermost last.
+#[derive(Debug, Default)]
+pub(crate) struct Savepoints {
+    open: RefCell<Vec<String>>,
+}
+
+impl Savepoints {
+    /// Validate `name` and return it quoted for use in SQL.
+    pub(crate) fn quoted(name: &str) -> Result<String, wit_sql::SqlError> {
+        let mut chars = name.chars();
+        let valid = chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(wit_sql::SqlError::InvalidParameter(error::adapter_error(
+                codes::MISUSE,
+                format!("invalid savepoint name `{name}`"),
+                None,
+            )));
+        }
+        Ok(format!("\"{name}\""))
+    }
+
+    pub(crate) fn push(&self, name: &str) {
+        self.open.borrow_mut().push(name.to_string());
+    }
+
+    /// Position of the innermost savepoint called `name`, as SQLite resolves it.
+    pub(crate) fn find(&self, name: &str) -> Result<usize, wit_sql::SqlError> {
+        self.open
+            .borrow()
+            .iter()
+            .rposition(|n| n == name)
+            .ok_or_else(|| {
+                wit_sql::SqlError::TransactionFailed(error::adapter_error(
+                    codes::MISUSE,
+                    format!("no such savepoint: {name}"),
+                    None,
+                ))
+            })
+    }
+
+    /// RELEASE removes the savepoint and everything opened after it.
+    pub(crate) fn released(&self, at: usize) {
+        self.open.borrow_mut().truncate(at);
+    }
+
+    /// ROLLBACK TO keeps the savepoint itself open but discards inner ones.
+    pub(crate) fn rolled_back_to(&self, at: usize) {
+        self.open.borrow_mut().truncate(at + 1);
+    }
+
+    #[cfg(test)]
+    pub(crate) fn names(&self) -> Vec<String> {
+        self.open.borrow().clone()
+    }
+}
+
+/// An explicit transaction on a database's shared connection. Other calls on
+/// the same database made while it is open run inside it.
+pub(crate) struct Transaction {
+    label: String,
+    conn: Rc<Connection>,
+    lifecycle: Lifecycle,
+    savepoints: Savepoints,
+    restore: Vec<String>,
+}
+
+impl Transaction {
+    pub(crate) fn begin(
+        label: &str,
+        options: Option<wit_sql::TransactionOptions>,
+    ) -> Result<Self, wit_sql::SqlError> {
+        if connection::in_transaction(label) {
+            return Err(wit_sql::SqlError::TransactionFailed(error::adapter_error(
+                codes::MISUSE,
+                format!("a transaction is already open on database {label:?}"),
+                None,
+            )));
+        }
+        let mut options = options.unwrap_or_else(default_options);
+        if Config::get().statements.read_only {
+            // `query_only` is already on for the connection's whole life and
+            // must not be switched off when the transaction ends.
+            options.read_only = false;
+        }
+        let conn = connection::shared(label)?;
+        let previous_timeout_ms = if options.busy_timeout_ms.is_some() {
+            connection::observe(label, busy_timeout(&conn))?
+        } else {
+            0
+        };
+        let (apply, restore) = session_statements(&options, previous_timeout_ms);
+        let tx = Self {
+            label: label.to_string(),
+            conn,
+            lifecycle: Lifecycle::new(),
+            savepoints: Savepoints::default(),
+            restore,
+        };
+        // Nothing has been written yet, so a busy BEGIN is safe to retry.
+        let begin = begin_statement(options.mode);
+        let started = apply
+            .iter()
+            .try_for_each(|statement| tx.finish(statement))
+            .and_then(|()| {
+                telemetry::observe("begin", label, begin, &[], || {
+                    Config::get()
+                        .retry
+                        .run(|| tx.finish(begin), std::thread::sleep)
+                })
+            });
+        if let Err(e) = started {
+            // BEGIN did not happen, so there is nothing to roll back on drop.
+            tx.lifecycle.abandon();
+            tx.restore_session();
+            return Err(e);
+        }
+        connection::set_in_transaction(label, true);
+        Ok(tx)
+    }
+
+    /// Run `f` on the connection if the transaction is still active.
+    fn run<T: RowCount>(
+        &self,
+        op: &'static str,
+        sql: &str,
+        params: &[wit_sql::SqlValue],
+        f: impl FnOnce(&Connection) -> Result<T, wit_sql::SqlError>,
+    ) -> Result<T, wit_sql::SqlError> {
+        self.lifecycle.ensure_active(op)?;
+        check_control(sql)?;
+        telemetry::observe(op, &self.label, sql, params, || {
+            connection::observe(&self.label, f(&self.conn))
+        })
+    }
+
+    fn finish(&self, statement: &str) -> Result<(), wit_sql::SqlError> {
+        connection::observe(
+            &self.label,
+            self.conn
+                .execute(statement, &[])
+                .map(|_| ())
+                .map_err(|e| map_err(e, "transaction", Some(statement))),
+        )
+    }
+
+    /// Mark the transaction closed and undo its session settings.
+    fn ended(&self) {
+        connection::set_in_transaction(&self.label, false);
+        self.restore_session();
+    }
+
+    /// Undo `transaction-options` once the transaction has ended.
+    fn restore_session(&self) {
+        for statement in &self.restore {
+            if let Err(e) = self.finish(statement) {
+                tracing::warn!(error = ?e, statement, "failed to restore session setting");
+            }
+        }
+    }
+}
+
+impl wit_sql::GuestTransaction for Transaction {
+    fn query(
+        &self,
+        sql: String,
+        params: Vec<wit_sql::SqlValue>,
+    ) -> Result<wit_sql::QueryResult, wit_sql::SqlError> {
+        self.run("query", &sql, &params, |conn| {
+            crate::exec_query_on(conn, &sql, &params, &[])
+        })
+    }
+
+    fn query_typed(
+        &self,
+        sql: String,
+        params: Vec<wit_sql::SqlValue>,
+        schema: Vec<wit_sql::ColumnHint>,
+    ) -> Result<wit_sql::QueryResult, wit_sql::SqlError> {
+        self.run("query", &sql, &params, |conn| {
+            crate::exec_query_on(conn, &sql, &params, &schema)
+        })
+    }
+
+    fn query_with(
+        &self,
+        sql: String,
+        params: Vec<wit_sql::SqlValue>,
+        schema: Vec<wit_sql::ColumnHint>,
+        options: wit_sql::CallOptions,
+    ) -> Result<wit_sql::QueryResult, wit_sql::SqlError> {
+        let limits = Limits::start(&options);
+        self.run("query", &sql, &params, |conn| {
+            crate::exec_query_limited_on(conn, &sql, &params, &schema, &limits)
+        })
+    }
+
+    fn query_named(
+        &self,
+        sql: String,
+        params: Vec<(String, wit_sql::SqlValue)>,
+    ) -> Result<wit_sql::QueryResult, wit_sql::SqlError> {
+        let (sql, params) = params::bind_named(&sql, &params)?;
+        self.run("query", &sql, &params, |conn| {
+            crate::exec_query_on(conn, &sql, &params, &[])
+        })
+    }
+
+    fn query_columnar(
+        &self,
+        sql: String,
+        params: Vec<wit_sql::SqlValue>,
+        schema: Vec<wit_sql::ColumnHint>,
+    ) -> Result<wit_sql::ColumnarResult, wit_sql::SqlError> {
+        self.run("query", &sql, &params, |conn| {
+            crate::exec_query_columnar_on(conn, &sql, &params, &schema)
+        })
+    }
+
+    fn execute(
+        &self,
+        sql: String,
+        params: Vec<wit_sql::SqlValue>,
+    ) -> Result<u64, wit_sql::SqlError> {
+        self.run("execute", &sql, &params, |conn| {
+            crate::exec_execute_on(conn, &sql, &params)
+        })
+    }
+
+    fn execute_named(
+        &self,
+        sql: String,
+        params: Vec<(String, wit_sql::SqlValue)>,
+    ) -> Result<u64, wit_sql::SqlError> {
+        let (sql, params) = params::bind_named(&sql, &params)?;
+        self.run("execute", &sql, &params, |conn| {
+            crate::exec_execute_on(conn, &sql, &params)
+        })
+    }
+
+    fn execute_returning(
+        &self,
+        sql: String,
+        params: Vec<wit_sql::SqlValue>,
+    ) -> Result<wit_sql::ExecuteResult, wit_sql::SqlError> {
+        self.run("execute", &sql, &params, |conn| {
+            crate::exec_execute_returning_on(conn, &sql, &params)
+        })
+    }
+
+    fn execute_batch(
+        &self,
+        sql: String,
+        rows: Vec<Vec<wit_sql::SqlValue>>,
+    ) -> Result<Vec<u64>, wit_sql::SqlError> {
+        self.run(
+            "execute-batch",
+            &sql,
+            rows.first().map_or(&[][..], Vec::as_slice),
+            |conn| batch::exec_batch_on(conn, &sql, &rows, true),
+        )
+    }
+
+    fn execute_script(&self, sql: String) -> Result<Vec<u64>, wit_sql::SqlError> {
+        self.run("execute-script", &sql, &[], |conn| {
+            batch::exec_script_on(conn, &sql, true)
+        })
+    }
+
+    fn commit(&self) -> Result<(), wit_sql::SqlError> {
+        self.lifecycle.ensure_active("commit")?;
+        let result = telemetry::observe("commit", &self.label, "COMMIT", &[], || {
+            self.finish("COMMIT")
+        });
+        self.lifecycle.committed(result)?;
+        self.ended();
+        Ok(())
+    }
+
+    fn rollback(&self) -> Result<(), wit_sql::SqlError> {
+        self.lifecycle.ensure_active("rollback")?;
+        let result = telemetry::observe("rollback", &self.label, "ROLLBACK", &[], || {
+            self.finish("ROLLBACK")
+        });
+        let result = self.lifecycle.rolled_back(result);
+        self.ended();
+        result
+    }
+
+    fn savepoint(&self, name: String) -> Result<(), wit_sql::SqlError> {
+        self.lifecycle.ensure_active("savepoint")?;
+        let quoted = Savepoints::quoted(&name)?;
+        self.finish(&format!("SAVEPOINT {quoted}"))?;
+        self.savepoints.push(&name);
+        Ok(())
+    }
+
+    fn release(&self, name: String) -> Result<(), wit_sql::SqlError> {
+        self.lifecycle.ensure_active("release")?;
+        let quoted = Savepoints::quoted(&name)?;
+        let at = self.savepoints.find(&name)?;
+        self.finish(&format!("RELEASE {quoted}"))?;
+        self.savepoints.released(at);
+        Ok(())
+    }
+
+    fn rollback_to(&self, name: String) -> Result<(), wit_sql::SqlError> {
+        self.lifecycle.ensure_active("rollback-to")?;
+        let quoted = Savepoints::quoted(&name)?;
+        let at = self.savepoints.find(&name)?;
+        self.finish(&format!("ROLLBACK TO {quoted}"))?;
+        self.savepoints.rolled_back_to(at);
+        Ok(())
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        if self.lifecycle.state() != State::Active {
+            return;
+        }
+        let result = telemetry::observe("rollback", &self.label, "ROLLBACK", &[], || {
+            self.finish("ROLLBACK")
+        });
+        if let Err(e) = self.lifecycle.rolled_back(result) {
+            tracing::warn!(error = ?e, "rollback of dropped transaction failed");
+        }
+        self.ended();
+    }
+}
+
+fn busy_timeout(conn: &Connection) -> Result<i64, wit_sql::SqlError> {
+    let qr = conn
+        .execute("PRAGMA busy_timeout", &[])
+        .map_err(|e| map_err(e, "transaction", Some("PRAGMA busy_timeout")))?;
+    Ok(qr.rows.first().and_then(|r| r.get::<i64>(0)).unwrap_or(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ok() -> Result<(), wit_sql::SqlError> {
+        Ok(())
+    }
+
+    fn busy() -> Result<(), wit_sql::SqlError> {
+        Err(wit_sql::SqlError::TransactionFailed(error::adapter_error(
+            codes::BUSY,
+            "database is locked",
+            Some("COMMIT"),
+        )))
+    }
+
+    fn rejected(result: Result<(), wit_sql::SqlError>, finished: &str) {
+        let Err(wit_sql::SqlError::TransactionFailed(info)) = result else {
+            panic!("expected transaction-failed, got {result:?}");
+        };
+        assert_eq!(info.code, codes::MISUSE);
+        assert!(info.message.contains(finished), "{}", info.message);
+    }
+
+    #[test]
+    fn starts_active() {
+        let tx = Lifecycle::new();
+        assert_eq!(tx.state(), State::Active);
+        assert!(tx.ensure_active("query").is_ok());
+    }
+
+    #[test]
+    fn commit_moves_to_committed_and_rejects_further_use() {
+        let tx = Lifecycle::new();
+        tx.committed(ok()).unwrap();
+        assert_eq!(tx.state(), State::Committed);
+        rejected(tx.ensure_active("commit"), "committed");
+        rejected(tx.ensure_active("query"), "committed");
+        rejected(tx.ensure_active("rollback"), "committed");
+    }
+
+    #[test]
+    fn rollback_moves_to_rolled_back_and_rejects_further_use() {
+        let tx = Lifecycle::new();
+        tx.rolled_back(ok()).unwrap();
+        assert_eq!(tx.state(), State::RolledBack);
+        rejected(tx.ensure_active("execute"), "rolled back");
+        rejected(tx.ensure_active("commit"), "rolled back");
+    }
+
+    #[test]
+    fn failed_commit_stays_active() {
+        let tx = Lifecycle::new();
+        assert!(tx.committed(busy()).is_err());
+        assert_eq!(tx.state(), State::Active);
+        tx.committed(ok()).unwrap();
+        assert_eq!(tx.state(), State::Committed);
+    }
+
+    #[test]
+    fn abandoned_transaction_is_finished() {
+        let tx = Lifecycle::new();
+        tx.abandon();
+        rejected(tx.ensure_active("query"), "rolled back");
+    }
+
+    #[test]
+    fn failed_rollback_still_finishes() {
+        let tx = Lifecycle::new();
+        assert!(tx.rolled_back(busy()).is_err());
+        assert_eq!(tx.state(), State::RolledBack);
+    }
+
+    #[test]
+    fn savepoint_names_are_validated_and_quoted() {
+        assert_eq!(Savepoints::quoted("billing_1").unwrap(), "\"billing_1\"");
+        assert_eq!(Savepoints::quoted("_sp").unwrap(), "\"_sp\"");
+        for bad in ["", "1sp", "sp; DROP TABLE users", "sp\"", "sp-1"] {
+            assert!(
+                matches!(
+                    Savepoints::quoted(bad),
+                    Err(wit_sql::SqlError::InvalidParameter(_))
+                ),
+                "{bad:?} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn release_pops_savepoint_and_inner_ones() {
+        let sp = Savepoints::default();
+        sp.push("outer");
+        sp.push("inner");
+        sp.push("innermost");
+        let at = sp.find("inner").unwrap();
+        sp.released(at);
+        assert_eq!(sp.names(), vec!["outer".to_string()]);
+        assert!(matches!(
+            sp.find("inner"),
+            Err(wit_sql::SqlError::TransactionFailed(_))
+        ));
+    }
+
+    #[test]
+    fn rollback_to_keeps_the_savepoint_open() {
+        let sp = Savepoints::default();
+        sp.push("outer");
+        sp.push("inner");
+        let at = sp.find("outer").unwrap();
+        sp.rolled_back_to(at);
+        assert_eq!(sp.names(), vec!["outer".to_string()]);
+        // The savepoint can be rolled back to again, then released.
+        assert_eq!(sp.find("outer").unwrap(), 0);
+    }
+
+    #[test]
+    fn duplicate_names_resolve_to_the_innermost() {
+        let sp = Savepoints::default();
+        sp.push("step");
+        sp.push("other");
+        sp.push("step");
+        assert_eq!(sp.find("step").unwrap(), 2);
+    }
+
+    #[test]
+    fn transaction_control_is_rejected() {
+        for sql in [
+            "COMMIT",
+            "end transaction",
+            "ROLLBACK",
+            "  -- undo\n rollback transaction",
+            "BEGIN IMMEDIATE",
+            "UPDATE t SET v = 1; COMMIT",
+            "ROLLBACK TO sp",
+            "rollback transaction to savepoint sp",
+            "SAVEPOINT sp",
+            "release savepoint sp",
+        ] {
+            let Err(wit_sql::SqlError::TransactionFailed(info)) = check_control(sql) else {
+                panic!("{sql:?} should be rejected");
+            };
+            assert_eq!(info.code, codes::MISUSE, "{sql}");
+        }
+        for sql in [
+            "SELECT 'COMMIT'",
+            "SELECT 1 AS savepoint",
+            "UPDATE t SET v = 1",
+        ] {
+            assert!(check_control(sql).is_ok(), "{sql:?}");
+        }
+    }
+
+    #[test]
+    fn raw_commit_leaves_the_transaction_active() {
+        use wit_sql::GuestTransaction as _;
+
+        let tx = Transaction::begin("control", None).unwrap();
+        tx.execute("CREATE TABLE t (v INTEGER)".to_string(), vec![])
+            .unwrap();
+        assert!(matches!(
+            tx.execute("COMMIT".to_string(), vec![]),
+            Err(wit_sql::SqlError::TransactionFailed(_))
+        ));
+        assert_eq!(tx.lifecycle.state(), State::Active);
+        assert!(connection::in_transaction("control"));
+        tx.rollback().unwrap();
+        assert!(!connection::in_transaction("control"));
+    }
+
+    #[test]
+    fn second_begin_leaves_the_open_transaction_alone() {
+        use wit_sql::GuestTransaction as _;
+
+        let options = wit_sql::TransactionOptions {
+            mode: wit_sql::TransactionMode::Deferred,
+            read_only: true,
+            busy_timeout_ms: Some(250),
+        };
+        let tx = Transaction::begin("nested", Some(options)).unwrap();
+        // A failed read-only BEGIN used to switch `query_only` back off.
+        let Err(wit_sql::SqlError::TransactionFailed(info)) =
+            Transaction::begin("nested", Some(options))
+        else {
+            panic!("a second BEGIN on the label should be rejected");
+        };
+        assert_eq!(info.code, codes::MISUSE);
+
+        // The first transaction keeps its session settings.
+        let setting = |pragma: &str| {
+            let result = tx.query(format!("PRAGMA {pragma}"), vec![]).unwrap();
+            result.rows[0].columns[0].1.clone()
+        };
+        assert_eq!(setting("query_only"), wit_sql::SqlValue::Int64(1));
+        assert_eq!(setting("busy_timeout"), wit_sql::SqlValue::Int64(250));
+        assert!(
+            tx.execute("CREATE TABLE t (v INTEGER)".to_string(), vec![])
+                .is_err()
+        );
+        tx.commit().unwrap();
+
+        // Committing restores the connection's own settings.
+        let restored = connection::with_shared("nested", |conn| {
+            crate::exec_query_on(conn, "PRAGMA query_only", &[], &[])
+        })
+        .unwrap();
+        assert_eq!(restored.rows[0].columns[0].1, wit_sql::SqlValue::Int64(0));
+    }
+
+    #[test]
+    fn begin_statement_matches_mode() {
+        use wit_sql::TransactionMode as M;
+        assert_eq!(begin_statement(M::Deferred), "BEGIN DEFERRED");
+        assert_eq!(begin_statement(M::Immediate), "BEGIN IMMEDIATE");
+        assert_eq!(begin_statement(M::Exclusive), "BEGIN EXCLUSIVE");
+    }
+
+    #[test]
+    fn immediate_begin_waits_for_the_lock_up_to_its_busy_timeout() {
+        use std::sync::mpsc;
+        use std::thread;
+        use std::time::{Duration, Instant};
+
+        fn immediate(busy_timeout_ms: u32) -> Option<wit_sql::TransactionOptions> {
+            Some(wit_sql::TransactionOptions {
+                mode: wit_sql::TransactionMode::Immediate,
+                read_only: false,
+                busy_timeout_ms: Some(busy_timeout_ms),
+            })
+        }
+        // Two instances of one component: same thread name, same files.
+        let instance = || thread::Builder::new().name("busy-wait".to_string());
+        let (locked, wait_for_lock) = mpsc::channel();
+        let holder = instance()
+            .spawn(move || {
+                let tx = Transaction::begin("busy", immediate(1000)).unwrap();
+                locked.send(()).unwrap();
+                thread::sleep(Duration::from_millis(300));
+                drop(tx);
+            })
+            .unwrap();
+        let waiter = instance()
+            .spawn(move || {
+                let mut config = Config::default();
+                config.retry.max_attempts = 1;
+                Config::set_for_thread(Some(config));
+                let timeout = |conn: &Connection| busy_timeout(conn).unwrap();
+                connection::with_shared("busy", |conn| {
+                    conn.execute("PRAGMA busy_timeout = 0", &[]).unwrap();
+                    Ok(())
+                })
+                .unwrap();
+                wait_for_lock.recv().unwrap();
+
+                // Too short a wait fails, and the connection's timeout is back.
+                let Err(mut e) = Transaction::begin("busy", immediate(20)) else {
+                    panic!("BEGIN IMMEDIATE took a held lock");
+                };
+                assert_eq!(error::info_mut(&mut e).map(|i| i.code), Some(codes::BUSY));
+                assert_eq!(timeout(&connection::shared("busy").unwrap()), 0);
+
+                let started = Instant::now();
+                let tx = Transaction::begin("busy", immediate(5000)).unwrap();
+                assert!(started.elapsed() >= Duration::from_millis(100));
+                drop(tx);
+                assert_eq!(timeout(&connection::shared("busy").unwrap()), 0);
+            })
+            .unwrap();
+        holder.join().unwrap();
+        waiter.join().unwrap();
+    }
+
+    #[test]
+    fn session_statements_apply_and_restore_options() {
+        let (apply, restore) = session_statements(&default_options(), 0);
+        assert!(apply.is_empty() && restore.is_empty());
+
+        let options = wit_sql::TransactionOptions {
+            mode: wit_sql::TransactionMode::Immediate,
+            read_only: true,
+            busy_timeout_ms: Some(250),
+        };
+        let (apply, restore) = session_statements(&options, 5000);
+        assert_eq!(
+            apply,
+            vec!["PRAGMA busy_timeout = 250", "PRAGMA query_only = ON"]
+        );
+        assert_eq!(
+            restore,
+            vec!["PRAGMA busy_timeout = 5000", "PRAGMA query_only = OFF"]
+        );
+    }
+}
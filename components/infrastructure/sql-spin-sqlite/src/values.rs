@@ -0,0 +1,445 @@
+//! Conversion between WIT `sql-value`s and Spin SQLite values.
+//!
+//! SQLite only has five storage classes, so `boolean`, `timestamp` and `uuid`
+//! are written as integers/text and can only be recovered on read when the
+//! caller supplies a `column-hint` for the result column.
+
+use spin_sdk::sqlite::Value as SpinValue;
+
+use crate::config::UuidStorage;
+use crate::error::{self, codes};
+use crate::wit_sql;
+
+// Map WIT sql-value to Spin SQLite Value
+pub(crate) fn to_spin_value(
+    v: &wit_sql::SqlValue,
+    uuids: UuidStorage,
+) -> Result<SpinValue, wit_sql::SqlError> {
+    use wit_sql::SqlValue as W;
+    Ok(match v {
+        W::Null => SpinValue::Null,
+        W::Boolean(b) => SpinValue::Integer(if *b { 1 } else { 0 }),
+        W::Int32(i) => SpinValue::Integer(*i as i64),
+        W::Int64(i) => SpinValue::Integer(*i),
+        W::Float32(f) => SpinValue::Real(*f as f64),
+        W::Float64(f) => SpinValue::Real(*f),
+        W::Text(s) => SpinValue::Text(s.clone()),
+        W::Bytes(b) => SpinValue::Blob(b.clone()),
+        W::Timestamp(ts) => SpinValue::Integer(*ts),
+        W::Uuid(u) => {
+            let parsed = uuid::Uuid::try_parse(u).map_err(|e| {
+                wit_sql::SqlError::InvalidParameter(error::adapter_error(
+                    codes::MISMATCH,
+                    format!("invalid uuid `{u}`: {e}"),
+                    None,
+                ))
+            })?;
+            match uuids {
+                UuidStorage::Text => SpinValue::Text(parsed.hyphenated().to_string()),
+                UuidStorage::Blob => SpinValue::Blob(parsed.as_bytes().to_vec()),
+            }
+        }
+    })
+}
+
+pub(crate) fn values_from(
+    params: &[wit_sql::SqlValue],
+    uuids: UuidStorage,
+) -> Result<Vec<SpinValue>, wit_sql::SqlError> {
+    params
+        .iter()
+        .enumerate()
+        .map(|(i, v)| {
+            to_spin_value(v, uuids).map_err(|mut e| {
+                if let Some(info) = error::info_mut(&mut e) {
+                    info.parameter_index = u32::try_from(i).ok();
+                }
+                e
+            })
+        })
+        .collect()
+}
+
+/// Line up a query's `schema` with its result columns by name.
+pub(crate) fn resolve_hints(
+    columns: &[String],
+    schema: &[wit_sql::ColumnHint],
+) -> Vec<Option<wit_sql::SqlType>> {
+    columns
+        .iter()
+        .map(|c| schema.iter().find(|h| &h.name == c).map(|h| h.decode_as))
+        .collect()
+}
+
+pub(crate) fn row_to_wit(
+    columns: &[String],
+    hints: &[Option<wit_sql::SqlType>],
+    row_values: &[SpinValue],
+) -> Result<wit_sql::SqlRow, wit_sql::SqlError> {
+    let cols = columns
+        .iter()
+        .zip(row_values.iter().cloned())
+        .enumerate()
+        .map(|(i, (name, v))| {
+            let hint = hints.get(i).copied().flatten();
+            Ok((name.clone(), decode_value(name, v, hint)?))
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(wit_sql::SqlRow { columns: cols })
+}
+
+/// Like [`row_to_wit`] but without repeating the column names.
+pub(crate) fn row_values_to_wit(
+    columns: &[String],
+    hints: &[Option<wit_sql::SqlType>],
+    row_values: &[SpinValue],
+) -> Result<Vec<wit_sql::SqlValue>, wit_sql::SqlError> {
+    columns
+        .iter()
+        .zip(row_values.iter().cloned())
+        .enumerate()
+        .map(|(i, (name, v))| decode_value(name, v, hints.get(i).copied().flatten()))
+        .collect()
+}
+
+/// Describe each result column from its hint and the decoded `rows`.
+pub(crate) fn column_meta(
+    columns: &[String],
+    hints: &[Option<wit_sql::SqlType>],
+    rows: &[Vec<wit_sql::SqlValue>],
+) -> Vec<wit_sql::ColumnMeta> {
+    columns
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let values = rows.iter().filter_map(|row| row.get(i));
+            let nullable = values.clone().any(|v| matches!(v, wit_sql::SqlValue::Null));
+            let declared_type = hints.get(i).copied().flatten().or_else(|| {
+                let mut types = values.filter_map(sql_type_of);
+                let first = types.next()?;
+                types.all(|t| t == first).then_some(first)
+            });
+            wit_sql::ColumnMeta {
+                name: name.clone(),
+                declared_type,
+                nullable,
+            }
+        })
+        .collect()
+}
+
+fn sql_type_of(v: &wit_sql::SqlValue) -> Option<wit_sql::SqlType> {
+    use wit_sql::SqlType as T;
+    use wit_sql::SqlValue as W;
+
+    Some(match v {
+        W::Null => return None,
+        W::Boolean(_) => T::Boolean,
+        W::Int32(_) => T::Int32,
+        W::Int64(_) => T::Int64,
+        W::Float32(_) => T::Float32,
+        W::Float64(_) => T::Float64,
+        W::Text(_) => T::Text,
+        W::Bytes(_) => T::Bytes,
+        W::Timestamp(_) => T::Timestamp,
+        W::Uuid(_) => T::Uuid,
+    })
+}
+
+fn decode_value(
+    column: &str,
+    v: SpinValue,
+    hint: Option<wit_sql::SqlType>,
+) -> Result<wit_sql::SqlValue, wit_sql::SqlError> {
+    use wit_sql::SqlType as T;
+    use wit_sql::SqlValue as W;
+
+    let Some(hint) = hint else {
+        return Ok(match v {
+            SpinValue::Null => W::Null,
+            SpinValue::Integer(i) => W::Int64(i),
+            SpinValue::Real(f) => W::Float64(f),
+            SpinValue::Text(s) => W::Text(s),
+            SpinValue::Blob(b) => W::Bytes(b),
+        });
+    };
+    Ok(match (hint, v) {
+        (_, SpinValue::Null) => W::Null,
+        (T::Boolean, SpinValue::Integer(i)) => W::Boolean(i != 0),
+        (T::Int32, SpinValue::Integer(i)) => match i32::try_from(i) {
+            Ok(i) => W::Int32(i),
+            Err(_) => {
+                return Err(mismatch(
+                    column,
+                    format!("integer {i} is out of range for int32"),
+                ));
+            }
+        },
+        (T::Int64, SpinValue::Integer(i)) => W::Int64(i),
+        (T::Float32, SpinValue::Real(f)) => W::Float32(f as f32),
+        (T::Float32, SpinValue::Integer(i)) => W::Float32(i as f32),
+        (T::Float64, SpinValue::Real(f)) => W::Float64(f),
+        (T::Float64, SpinValue::Integer(i)) => W::Float64(i as f64),
+        (T::Text, SpinValue::Text(s)) => W::Text(s),
+        (T::Bytes, SpinValue::Blob(b)) => W::Bytes(b),
+        (T::Bytes, SpinValue::Text(s)) => W::Bytes(s.into_bytes()),
+        (T::Timestamp, SpinValue::Integer(i)) => W::Timestamp(i),
+        (T::Uuid, SpinValue::Text(s)) => W::Uuid(s),
+        (T::Uuid, SpinValue::Blob(b)) => match uuid::Uuid::from_slice(&b) {
+            Ok(u) => W::Uuid(u.hyphenated().to_string()),
+            Err(_) => {
+                return Err(mismatch(
+                    column,
+                    format!("{}-byte blob is not a uuid", b.len()),
+                ));
+            }
+        },
+        (hint, v) => {
+            return Err(mismatch(
+                column,
+                format!("cannot decode {} as {hint:?}", storage_class(&v)),
+            ));
+        }
+    })
+}
+
+fn storage_class(v: &SpinValue) -> &'static str {
+    match v {
+        SpinValue::Null => "null",
+        SpinValue::Integer(_) => "integer",
+        SpinValue::Real(_) => "real",
+        SpinValue::Text(_) => "text",
+        SpinValue::Blob(_) => "blob",
+    }
+}
+
+fn mismatch(column: &str, detail: String) -> wit_sql::SqlError {
+    wit_sql::SqlError::QueryFailed(error::adapter_error(
+        codes::MISMATCH,
+        format!("column `{column}`: {detail}"),
+        None,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_spin_value_maps_all_variants() {
+        use wit_sql::SqlValue as W;
+
+        assert!(matches!(
+            to_spin_value(&W::Null, UuidStorage::Text).unwrap(),
+            SpinValue::Null
+        ));
+        assert!(matches!(
+            to_spin_value(&W::Boolean(true), UuidStorage::Text).unwrap(),
+            SpinValue::Integer(1)
+        ));
+        assert!(matches!(
+            to_spin_value(&W::Boolean(false), UuidStorage::Text).unwrap(),
+            SpinValue::Integer(0)
+        ));
+        assert!(matches!(
+            to_spin_value(&W::Int32(7), UuidStorage::Text).unwrap(),
+            SpinValue::Integer(7)
+        ));
+        assert!(matches!(
+            to_spin_value(&W::Int64(9), UuidStorage::Text).unwrap(),
+            SpinValue::Integer(9)
+        ));
+        assert!(
+            matches!(to_spin_value(&W::Float32(1.5), UuidStorage::Text).unwrap(), SpinValue::Real(x) if (x-1.5).abs() < 1e-6)
+        );
+        assert!(
+            matches!(to_spin_value(&W::Float64(2.5), UuidStorage::Text).unwrap(), SpinValue::Real(x) if (x-2.5).abs() < 1e-12)
+        );
+        assert!(
+            matches!(to_spin_value(&W::Text("hi".into()), UuidStorage::Text).unwrap(), SpinValue::Text(s) if s=="hi")
+        );
+        assert!(
+            matches!(to_spin_value(&W::Bytes(vec![1,2]), UuidStorage::Text).unwrap(), SpinValue::Blob(b) if b==vec![1,2])
+        );
+        assert!(matches!(
+            to_spin_value(&W::Timestamp(123), UuidStorage::Text).unwrap(),
+            SpinValue::Integer(123)
+        ));
+        assert!(
+            matches!(to_spin_value(&W::Uuid("67E55044-10B1-426F-9247-BB680E5FE0C8".into()), UuidStorage::Text).unwrap(), SpinValue::Text(s) if s=="67e55044-10b1-426f-9247-bb680e5fe0c8")
+        );
+    }
+
+    #[test]
+    fn row_to_wit_roundtrip_basic() {
+        let cols = vec![
+            "a".to_string(),
+            "b".to_string(),
+            "c".to_string(),
+            "d".to_string(),
+            "e".to_string(),
+        ];
+        let values = vec![
+            SpinValue::Null,
+            SpinValue::Integer(42),
+            SpinValue::Real(3.5),
+            SpinValue::Text("ok".into()),
+            SpinValue::Blob(vec![9, 8, 7]),
+        ];
+
+        let row = row_to_wit(&cols, &[], &values).unwrap();
+        assert_eq!(row.columns.len(), cols.len());
+        for (i, (name, v)) in row.columns.iter().enumerate() {
+            assert_eq!(name, &cols[i]);
+            match (i, v) {
+                (0, wit_sql::SqlValue::Null) => {}
+                (1, wit_sql::SqlValue::Int64(n)) => assert_eq!(*n, 42),
+                (2, wit_sql::SqlValue::Float64(f)) => assert!((f - 3.5).abs() < 1e-12),
+                (3, wit_sql::SqlValue::Text(s)) => assert_eq!(s, "ok"),
+                (4, wit_sql::SqlValue::Bytes(b)) => assert_eq!(b, &vec![9, 8, 7]),
+                _ => panic!("unexpected mapping at index {}: {:?}", i, v),
+            }
+        }
+    }
+
+    #[test]
+    fn typed_values_roundtrip_through_storage() {
+        use wit_sql::SqlType as T;
+        use wit_sql::SqlValue as W;
+
+        let cases = vec![
+            (W::Boolean(true), T::Boolean),
+            (W::Boolean(false), T::Boolean),
+            (W::Int32(i32::MIN), T::Int32),
+            (W::Int32(i32::MAX), T::Int32),
+            (W::Int64(i64::MIN), T::Int64),
+            (W::Float32(1.25), T::Float32),
+            (W::Float64(-2.5e300), T::Float64),
+            (W::Text("héllo".into()), T::Text),
+            (W::Bytes(vec![0, 255, 7]), T::Bytes),
+            (W::Timestamp(1_700_000_000_000), T::Timestamp),
+            (
+                W::Uuid("67e55044-10b1-426f-9247-bb680e5fe0c8".into()),
+                T::Uuid,
+            ),
+            (W::Null, T::Uuid),
+        ];
+        for (written, hint) in cases {
+            for uuids in [UuidStorage::Text, UuidStorage::Blob] {
+                let stored = to_spin_value(&written, uuids).unwrap();
+                let read = decode_value("c", stored, Some(hint)).unwrap();
+                assert_eq!(read, written, "round-trip as {hint:?} with {uuids:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn column_meta_prefers_hints_then_observed_types() {
+        use wit_sql::SqlValue as W;
+
+        let cols: Vec<String> = ["id", "name", "score", "flag"].map(String::from).into();
+        let rows = vec![
+            vec![W::Int64(1), W::Text("a".into()), W::Int64(3), W::Int64(1)],
+            vec![W::Int64(2), W::Null, W::Float64(2.5), W::Int64(0)],
+        ];
+        let hints = [None, None, None, Some(wit_sql::SqlType::Boolean)];
+        let meta = column_meta(&cols, &hints, &rows);
+        let summary: Vec<_> = meta
+            .iter()
+            .map(|m| (m.name.as_str(), m.declared_type, m.nullable))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("id", Some(wit_sql::SqlType::Int64), false),
+                ("name", Some(wit_sql::SqlType::Text), true),
+                ("score", None, false),
+                ("flag", Some(wit_sql::SqlType::Boolean), false),
+            ]
+        );
+        assert!(
+            column_meta(&cols, &[], &[])
+                .iter()
+                .all(|m| m.declared_type.is_none())
+        );
+    }
+
+    #[test]
+    fn row_values_match_row_to_wit() {
+        let cols = vec!["a".to_string(), "b".to_string()];
+        let values = vec![SpinValue::Integer(7), SpinValue::Text("x".into())];
+        let row = row_to_wit(&cols, &[], &values).unwrap();
+        let flat = row_values_to_wit(&cols, &[], &values).unwrap();
+        assert_eq!(
+            flat,
+            row.columns.into_iter().map(|(_, v)| v).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn hints_match_columns_by_name() {
+        let cols = vec!["id".to_string(), "active".to_string(), "at".to_string()];
+        let schema = vec![
+            wit_sql::ColumnHint {
+                name: "at".into(),
+                decode_as: wit_sql::SqlType::Timestamp,
+            },
+            wit_sql::ColumnHint {
+                name: "active".into(),
+                decode_as: wit_sql::SqlType::Boolean,
+            },
+        ];
+        let hints = resolve_hints(&cols, &schema);
+        let row = row_to_wit(
+            &cols,
+            &hints,
+            &[
+                SpinValue::Integer(1),
+                SpinValue::Integer(1),
+                SpinValue::Integer(99),
+            ],
+        )
+        .unwrap();
+        assert_eq!(row.columns[0].1, wit_sql::SqlValue::Int64(1));
+        assert_eq!(row.columns[1].1, wit_sql::SqlValue::Boolean(true));
+        assert_eq!(row.columns[2].1, wit_sql::SqlValue::Timestamp(99));
+    }
+
+    #[test]
+    fn decode_rejects_mismatched_storage() {
+        use wit_sql::SqlType as T;
+
+        let err = decode_value(
+            "n",
+            SpinValue::Integer(i64::from(i32::MAX) + 1),
+            Some(T::Int32),
+        )
+        .unwrap_err();
+        assert!(
+            matches!(err, wit_sql::SqlError::QueryFailed(info) if info.code == codes::MISMATCH)
+        );
+        assert!(decode_value("u", SpinValue::Integer(3), Some(T::Uuid)).is_err());
+        assert!(decode_value("u", SpinValue::Blob(vec![1, 2, 3]), Some(T::Uuid)).is_err());
+        assert!(decode_value("t", SpinValue::Real(1.0), Some(T::Text)).is_err());
+    }
+
+    #[test]
+    fn uuid_parameters_are_validated() {
+        let id = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+        let stored = to_spin_value(&wit_sql::SqlValue::Uuid(id.into()), UuidStorage::Blob).unwrap();
+        assert!(
+            matches!(stored, SpinValue::Blob(b) if b == uuid::Uuid::parse_str(id).unwrap().as_bytes())
+        );
+
+        let params = vec![
+            wit_sql::SqlValue::Int32(1),
+            wit_sql::SqlValue::Uuid("not-a-uuid".into()),
+        ];
+        let err = values_from(&params, UuidStorage::Text).unwrap_err();
+        let wit_sql::SqlError::InvalidParameter(info) = err else {
+            panic!("expected invalid-parameter, got {err:?}");
+        };
+        assert_eq!(info.parameter_index, Some(1));
+        assert!(info.message.contains("not-a-uuid"));
+    }
+}